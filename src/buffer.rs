@@ -0,0 +1,31 @@
+use super::document::Document;
+use super::editor::Position;
+
+/// A document that isn't shown right now, along with where the cursor was in it.
+pub struct Buffer {
+    // The number `ls` shows and `b N` takes, it's never reused.
+    pub id: usize,
+    pub document: Document,
+    pub cursor: Position,
+    pub offset: Position,
+}
+
+impl Buffer {
+    pub fn new(id: usize, document: Document) -> Self {
+        Self {
+            id,
+            document,
+            cursor: Position::default(),
+            offset: Position::default(),
+        }
+    }
+}
+
+/// The name `ls` and the status bar show for a document.
+pub fn name(document: &Document) -> &str {
+    if document.filename.is_empty() {
+        "[No Name]"
+    } else {
+        &document.filename
+    }
+}
@@ -0,0 +1,315 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The two clipboards of X11 and Wayland. `+` is the one copy and paste go to, `*` is the
+/// primary selection that middle click pastes. Elsewhere they are the same.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    pub fn from_register(name: char) -> Option<Self> {
+        match name {
+            '+' => Some(Selection::Clipboard),
+            '*' => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+}
+
+/// How the `+` and `*` registers get to the system clipboard.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Provider {
+    // `wl-copy` and `wl-paste`.
+    Wayland,
+    Xclip,
+    Xsel,
+    // The terminal sets the clipboard when it gets the OSC 52 escape sequence, which works over
+    // SSH too. Terminals don't let programs read the clipboard, so pasting gives back whatever
+    // was copied last.
+    Osc52,
+    // A program that copies what it reads, and one that prints what's on the clipboard. Both
+    // registers use the same ones.
+    Command {
+        copy: Vec<String>,
+        paste: Vec<String>,
+    },
+    None,
+}
+
+impl Provider {
+    /// The provider that fits where hecto runs: the programs in `HECTO_COPY` and `HECTO_PASTE`
+    /// if both are set, the helper programs of the display server if they are installed, OSC 52
+    /// otherwise.
+    pub fn detect() -> Self {
+        if let (Ok(copy), Ok(paste)) = (env::var("HECTO_COPY"), env::var("HECTO_PASTE")) {
+            // There's no quoting, the arguments are split at spaces.
+            let split = |command: String| command.split_whitespace().map(str::to_string).collect();
+            Provider::Command {
+                copy: split(copy),
+                paste: split(paste),
+            }
+        } else if env::var_os("WAYLAND_DISPLAY").is_some()
+            && installed("wl-copy")
+            && installed("wl-paste")
+        {
+            Provider::Wayland
+        } else if env::var_os("DISPLAY").is_some() && installed("xclip") {
+            Provider::Xclip
+        } else if env::var_os("DISPLAY").is_some() && installed("xsel") {
+            Provider::Xsel
+        } else {
+            Provider::Osc52
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Wayland => "wayland",
+            Provider::Xclip => "xclip",
+            Provider::Xsel => "xsel",
+            Provider::Osc52 => "osc52",
+            Provider::Command { .. } => "command",
+            Provider::None => "none",
+        }
+    }
+
+    // The programs that copy and paste, `None` for providers that don't use any.
+    fn commands(&self, selection: Selection) -> Option<(Vec<String>, Vec<String>)> {
+        let primary = selection == Selection::Primary;
+        let (copy, paste): (Vec<&str>, Vec<&str>) = match self {
+            Provider::Wayland if primary => (
+                vec!["wl-copy", "--primary"],
+                vec!["wl-paste", "--no-newline", "--primary"],
+            ),
+            Provider::Wayland => (vec!["wl-copy"], vec!["wl-paste", "--no-newline"]),
+            Provider::Xclip => {
+                let name = if primary { "primary" } else { "clipboard" };
+                (
+                    vec!["xclip", "-i", "-selection", name],
+                    vec!["xclip", "-o", "-selection", name],
+                )
+            }
+            Provider::Xsel => {
+                let flag = if primary { "--primary" } else { "--clipboard" };
+                (
+                    vec!["xsel", "--input", flag],
+                    vec!["xsel", "--output", flag],
+                )
+            }
+            Provider::Command { copy, paste } => return Some((copy.clone(), paste.clone())),
+            Provider::Osc52 | Provider::None => return None,
+        };
+
+        let owned = |args: Vec<&str>| args.into_iter().map(str::to_string).collect();
+        Some((owned(copy), owned(paste)))
+    }
+
+    /// Handles the `clipboard` option of the `set` command, e.g. `set clipboard=xclip`.
+    /// Returns None if `argument` is about some other option.
+    pub fn set_option(&mut self, argument: &str) -> Option<Result<Option<String>, String>> {
+        if argument == "clipboard?" {
+            return Some(Ok(Some(format!("clipboard={}", self.name()))));
+        }
+
+        let value = argument.strip_prefix("clipboard=")?;
+        *self = match value {
+            "wayland" => Provider::Wayland,
+            "xclip" => Provider::Xclip,
+            "xsel" => Provider::Xsel,
+            "osc52" => Provider::Osc52,
+            "none" => Provider::None,
+            "auto" => Provider::detect(),
+            _ => return Some(Err(format!("Invalid clipboard: {}", value))),
+        };
+        Some(Ok(None))
+    }
+
+    /// Puts text on the clipboard with the provider's program. OSC 52 goes through the
+    /// terminal instead, this does nothing for it.
+    pub fn copy(&self, selection: Selection, text: &str) -> Result<(), String> {
+        let (copy, _) = match self.commands(selection) {
+            Some(commands) => commands,
+            None => return Ok(()),
+        };
+
+        // The helpers leave a process of their own behind to hand out the text, this one
+        // exits right away.
+        run(&copy, Some(text.to_string()), TIMEOUT)?;
+        Ok(())
+    }
+
+    /// The text on the clipboard, `None` if the provider can't read it.
+    pub fn paste(&self, selection: Selection) -> Result<Option<String>, String> {
+        let (_, paste) = match self.commands(selection) {
+            Some(commands) => commands,
+            None => return Ok(None),
+        };
+
+        let output = run(&paste, None, TIMEOUT)?;
+        Ok(Some(String::from_utf8_lossy(&output).into_owned()))
+    }
+}
+
+/// How long a clipboard program gets before it's killed. A helper that waits for a display
+/// server that doesn't answer would freeze the editor otherwise.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs `command` with `input` on its standard input and returns what it printed, killing it
+/// if it takes longer than `timeout`.
+fn run(command: &[String], input: Option<String>, timeout: Duration) -> Result<Vec<u8>, String> {
+    let (program, args) = command.split_first().ok_or("No clipboard command")?;
+    let error = |error: io::Error| format!("{}: {}", program, error);
+    let deadline = Instant::now() + timeout;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(error)?;
+
+    // Writing and reading happen on their own threads, a program that never reads its input or
+    // never closes its output can't block the editor that way.
+    let (sender, writing) = mpsc::channel();
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        thread::spawn(move || sender.send(stdin.write_all(input.as_bytes())));
+    } else {
+        let _ = sender.send(Ok(()));
+    }
+    let (sender, reading) = mpsc::channel();
+    if let Some(mut stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            sender.send(stdout.read_to_end(&mut output).map(|_| output))
+        });
+    }
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(error)? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{} did not finish in time", program));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        return Err(format!("{} failed with {}", program, status));
+    }
+
+    // A process the program left behind may still hold on to the pipes.
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let timed_out = |_| format!("{} did not finish in time", program);
+    writing
+        .recv_timeout(remaining)
+        .map_err(timed_out)?
+        .map_err(error)?;
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    reading
+        .recv_timeout(remaining)
+        .map_err(timed_out)?
+        .map_err(error)
+}
+
+fn installed(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|directory| directory.join(program).is_file())
+    })
+}
+
+/// Encodes text for the OSC 52 escape sequence.
+pub fn base64(text: &str) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in text.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn base64_pads_to_whole_groups() {
+        assert_eq!(base64(""), "");
+        assert_eq!(base64("a"), "YQ==");
+        assert_eq!(base64("ab"), "YWI=");
+        assert_eq!(base64("abc"), "YWJj");
+        assert_eq!(base64("hello world\n"), "aGVsbG8gd29ybGQK");
+        assert_eq!(base64("héllo"), "aMOpbGxv");
+        assert_eq!(base64("\u{ff}\u{0}"), "w78A");
+    }
+
+    #[test]
+    fn clipboard_programs_get_input_and_give_output() {
+        let output = run(&command(&["cat"]), Some("copied".to_string()), TIMEOUT);
+        assert_eq!(output.unwrap(), b"copied");
+
+        let provider = Provider::Command {
+            copy: command(&["true"]),
+            paste: command(&["printf", "pasted"]),
+        };
+        assert_eq!(provider.copy(Selection::Clipboard, "text"), Ok(()));
+        assert_eq!(
+            provider.paste(Selection::Primary),
+            Ok(Some("pasted".to_string()))
+        );
+
+        let failing = run(&command(&["false"]), None, TIMEOUT);
+        assert!(failing.unwrap_err().starts_with("false failed"));
+        let missing = run(&command(&["hecto-no-such-program"]), None, TIMEOUT);
+        assert!(missing.unwrap_err().starts_with("hecto-no-such-program: "));
+    }
+
+    #[test]
+    fn clipboard_programs_that_hang_are_killed() {
+        let started = Instant::now();
+        let timeout = Duration::from_millis(200);
+
+        let output = run(&command(&["sleep", "5"]), None, timeout);
+        assert_eq!(output, Err("sleep did not finish in time".to_string()));
+        // Input that is never read doesn't block either.
+        let output = run(
+            &command(&["sleep", "5"]),
+            Some("x".repeat(1 << 20)),
+            timeout,
+        );
+        assert_eq!(output, Err("sleep did not finish in time".to_string()));
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
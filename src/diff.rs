@@ -0,0 +1,177 @@
+use std::cmp;
+
+// Lines of unchanged text shown around every change.
+const CONTEXT: usize = 2;
+// Above this many line pairs the changed part is shown as removed and added in full.
+const MAX_TABLE: usize = 4_000_000;
+
+enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The longest common subsequence of two lists of lines, as the edits that turn `old` into `new`.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    if old.len() * new.len() > MAX_TABLE {
+        let removed = old.iter().map(|line| Edit::Removed(line));
+        return removed
+            .chain(new.iter().map(|line| Edit::Added(line)))
+            .collect();
+    }
+
+    // table[i][j] is the length of the common subsequence of old[i..] and new[j..].
+    let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i][j] = if old[i] == new[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                cmp::max(table[i + 1][j], table[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || table[i + 1][j] >= table[i][j + 1]) {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+/// A line based diff of two texts, in the style of `diff -u`.
+/// Every group of changes starts with `@@ line N @@`, N being the line in `old`.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Only the part between the common start and end has to go through the table.
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let max_suffix = cmp::min(old.len(), new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut all: Vec<Edit> = old[..prefix].iter().map(|line| Edit::Same(line)).collect();
+    all.extend(edits(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    ));
+    all.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Edit::Same(line)),
+    );
+
+    let changed: Vec<bool> = all.iter().map(|e| !matches!(e, Edit::Same(_))).collect();
+    let near_change = |index: usize| {
+        let start = index.saturating_sub(CONTEXT);
+        let end = cmp::min(index + CONTEXT + 1, changed.len());
+        changed[start..end].contains(&true)
+    };
+
+    let mut output = vec![];
+    let mut old_line = 0;
+    let mut in_hunk = false;
+    for (index, edit) in all.iter().enumerate() {
+        if !near_change(index) {
+            in_hunk = false;
+        } else {
+            if !in_hunk {
+                output.push(format!("@@ line {} @@", old_line + 1));
+                in_hunk = true;
+            }
+
+            let line = match edit {
+                Edit::Same(line) => format!("  {}", line),
+                Edit::Removed(line) => format!("- {}", line),
+                Edit::Added(line) => format!("+ {}", line),
+            };
+            output.push(line);
+        }
+
+        if !matches!(edit, Edit::Added(_)) {
+            old_line += 1;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_text_has_no_diff() {
+        assert!(diff("", "").is_empty());
+        assert!(diff("one\ntwo\n", "one\ntwo").is_empty());
+    }
+
+    #[test]
+    fn changes_are_shown_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n";
+        let new = "1\n2\n3\nfour\n5\n6\n7\n";
+        assert_eq!(
+            diff(old, new),
+            ["@@ line 2 @@", "  2", "  3", "- 4", "+ four", "  5", "  6"]
+        );
+
+        assert_eq!(diff("a\n", "a\nb\n"), ["@@ line 1 @@", "  a", "+ b"]);
+        assert_eq!(diff("a\nb\n", "b\n"), ["@@ line 1 @@", "- a", "  b"]);
+    }
+
+    #[test]
+    fn changes_far_apart_get_hunks_of_their_own() {
+        let old: Vec<String> = (1..=12).map(|n| n.to_string()).collect();
+        let mut new = old.clone();
+        new[0] = "one".to_string();
+        new.remove(10);
+
+        assert_eq!(
+            diff(&old.join("\n"), &new.join("\n")),
+            [
+                "@@ line 1 @@",
+                "- 1",
+                "+ one",
+                "  2",
+                "  3",
+                "@@ line 9 @@",
+                "  9",
+                "  10",
+                "- 11",
+                "  12",
+            ]
+        );
+    }
+
+    #[test]
+    fn moved_lines_keep_the_longest_common_part() {
+        let edits = edits(&["a", "b", "c", "d"], &["b", "c", "a", "d"]);
+        let kinds: String = edits
+            .iter()
+            .map(|edit| match edit {
+                Edit::Same(_) => '=',
+                Edit::Removed(_) => '-',
+                Edit::Added(_) => '+',
+            })
+            .collect();
+        assert_eq!(kinds, "-==+=");
+    }
+}
@@ -1,100 +1,923 @@
+use std::cmp;
+use std::fmt;
 use std::fs;
-use std::io::Write;
+use std::io::{Error, ErrorKind};
+use std::iter;
+use std::mem;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
 use super::editor::Position;
+use super::encoding::{self, Decoded};
 use super::gap_buffer::GapBuffer;
+use super::history::{Change, History, Step};
+use super::rope::Rope;
+use super::save;
+use super::swap_file::{self, Found, Swap};
+use super::undo_file;
 
-use unicode_segmentation::UnicodeSegmentation;
+use encoding_rs::{Encoding, UTF_8};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    /// The line ending that shows up first, files without any are treated as unix files.
+    pub fn detect(contents: &str) -> Self {
+        match contents.find(['\n', '\r']) {
+            Some(i) if contents[i..].starts_with("\r\n") => Self::CrLf,
+            Some(i) if contents[i..].starts_with('\r') => Self::Cr,
+            _ => Self::Lf,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    /// Splits the contents of a file into lines. Dos files also accept a lone `\n`, like vim does.
+    fn split(self, contents: &str) -> Vec<&str> {
+        match self {
+            Self::Lf => contents.split('\n').collect(),
+            Self::CrLf => contents
+                .split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                .collect(),
+            Self::Cr => contents.split('\r').collect(),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self {
+            Self::Lf => "unix",
+            Self::CrLf => "dos",
+            Self::Cr => "mac",
+        };
+
+        write!(f, "{}", format)
+    }
+}
+
+// Some file systems only keep modification times to the second, FAT even to two seconds.
+const TIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// What a file looks like on disk, to notice when something else changes it.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    // Programs that save by renaming a new file over the old one change the inode.
+    inode: u64,
+}
+
+impl Stamp {
+    /// None if the file doesn't exist.
+    fn of(filename: &str) -> Option<Self> {
+        let metadata = fs::metadata(filename).ok()?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode,
+        })
+    }
+
+    /// A write of the same length right after this one can leave the stamp as it is, as long as
+    /// the modification time is this recent.
+    fn is_recent(&self) -> bool {
+        let age = self.modified.and_then(|modified| modified.elapsed().ok());
+        age.is_none_or(|age| age < TIME_GRANULARITY)
+    }
+}
+
+/// How the file on disk changed since hecto last read or wrote it.
+#[derive(PartialEq)]
+pub enum FileChange {
+    Modified,
+    Removed,
+}
+
+/// Splits the contents of a file into lines, and tells its line ending and whether the last line has one.
+fn split_file(contents: &str) -> (Vec<&str>, LineEnding, bool) {
+    let line_ending = LineEnding::detect(contents);
+    let mut lines = line_ending.split(contents);
+    // A file ending with a line ending leaves an empty string after the last split.
+    let final_newline = contents.is_empty() || lines.last() == Some(&"");
+    if final_newline {
+        lines.pop();
+    }
+
+    (lines, line_ending, final_newline)
+}
 
-// TODO: Change rows to a gap buffer.
-#[derive(Default, Clone)]
 pub struct Document {
-    pub gap_buffer: Vec<GapBuffer>,
+    pub rope: Rope,
     pub filename: String,
+    pub history: History,
+    pub line_ending: LineEnding,
+    // Whether the last line ends with a line ending too.
+    pub final_newline: bool,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    // Set when the file could not be decoded, saving it would write back the replaced characters.
+    pub read_only: bool,
+    // Every change since the file was last saved, this is what goes into the swap file.
+    pub journal: Vec<Change>,
+    // How much of the journal is in the swap file already.
+    pub journal_written: usize,
+    // Hash of the file as it is on disk.
+    pub saved_hash: u64,
+    // Only one hecto writes the swap file of a file, the others open it read-only.
+    pub owns_swap: bool,
+    // A swap file left behind by a crash, waiting for the user to recover or discard it.
+    pub stale_swap: Option<Swap>,
+    // The process of another hecto that has the file open.
+    pub other_instance: Option<u32>,
+    // The file doesn't exist yet, it's created when the document is first saved.
+    pub new_file: bool,
+    // The revision of the undo tree that matches the file on disk, undoing back to it makes
+    // the document unmodified again. None once the file changed into something hecto never had.
+    pub saved_revision: Option<usize>,
+    // Line ending, final newline, encoding and BOM of the file on disk.
+    pub saved_format: (LineEnding, bool, &'static Encoding, bool),
+    // The file on disk when it was last read or written, None if it doesn't exist.
+    pub stamp: Option<Stamp>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rope: Rope::default(),
+            filename: String::new(),
+            history: History::default(),
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            encoding: UTF_8,
+            bom: false,
+            read_only: false,
+            journal: vec![],
+            journal_written: 0,
+            saved_hash: 0,
+            owns_swap: false,
+            stale_swap: None,
+            other_instance: None,
+            new_file: false,
+            saved_revision: Some(0),
+            saved_format: (LineEnding::Lf, true, UTF_8, false),
+            stamp: None,
+        }
+    }
 }
 
 // Utility functions
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let mut gap_buffer = vec![];
-        for line in contents.lines() {
-            let line = line.graphemes(true).collect::<String>();
-            let buffer = GapBuffer::new(line.as_str());
-            gap_buffer.push(buffer);
-        }
+        let (bytes, new_file) = match fs::read(filename) {
+            Ok(bytes) => (bytes, false),
+            Err(error) if error.kind() == ErrorKind::NotFound => (vec![], true),
+            Err(error) => return Err(error),
+        };
+        let Decoded {
+            text: contents,
+            encoding,
+            bom,
+            lossy,
+        } = encoding::decode(&bytes);
+        let (lines, line_ending, final_newline) = split_file(&contents);
+        let rope = Rope::from_lines(lines.into_iter().map(GapBuffer::new));
 
-        let filename = filename.to_string();
+        // The undo tree from the last session only fits if nothing else touched the file since.
+        let saved_hash = undo_file::hash(&contents);
+        let history = undo_file::read(filename, saved_hash).unwrap_or_default();
 
-        Ok(Self {
-            gap_buffer,
-            filename,
-        })
+        let (stale_swap, other_instance) = match swap_file::find(filename) {
+            Found::Nothing => (None, None),
+            Found::Running(pid) => (None, Some(pid)),
+            Found::Stale(swap) => (Some(swap), None),
+        };
+
+        let mut document = Self {
+            rope,
+            filename: filename.to_string(),
+            history,
+            line_ending,
+            final_newline,
+            encoding,
+            bom,
+            read_only: lossy || other_instance.is_some(),
+            saved_hash,
+            // A stale swap file is only overwritten once the user decided what to do with it.
+            owns_swap: other_instance.is_none() && stale_swap.is_none(),
+            stale_swap,
+            other_instance,
+            new_file,
+            stamp: Stamp::of(filename),
+            ..Self::default()
+        };
+
+        document.mark_saved();
+        // An empty swap file tells other instances that the file is taken.
+        let _ = document.write_swap();
+        Ok(document)
+    }
+
+    /// Handles the options of the `set` command that belong to a single document.
+    /// Returns None if `argument` is about some other option.
+    pub fn set_option(&mut self, argument: &str) -> Option<Result<Option<String>, String>> {
+        let result = match argument {
+            "fileformat?" | "ff?" => Ok(Some(format!("fileformat={}", self.line_ending))),
+            "endofline?" | "eol?" => {
+                let prefix = if self.final_newline { "" } else { "no" };
+                Ok(Some(format!("{}endofline", prefix)))
+            }
+            "fileencoding?" | "fenc?" => Ok(Some(format!(
+                "fileencoding={}",
+                self.encoding.name().to_lowercase()
+            ))),
+            "bomb" => {
+                self.bom = true;
+                Ok(None)
+            }
+            "nobomb" => {
+                self.bom = false;
+                Ok(None)
+            }
+            "readonly" | "ro" => {
+                self.read_only = true;
+                Ok(None)
+            }
+            "noreadonly" | "noro" => {
+                self.read_only = false;
+                Ok(None)
+            }
+            "endofline" | "eol" => {
+                self.final_newline = true;
+                Ok(None)
+            }
+            "noendofline" | "noeol" => {
+                self.final_newline = false;
+                Ok(None)
+            }
+            _ if argument.starts_with("fileencoding=") || argument.starts_with("fenc=") => {
+                let (_, label) = argument.split_once('=')?;
+                match encoding::for_label(label) {
+                    Some(encoding) => {
+                        self.encoding = encoding;
+                        Ok(None)
+                    }
+                    None => Err(format!("Unknown encoding: {}", label)),
+                }
+            }
+            _ => {
+                let value = argument
+                    .strip_prefix("fileformat=")
+                    .or_else(|| argument.strip_prefix("ff="))?;
+
+                match value {
+                    "unix" => self.line_ending = LineEnding::Lf,
+                    "dos" => self.line_ending = LineEnding::CrLf,
+                    "mac" => self.line_ending = LineEnding::Cr,
+                    _ => return Some(Err(format!("Invalid fileformat: {}", value))),
+                }
+                Ok(None)
+            }
+        };
+
+        Some(result)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.gap_buffer.is_empty()
+        self.rope.is_empty()
+    }
+
+    fn format(&self) -> (LineEnding, bool, &'static Encoding, bool) {
+        (
+            self.line_ending,
+            self.final_newline,
+            self.encoding,
+            self.bom,
+        )
+    }
+
+    /// Whether the document differs from the file on disk, in its text or in how it's written.
+    pub fn is_modified(&self) -> bool {
+        self.saved_revision != Some(self.history.current)
+            || self.history.has_pending()
+            || self.format() != self.saved_format
+    }
+
+    fn mark_saved(&mut self) {
+        self.saved_revision = Some(self.history.current);
+        self.saved_format = self.format();
     }
 
     pub fn len(&self) -> usize {
-        self.gap_buffer.len()
+        self.rope.len()
+    }
+
+    /// Amount of characters in the document, newlines included.
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
     }
 
     pub fn buffer(&self, index: usize) -> Option<&GapBuffer> {
-        self.gap_buffer.get(index)
+        self.rope.get(index)
     }
 
-    pub fn buffer_mut(&mut self, index: usize) -> Option<&mut GapBuffer> {
-        self.gap_buffer.get_mut(index)
+    /// Converts a position into the amount of characters in front of it.
+    /// `Position.x` counts graphemes, offsets count characters.
+    pub fn offset(&self, at: &Position) -> usize {
+        let x = self
+            .buffer(at.y)
+            .map_or(0, |buffer| buffer.char_index(at.x));
+        self.rope.line_to_offset(at.y) + x
+    }
+
+    /// Converts an amount of characters from the start of the document into a position.
+    pub fn position(&self, offset: usize) -> Position {
+        let (y, x) = self.rope.offset_to_line(offset);
+        let x = self.buffer(y).map_or(x, |buffer| buffer.grapheme_index(x));
+        Position { x, y }
     }
 }
 
 // Functions related to typing
 impl Document {
+    /// Text can go anywhere on a line, and at the start of a document without any lines.
+    fn can_insert(&self, at: &Position) -> bool {
+        self.buffer(at.y).is_some() || (self.is_empty() && at.y == 0)
+    }
+
     pub fn insert(&mut self, c: char, at: &Position) {
-        if let Some(buffer) = self.gap_buffer.get_mut(at.y) {
-            buffer.insert(c, at.x);
+        if !self.can_insert(at) {
+            return;
+        }
+
+        let change = Change::Insert {
+            at: self.offset(at),
+            text: c.to_string(),
+        };
+        self.change(change, at);
+    }
+
+    /// Deletes the grapheme in front of `at`, like backspace does.
+    /// At the start of a line, the line gets joined with the one above it.
+    pub fn delete(&mut self, at: &Position) {
+        let end = self.offset(at);
+        let start = if at.x == 0 {
+            end.saturating_sub(1)
+        } else {
+            self.offset(&Position::new(at.x - 1, at.y))
+        };
+
+        self.remove(start, end, at);
+    }
+
+    pub fn enter(&mut self, at: &Position) {
+        if !self.can_insert(at) {
+            return;
+        }
+
+        let change = Change::Insert {
+            at: self.offset(at),
+            text: "\n".to_string(),
+        };
+        self.change(change, at);
+    }
+
+    /// Inserts `text` at `at`, every newline in it splits the line.
+    pub fn insert_text(&mut self, text: &str, at: &Position) {
+        if text.is_empty() || !self.can_insert(at) {
+            return;
         }
+
+        let change = Change::Insert {
+            at: self.offset(at),
+            text: text.to_string(),
+        };
+        self.change(change, at);
     }
 
-    pub fn delete(&mut self, at: &Position) -> bool {
-        if at.x == 0 && at.y != 0 {
-            let mut current = self.gap_buffer.get(at.y).unwrap().clone();
-            let mut above = self.gap_buffer.get(at.y - 1).unwrap().clone();
+    /// Removes the characters between the offsets `start` and `end`, and returns them.
+    pub fn remove(&mut self, start: usize, end: usize, cursor: &Position) -> String {
+        let text = self.text(start, end);
+        if !text.is_empty() {
+            let change = Change::Delete {
+                at: start,
+                text: text.clone(),
+            };
+            self.change(change, cursor);
+        }
 
-            above.chs.append(&mut current.chs);
-            above.update_len();
+        text
+    }
+
+    /// The characters between the offsets `start` and `end`.
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let end = cmp::min(end, self.len_chars());
+        let mut text = String::new();
+        if start >= end {
+            return text;
+        }
 
-            self.gap_buffer.remove(at.y);
-            if let Some(old_above) = self.gap_buffer.get_mut(at.y - 1) {
-                *old_above = above;
+        // Whole lines are copied out of the rope, rather than looking up every character.
+        let (y, mut x) = self.rope.offset_to_line(start);
+        let mut remaining = end - start;
+        for buffer in self.rope.iter_from(y) {
+            for c in buffer.chars().chain(iter::once('\n')).skip(x) {
+                if remaining == 0 {
+                    return text;
+                }
+                text.push(c);
+                remaining -= 1;
+            }
+            x = 0;
+        }
+
+        text
+    }
+
+    /// Applies a change and remembers it, so that it can be undone.
+    fn change(&mut self, change: Change, cursor: &Position) {
+        self.apply(&change);
+        self.history.record(change, cursor);
+    }
+
+    fn apply(&mut self, change: &Change) {
+        self.journal.push(change.clone());
+        match change {
+            Change::Insert { at, text } => {
+                if self.rope.is_empty() {
+                    self.rope.insert(0, GapBuffer::default());
+                }
+
+                let (mut y, mut x) = self.rope.offset_to_line(*at);
+                for c in text.chars() {
+                    if c == '\n' {
+                        self.rope.split_line(y, x);
+                        y += 1;
+                        x = 0;
+                    } else {
+                        self.rope.edit(y, |buffer| buffer.insert(c, x));
+                        x += 1;
+                    }
+                }
+            }
+            Change::Delete { at, text } => {
+                let (y, x) = self.rope.offset_to_line(*at);
+                for c in text.chars() {
+                    if c == '\n' {
+                        self.rope.join_line(y + 1);
+                    } else {
+                        self.rope.edit(y, |buffer| buffer.delete(x));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Functions related to undo and redo
+impl Document {
+    /// Ends the current group of changes, everything since the last commit is undone in one go.
+    pub fn commit(&mut self, cursor: &Position) {
+        self.history.commit(cursor);
+    }
+
+    fn take_step(&mut self, step: Step) -> Position {
+        let cursor = match step {
+            Step::Undo(id) => {
+                let revision = &self.history.revisions[id];
+                let changes: Vec<Change> =
+                    revision.changes.iter().rev().map(Change::inverse).collect();
+                let cursor = revision.cursor_before;
+                changes.iter().for_each(|change| self.apply(change));
+                cursor
+            }
+            Step::Redo(id) => {
+                let revision = &self.history.revisions[id];
+                let changes = revision.changes.clone();
+                let cursor = revision.cursor_after;
+                changes.iter().for_each(|change| self.apply(change));
+                cursor
             }
+        };
 
-            true
+        self.history.apply(&step);
+        cursor
+    }
+
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.undo_step()?;
+        Some(self.take_step(step))
+    }
+
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.redo_step()?;
+        Some(self.take_step(step))
+    }
+
+    /// Moves to any revision in the undo tree, even if it's on another branch.
+    pub fn goto_revision(&mut self, target: usize) -> Option<Position> {
+        if target >= self.history.revisions.len() || target == self.history.current {
+            return None;
+        }
+
+        let mut cursor = None;
+        for step in self.history.path_to(target) {
+            cursor = Some(self.take_step(step));
+        }
+
+        cursor
+    }
+}
+
+// Functions related to files
+impl Document {
+    /// The whole file as it's written to disk, before encoding.
+    fn contents(&self) -> String {
+        let line_ending = self.line_ending.as_str();
+        let mut contents = String::new();
+        for (index, buffer) in self.rope.iter().enumerate() {
+            if index > 0 {
+                contents.push_str(line_ending);
+            }
+            contents.push_str(&buffer.line());
+        }
+
+        if self.final_newline && !self.is_empty() {
+            contents.push_str(line_ending);
+        }
+
+        contents
+    }
+
+    /// Writes the document to `filename`, and returns the hash of what was written.
+    fn write(&self, filename: &str, backup: bool) -> Result<u64, std::io::Error> {
+        if self.read_only {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "The file is read-only, use `set noreadonly` to write it anyway",
+            ));
+        }
+
+        // Encoding happens before the file is touched, so a failure leaves it as it was.
+        let contents = self.contents();
+        let bytes = encoding::encode(&contents, self.encoding, self.bom)
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+
+        save::write_atomically(Path::new(filename), &bytes, backup)?;
+        Ok(undo_file::hash(&contents))
+    }
+
+    /// Writes a copy of the document to another file, the document stays bound to its own file.
+    pub fn write_copy(&self, filename: &str, backup: bool) -> Result<(), std::io::Error> {
+        self.write(filename, backup).map(|_| ())
+    }
+
+    /// Saves the document, with `backup` the previous version is kept as `filename~`.
+    pub fn save_file(&mut self, backup: bool) -> Result<(), std::io::Error> {
+        self.saved_hash = self.write(&self.filename, backup)?;
+        self.stamp = Stamp::of(&self.filename);
+        self.new_file = false;
+        self.mark_saved();
+        let _ = undo_file::write(&self.filename, self.saved_hash, &self.history);
+
+        self.journal.clear();
+        self.journal_written = 0;
+        let _ = self.write_swap();
+
+        Ok(())
+    }
+
+    /// Binds the document to another file and saves it there, the swap file moves along.
+    pub fn save_as(&mut self, filename: &str, backup: bool) -> Result<(), std::io::Error> {
+        let previous = mem::replace(&mut self.filename, filename.to_string());
+        let owned_swap = mem::replace(&mut self.owns_swap, true);
+
+        if let Err(error) = self.save_file(backup) {
+            self.filename = previous;
+            self.owns_swap = owned_swap;
+            return Err(error);
+        }
+
+        if owned_swap {
+            swap_file::remove(&previous);
+        }
+
+        Ok(())
+    }
+}
+
+// Functions related to changes made to the file by other programs
+impl Document {
+    /// Looks at the file on disk, and tells if something else changed it since it was last read
+    /// or written. Every change is only reported once.
+    pub fn check_disk(&mut self) -> Option<FileChange> {
+        if self.filename.is_empty() {
+            return None;
+        }
+
+        let stamp = Stamp::of(&self.filename);
+        if stamp == self.stamp && !stamp.is_some_and(|stamp| stamp.is_recent()) {
+            return None;
+        }
+
+        self.stamp = stamp;
+        if stamp.is_none() {
+            return Some(FileChange::Removed);
+        }
+
+        // Only the contents count, `touch` changes the time but not the file.
+        let bytes = fs::read(&self.filename).ok()?;
+        if undo_file::hash(&encoding::decode(&bytes).text) == self.saved_hash {
+            None
         } else {
-            let current = self.gap_buffer.get_mut(at.y).unwrap();
-            current.delete(at.x);
+            Some(FileChange::Modified)
+        }
+    }
+
+    /// The text of the file on disk, with `\n` between lines like `text` gives.
+    pub fn disk_text(&self) -> Result<String, std::io::Error> {
+        let bytes = fs::read(&self.filename)?;
+        let contents = encoding::decode(&bytes).text;
+        let (lines, _, _) = split_file(&contents);
+        Ok(lines.join("\n"))
+    }
+
+    /// Replaces the document with the file on disk. Like any other change, this can be undone,
+    /// all at once.
+    pub fn reload(&mut self, cursor: &Position) -> Result<(), std::io::Error> {
+        let bytes = fs::read(&self.filename)?;
+        let Decoded {
+            text: contents,
+            encoding,
+            bom,
+            lossy,
+        } = encoding::decode(&bytes);
+        let (lines, line_ending, final_newline) = split_file(&contents);
 
-            false
+        // Only the part that differs is replaced, so that the history doesn't keep two copies
+        // of the whole file.
+        let (start, end, inserted) = self.difference(&lines);
+        if start != end || !inserted.is_empty() {
+            self.commit(cursor);
+            self.remove(start, end, cursor);
+            if !inserted.is_empty() {
+                let change = Change::Insert {
+                    at: start,
+                    text: inserted,
+                };
+                self.change(change, cursor);
+            }
+            self.commit(cursor);
         }
+
+        self.line_ending = line_ending;
+        self.final_newline = final_newline;
+        self.encoding = encoding;
+        self.bom = bom;
+        self.read_only = lossy;
+        self.new_file = false;
+        self.saved_hash = undo_file::hash(&contents);
+        self.stamp = Stamp::of(&self.filename);
+        self.mark_saved();
+
+        self.journal.clear();
+        self.journal_written = 0;
+        let _ = self.write_swap();
+        Ok(())
     }
 
-    pub fn enter(&mut self, at: &Position) {
-        let new = self.gap_buffer.get_mut(at.y).unwrap().split(at.x);
-        self.gap_buffer.insert(at.y + 1, new);
+    /// Where the document stops matching `lines`: the offsets of the part that differs, and
+    /// the text that replaces it. The lines the two have in common at the start and the end
+    /// are skipped first, only the rest is compared character by character.
+    fn difference(&self, lines: &[&str]) -> (usize, usize, String) {
+        let same = |buffer: &GapBuffer, line: &str| buffer.chars().eq(line.chars());
+        let prefix = self
+            .rope
+            .iter()
+            .zip(lines)
+            .take_while(|(buffer, line)| same(buffer, line))
+            .count();
+        let max_suffix = cmp::min(self.len(), lines.len()) - prefix;
+        let suffix = (1..=max_suffix)
+            .take_while(|i| {
+                let buffer = self.buffer(self.len() - i);
+                buffer.is_some_and(|buffer| same(buffer, lines[lines.len() - i]))
+            })
+            .count();
+
+        // The common lines in characters, each with the newline in front of or behind it,
+        // unless that's past the end of either text.
+        let old_len = self.len_chars();
+        let new_len = lines
+            .iter()
+            .map(|line| line.chars().count() + 1)
+            .sum::<usize>();
+        let new_len = new_len.saturating_sub(1);
+        let start = cmp::min(self.rope.line_to_offset(prefix), cmp::min(old_len, new_len));
+        let suffix_len = lines[lines.len() - suffix..]
+            .iter()
+            .map(|line| line.chars().count() + 1)
+            .sum::<usize>();
+        let suffix_len = cmp::min(suffix_len, cmp::min(old_len, new_len) - start);
+
+        let old: Vec<char> = self.text(start, old_len - suffix_len).chars().collect();
+        let new: Vec<char> = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| (i > 0).then_some('\n').into_iter().chain(line.chars()))
+            .skip(start)
+            .take(new_len - suffix_len - start)
+            .collect();
+
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let max_suffix = cmp::min(old.len(), new.len()) - prefix;
+        let suffix = old
+            .iter()
+            .rev()
+            .zip(new.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let inserted = new[prefix..new.len() - suffix].iter().collect();
+        (start + prefix, start + old.len() - suffix, inserted)
+    }
+
+    /// Keeps the document as it is after the file changed on disk. It counts as modified,
+    /// since it no longer matches the file.
+    pub fn keep_changes(&mut self) {
+        if let Ok(bytes) = fs::read(&self.filename) {
+            self.saved_hash = undo_file::hash(&encoding::decode(&bytes).text);
+        }
+        self.saved_revision = None;
+    }
+}
+
+// Functions related to the swap file
+impl Document {
+    /// Amount of changes made since the swap file was last written.
+    pub fn unwritten_changes(&self) -> usize {
+        self.journal.len() - self.journal_written
+    }
+
+    /// Writes every change since the last save to the swap file, so they survive a crash.
+    pub fn write_swap(&mut self) -> Result<(), std::io::Error> {
+        if !self.owns_swap {
+            return Ok(());
+        }
+
+        swap_file::write(&self.filename, self.saved_hash, &self.journal)?;
+        self.journal_written = self.journal.len();
+        Ok(())
+    }
+
+    /// Removes the swap file when the document is closed.
+    pub fn close(&mut self) {
+        if self.owns_swap {
+            swap_file::remove(&self.filename);
+        }
+    }
+
+    /// Whether a change can be made to the document as it is, deletions have to match the text.
+    fn fits(&self, change: &Change) -> bool {
+        match change {
+            Change::Insert { at, .. } => *at <= self.len_chars(),
+            Change::Delete { at, text } => {
+                let end = at + text.chars().count();
+                end <= self.len_chars() && self.text(*at, end) == *text
+            }
+        }
     }
 
-    fn truncate_and_open_file(&self) -> Result<fs::File, std::io::Error> {
-        let mut file = fs::OpenOptions::new();
-        file.write(true).truncate(true).open(&self.filename)
+    /// Whether the swap file was written for the file as it is on disk now.
+    pub fn swap_matches(&self, swap: &Swap) -> bool {
+        swap.hash == self.saved_hash
     }
 
-    pub fn save_file(&mut self) {
-        if let Ok(mut file) = self.truncate_and_open_file() {
-            for buffer in &self.gap_buffer {
-                let string = format!("{}\n", buffer.line());
-                file.write_all(string.as_bytes()).unwrap();
+    /// Replays the changes of a stale swap file as one undoable change.
+    /// Stops at the first change that doesn't fit, returns how many were made.
+    pub fn recover(&mut self, swap: &Swap, cursor: &Position) -> usize {
+        let mut recovered = 0;
+        for change in &swap.changes {
+            if !self.fits(change) {
+                break;
             }
+
+            self.change(change.clone(), cursor);
+            recovered += 1;
         }
+
+        self.commit(cursor);
+        self.owns_swap = true;
+        let _ = self.write_swap();
+        recovered
+    }
+
+    /// Forgets about a stale swap file, it's replaced by the swap file of this session.
+    pub fn discard_swap(&mut self) {
+        self.owns_swap = true;
+        let _ = self.write_swap();
+    }
+
+    /// The whole document as it would be after recovering `swap`, with `\n` between lines.
+    pub fn recovered_text(&self, swap: &Swap) -> String {
+        let lines = self
+            .rope
+            .iter()
+            .map(|buffer| GapBuffer::new(&buffer.line()));
+        let mut copy = Self {
+            rope: Rope::from_lines(lines),
+            ..Self::default()
+        };
+        copy.recover(swap, &Position::default());
+        copy.text(0, copy.len_chars())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text: &str) -> Document {
+        let lines = split_file(text).0.into_iter().map(GapBuffer::new);
+        Document {
+            rope: Rope::from_lines(lines),
+            ..Document::default()
+        }
+    }
+
+    const TEXTS: [&str; 10] = [
+        "",
+        "a",
+        "b",
+        "ab",
+        "a\nb",
+        "a\nb\nc",
+        "a\nc",
+        "\nb",
+        "a\n\nb",
+        "ab\nb\nab",
+    ];
+
+    #[test]
+    fn text_can_start_and_end_anywhere() {
+        for text in TEXTS {
+            let document = document(text);
+            let chars: Vec<char> = text.chars().collect();
+            assert_eq!(document.len_chars(), chars.len());
+            for start in 0..=chars.len() {
+                for end in start..=chars.len() + 1 {
+                    let expected: String = chars[start..end.min(chars.len())].iter().collect();
+                    assert_eq!(document.text(start, end), expected, "{:?}", text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn differences_cover_only_what_changed() {
+        for old in TEXTS {
+            for new in TEXTS {
+                let document = document(old);
+                let (lines, _, _) = split_file(new);
+                let (start, end, inserted) = document.difference(&lines);
+
+                let chars: Vec<char> = old.chars().collect();
+                let before: String = chars[..start].iter().collect();
+                let after: String = chars[end..].iter().collect();
+                assert_eq!(before + &inserted + &after, new, "{:?} to {:?}", old, new);
+                // Nothing that stays the same is taken out and put back.
+                let removed: String = chars[start..end].iter().collect();
+                assert!(
+                    removed.chars().next() != inserted.chars().next() || removed.is_empty(),
+                    "{:?} to {:?}",
+                    old,
+                    new
+                );
+            }
+        }
+
+        let document = document("one\ntwo\nthree");
+        assert_eq!(
+            document.difference(&["one", "TWO", "three"]),
+            (4, 7, "TWO".to_string())
+        );
     }
 }
@@ -1,33 +1,44 @@
-use std::env;
-
-use std::io;
+use std::fs;
+use std::iter;
+use std::mem;
+use std::path::Path;
 use std::time::Duration;
 use std::time::Instant;
 
-use std::fmt;
-
 use crate::ui::State;
 
 use super::terminal;
 use terminal::Terminal;
 
 use super::modes::Mode;
+use super::options::Options;
 use super::status_message::StatusMessage;
 
+use super::buffer::{self, Buffer};
+use super::clipboard::{Provider, Selection};
+use super::diff;
 use super::gap_buffer::GapBuffer;
+use super::rope::Rope;
+use super::swap_file::Swap;
+use super::width;
+use super::window::{Layout, Rect, Split, Tab, Window};
 
-use super::ui::{run_command_mode, App, Command};
-use tui::backend::CrosstermBackend;
+use super::motion::{self, column_of, Kind, Motion, Range, TextObject};
+use super::operator::{self, Action, Operator, Parse, Prefix, Target};
+use super::register::{self, Register, Registers};
+use super::timer::{Timer, Timers};
+use super::ui::{command_window, run_command_mode, App, Command};
 
 use super::document;
-use document::Document;
+use document::{Document, FileChange};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 use crossterm::{
     cursor::CursorShape,
-    event::{poll, read, Event, KeyCode as Key, KeyEvent, KeyModifiers as Mod},
-    queue,
+    event::{Event, KeyCode as Key, KeyEvent, KeyModifiers as Mod},
     style::Color,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen},
 };
 
 const STATUS_FG_COLOUR: Color = Color::Rgb {
@@ -40,9 +51,32 @@ const STATUS_BAR_BG_COLOUR: Color = Color::Rgb {
     g: 239,
     b: 239,
 };
+// The text selected in visual mode.
+const SELECTION_BG_COLOUR: Color = Color::Rgb {
+    r: 68,
+    g: 85,
+    b: 136,
+};
+// The status lines of windows that don't have the cursor.
+const INACTIVE_STATUS_BAR_BG_COLOUR: Color = Color::Rgb {
+    r: 160,
+    g: 160,
+    b: 160,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const FILENAME_PROMPT: &str = "Save as: ";
+
+// How often the file is checked for changes made by other programs.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// How long the user has to stop typing before unsaved changes go to the swap file.
+const SWAP_DELAY: Duration = Duration::from_secs(4);
+// The swap file is also written after this many changes, in case the typing never stops.
+const SWAP_CHANGES: usize = 200;
+// How long a status message stays in the message bar.
+const STATUS_DURATION: Duration = Duration::from_secs(5);
+
 #[derive(Default, Clone, Copy, Debug)]
 pub struct Position {
     pub x: usize,
@@ -68,21 +102,11 @@ impl From<(usize, usize)> for Position {
 }
 
 impl Position {
-    fn new(x: usize, y: usize) -> Self {
+    pub fn new(x: usize, y: usize) -> Self {
         Self { x, y }
     }
 }
 
-pub struct Object<T: fmt::Debug> {
-    obj: T,
-}
-
-impl<T: fmt::Debug> fmt::Debug for Object<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#?}", self.obj)
-    }
-}
-
 pub fn create_event(key: Key, modifier: Mod) -> Event {
     Event::Key(KeyEvent {
         code: key,
@@ -90,6 +114,26 @@ pub fn create_event(key: Key, modifier: Mod) -> Event {
     })
 }
 
+/// What was selected in visual mode, kept after the selection is gone for operators and `gv`.
+#[derive(Clone, Copy)]
+struct Visual {
+    mode: Mode,
+    anchor: Position,
+    cursor: Position,
+}
+
+/// A question in the message bar, every key goes to it until it's answered.
+enum Prompt {
+    // What to do with a swap file found when opening the document.
+    Recovery(Swap),
+    // The name for a document that doesn't have one yet, and whether to quit once it's saved.
+    Filename { input: String, quit: bool },
+    // Whether to save the document before quitting.
+    Quit,
+    // What to do after another program changed the file.
+    Changed,
+}
+
 pub struct Editor {
     mode: Mode,
     offset: Position,
@@ -99,33 +143,133 @@ pub struct Editor {
     should_quit: bool,
     status: StatusMessage,
     app: App,
+    options: Options,
+    prompt: Option<Prompt>,
+    // The real document, while the diff between it and the swap file is being shown.
+    diff_view: Option<Document>,
+    // The buffers that aren't shown, ordered by id. The one that is shown lives in `document`,
+    // `cursor_position` and `offset`.
+    buffers: Vec<Buffer>,
+    buffer_id: usize,
+    next_buffer_id: usize,
+    // The buffer that was shown before this one, `Ctrl-^` goes back to it.
+    alternate: Option<usize>,
+    // The windows that don't have the cursor. The one that has it shows `buffer_id`.
+    windows: Vec<Window>,
+    window_id: usize,
+    next_window_id: usize,
+    // The window that had the cursor before this one, `Ctrl-W p` goes back to it.
+    previous_window: Option<usize>,
+    layout: Layout,
+    // The tab pages that aren't shown. The one that is shown comes `tab_index` tabs from the
+    // left, its windows are the ones above.
+    tabs: Vec<Tab>,
+    tab_index: usize,
+    // The start of a command like `gg` or `Ctrl-W v`, while it waits for the rest.
+    pending: Vec<KeyEvent>,
+    timers: Timers,
+    // Every window as it was last drawn, with the offset it had then.
+    drawn: Vec<(usize, Rect, Position)>,
+    registers: Registers,
+    // Where the `+` and `*` registers go.
+    clipboard: Provider,
+    // The text typed since insert mode was entered, it goes to the `.` register when it's left.
+    typed: String,
+    // The end of the selection that stays put while the cursor moves, in visual mode.
+    anchor: Position,
+    // The last selection.
+    visual: Option<Visual>,
+    // The column and the other lines of a block that is being changed, they get the text typed
+    // into the first line when insert mode is left.
+    block_change: Option<(usize, Vec<usize>)>,
 }
 
-impl Editor {
-    pub fn new(stdout: io::Stdout) -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = "Press CTRL + Q to QUIT.".to_string();
-        let document = if args.len() > 1 {
-            let doc = Document::open(&args[1]);
-            if doc.is_ok() {
-                doc
+/// Moves `offset` just enough for the cursor to be inside a `width` by `height` view.
+fn follow(offset: &mut Position, column: usize, y: usize, width: usize, height: usize) {
+    if y < offset.y {
+        offset.y = y;
+    } else if y >= offset.y.saturating_add(height) {
+        offset.y = y.saturating_sub(height).saturating_add(1);
+    }
+
+    if column < offset.x {
+        offset.x = column;
+    } else if column >= offset.x.saturating_add(width) {
+        offset.x = column.saturating_sub(width).saturating_add(1);
+    }
+}
+
+/// Two positions, the one that comes first in the text first.
+fn ordered(a: Position, b: Position) -> (Position, Position) {
+    if (b.y, b.x) < (a.y, a.x) {
+        (b, a)
+    } else {
+        (a, b)
+    }
+}
+
+/// Opens a file for a new buffer, along with anything the user should know about it.
+fn open_document(filename: &str) -> (Document, Option<String>) {
+    match Document::open(filename) {
+        Ok(document) => {
+            let message = if let Some(pid) = document.other_instance {
+                Some(format!(
+                    "{} is being edited by another hecto (process {}), it was opened read-only.",
+                    filename, pid
+                ))
+            } else if document.read_only {
+                Some(format!(
+                    "Could not decode the file as {}, it was opened read-only.",
+                    document.encoding.name()
+                ))
+            } else if document.new_file {
+                Some(format!("\"{}\" [New]", filename))
             } else {
-                let mut doc = Document::default();
-                initial_status = format!("ERR: Could not open file: {}", doc.filename);
-                doc.filename = "[ERROR COULD NOT OPEN FILE]".to_string();
-                Ok(doc)
-            }
-        } else {
-            let mut doc = Document::default();
-            doc.filename = "[NO FILE OPENED]".to_string();
-            Ok(doc)
+                None
+            };
+            (document, message)
+        }
+        Err(error) => {
+            // The name is kept, but a file that couldn't be read shouldn't be overwritten.
+            let message = format!("ERR: Could not open {}: {}", filename, error);
+            let document = Document {
+                filename: filename.to_string(),
+                read_only: true,
+                ..Document::default()
+            };
+            (document, Some(message))
         }
-        .unwrap();
+    }
+}
+
+impl Editor {
+    /// An editor drawing on `terminal`, for the command line arguments `args`.
+    pub fn new(terminal: Terminal, mut args: Vec<String>) -> Self {
+        // `-p` opens every file in a tab page of its own.
+        let tabbed = args.iter().any(|arg| arg == "-p");
+        args.retain(|arg| arg != "-p");
+
+        // Every file on the command line gets a buffer, the first one is shown.
+        let mut documents = args.iter().map(|filename| open_document(filename));
+        let (document, message) = documents.next().unwrap_or_default();
+        let buffers: Vec<Buffer> = documents
+            .zip(2..)
+            .map(|((document, _), id)| Buffer::new(id, document))
+            .collect();
+
+        let tabs: Vec<Tab> = if tabbed {
+            let windows = 2..;
+            let tabs = buffers.iter().zip(windows);
+            tabs.map(|(buffer, window)| Tab::new(window, buffer.id))
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        let terminal = Terminal::new(stdout).expect("Failed to initialize terminal.");
         let app = App::default();
+        let initial_status = message.unwrap_or_else(|| "Press CTRL + Q to QUIT.".to_string());
 
-        Self {
+        let mut editor = Self {
             mode: Mode::Normal,
             offset: Position::default(),
             should_quit: false,
@@ -134,11 +278,33 @@ impl Editor {
             cursor_position: Position { x: 0, y: 0 },
             status: StatusMessage::from(initial_status),
             app,
-        }
-    }
+            options: Options::default(),
+            prompt: None,
+            diff_view: None,
+            buffer_id: 1,
+            next_buffer_id: buffers.len() + 2,
+            buffers,
+            alternate: None,
+            windows: Vec::new(),
+            window_id: 1,
+            next_window_id: tabs.len() + 2,
+            previous_window: None,
+            layout: Layout::Window(1),
+            tabs,
+            tab_index: 0,
+            pending: Vec::new(),
+            timers: Timers::default(),
+            drawn: Vec::new(),
+            registers: Registers::default(),
+            clipboard: Provider::detect(),
+            typed: String::new(),
+            anchor: Position::default(),
+            visual: None,
+            block_change: None,
+        };
 
-    pub fn has_event(&self, timeout: Duration) -> bool {
-        poll(timeout).unwrap_or(false)
+        editor.enter_buffer();
+        editor
     }
 
     pub fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
@@ -147,11 +313,16 @@ impl Editor {
     }
 
     fn check_mode(&mut self, key: Event) {
-        if self.mode == Mode::Normal {
+        if self.prompt.is_some() {
+            self.answer_prompt(key);
+        } else if self.mode == Mode::Normal {
             self.terminal.change_cursor_shape(CursorShape::Block);
             self.normal_mode(key);
         } else if self.mode == Mode::Command {
             self.command_mode(key);
+        } else if self.mode.is_visual() {
+            self.terminal.change_cursor_shape(CursorShape::Block);
+            self.visual_mode(key);
         } else {
             self.terminal.change_cursor_shape(CursorShape::Line);
             self.insert_mode(key);
@@ -159,513 +330,3813 @@ impl Editor {
     }
 
     pub fn run(&mut self) {
-        enable_raw_mode().unwrap();
-        queue!(&mut self.terminal.stdout, EnterAlternateScreen).unwrap();
+        self.terminal.enable_raw_mode();
+        self.terminal.enter_alternate_screen();
         if let Err(error) = self.refresh_screen() {
             self.terminal.clear_screen();
             eprintln!("{}", error);
         };
 
-        loop {
-            let key = create_event(Key::Null, Mod::NONE);
-            self.check_mode(key);
+        self.timers
+            .schedule(Timer::Watch, Instant::now() + WATCH_INTERVAL);
+        self.render();
 
-            if self.should_quit {
-                self.terminal.clear_screen();
-                disable_raw_mode().unwrap();
-                queue!(&mut self.terminal.stdout, EnterAlternateScreen).unwrap();
-                break;
-            } else if self.mode != Mode::Command {
-                self.terminal
-                    .update_dimensions(self.terminal.size().clone());
-
-                self.terminal.hide_cursor();
-                self.draw_rows();
-                self.draw_status_bar();
-                self.draw_message_bar();
-
-                let pos = Position {
-                    x: self.cursor_position.x.saturating_sub(self.offset.x),
-                    y: self.cursor_position.y.saturating_sub(self.offset.y),
-                };
+        // Printing to stderr would end up in the middle of the screen, errors go to the message
+        // bar instead.
+        while !self.should_quit {
+            if let Err(error) = self.step() {
+                self.status = StatusMessage::from(format!("ERR: {}", error));
+                self.render();
+            };
+        }
 
-                self.terminal.set_cursor_position(&pos);
-                self.terminal.show_cursor();
-            } else if self.mode == Mode::Command {
-                let prev_dim = self.terminal.size().clone();
+        self.document.close();
+        for buffer in &mut self.buffers {
+            buffer.document.close();
+        }
+        self.terminal.clear_screen();
+        let _ = self.terminal.flush();
+        self.terminal.leave_alternate_screen();
+        self.terminal.disable_raw_mode();
+    }
 
-                // self.terminal.update_dimensions(self.terminal.size().clone());
+    /// Waits for an event or for the next timer to go off, handles it and draws the screen again
+    /// if anything changed.
+    fn step(&mut self) -> Result<(), std::io::Error> {
+        let timeout = match self.timers.next() {
+            Some(at) => at.saturating_duration_since(Instant::now()),
+            None => WATCH_INTERVAL,
+        };
 
-                let cur_dim = self.terminal.size().clone();
-                let _result = prev_dim != cur_dim;
-            }
+        let mut redraw = false;
+        if self.terminal.poll(timeout) {
+            let event = self.terminal.read()?;
+            redraw = self.handle_event(event);
+        }
 
-            if let Err(error) = self.process_keypress() {
-                eprintln!("{}", error);
-            };
+        for timer in self.timers.due(Instant::now()) {
+            redraw |= self.on_timer(timer);
+        }
 
-            self.terminal.flush();
+        if redraw && !self.should_quit {
+            self.render();
         }
+        Ok(())
     }
 
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = read().unwrap();
-        if let Event::Key(event) = pressed_key {
-            match event.code {
-                Key::Esc => {
-                    if self.mode != Mode::Normal {
-                        self.check_mode(pressed_key);
-                    }
+    /// Returns whether the screen has to be drawn again.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Key(key) => self.handle_key(key),
+            Event::Resize(width, height) => {
+                self.terminal.resize(width, height);
+                self.terminal.clear_screen();
+            }
+            // Mouse capture isn't turned on, so these only come from terminals that send them
+            // anyway.
+            Event::Mouse(_) => return false,
+        }
 
-                    self.change_mode(Mode::Normal);
+        true
+    }
+
+    fn handle_key(&mut self, event: KeyEvent) {
+        let pressed_key = Event::Key(event);
+        match event.code {
+            Key::Esc => {
+                if self.mode != Mode::Normal || self.prompt.is_some() {
+                    self.check_mode(pressed_key);
                 }
-                _ => self.check_mode(pressed_key),
+
+                // Esc also throws away the start of a command like `gg`.
+                self.pending.clear();
+                self.timers.cancel(Timer::PendingKeys);
+                self.change_mode(Mode::Normal);
             }
-        };
+            _ => self.check_mode(pressed_key),
+        }
+
+        if self.document.unwritten_changes() >= SWAP_CHANGES {
+            self.write_swap();
+        }
+
+        // Unsaved changes go to the swap file (and with `autosave` to the file itself) once the
+        // user stops typing for a moment.
+        let now = Instant::now();
+        self.timers.schedule(Timer::Swap, now + SWAP_DELAY);
+        if self.options.autosave > 0 {
+            let delay = Duration::from_secs(self.options.autosave as u64);
+            self.timers.schedule(Timer::AutoSave, now + delay);
+        } else {
+            self.timers.cancel(Timer::AutoSave);
+        }
 
         self.scroll();
-        Ok(())
     }
 
-    fn change_mode(&mut self, change_to: Mode) {
-        self.mode = change_to;
+    /// Returns whether the screen has to be drawn again.
+    fn on_timer(&mut self, timer: Timer) -> bool {
+        match timer {
+            Timer::PendingKeys => {
+                self.pending.clear();
+                false
+            }
+            Timer::Status => true,
+            Timer::Swap if self.unwritten_changes() > 0 => {
+                self.write_swap();
+                true
+            }
+            Timer::Swap => false,
+            Timer::Watch => {
+                self.timers
+                    .schedule(Timer::Watch, Instant::now() + WATCH_INTERVAL);
+                self.check_file()
+            }
+            Timer::AutoSave => self.auto_save(),
+        }
     }
 
-    fn normal_mode(&mut self, key: Event) {
-        let terminal_height = self.terminal.size().height as usize;
-        let Position { mut x, mut y } = self.cursor_position;
+    fn render(&mut self) {
+        self.draw();
+        if self.mode == Mode::Command {
+            self.draw_command_window();
+        }
+        if let Err(error) = self.terminal.flush() {
+            // The whole screen is drawn again next time, the message comes along with it.
+            self.status = StatusMessage::from(format!("ERR: Could not draw the screen: {}", error));
+        }
 
-        let doc_height = self.document.len();
-        // the width changes depending on the length of the row
-        let mut width = if let Some(buffer) = self.document.buffer(y) {
-            buffer.len.saturating_sub(1)
-        } else {
-            0
-        };
+        // The message bar has to be drawn again when the status message runs out.
+        let expiry = self.status.time + STATUS_DURATION;
+        if expiry > Instant::now() {
+            self.timers.schedule(Timer::Status, expiry);
+        }
+    }
 
-        if let Event::Key(event) = key {
-            match event.code {
-                Key::Char('k') => {
-                    y = y.saturating_sub(1);
-                    if x == width {
-                        let top = self.document.buffer(y).unwrap();
-                        x = top.len.saturating_sub(1);
-                    }
-                }
+    fn draw(&mut self) {
+        self.terminal.hide_cursor();
+        // The terminal may have been resized since the last key.
+        self.scroll();
+        self.fit_windows();
+        self.draw_tab_line();
+        self.draw_windows();
 
-                Key::Char('j') => {
-                    if y < doc_height.saturating_sub(1) {
-                        y = y.saturating_add(1);
-                        if x >= width.saturating_sub(1) {
-                            // NOTE: In the case of an empty line, it will move the cursor to the
-                            // very end of it because the if condition is still true.
-                            let bottom = self.document.buffer(y).unwrap();
-                            x = bottom.len.saturating_sub(1);
-                        }
-                    }
+        // The message bar is right below the windows.
+        let message_bar = self.terminal.size().height as usize + 1;
+        self.terminal
+            .set_cursor_position(&Position::new(0, message_bar));
+        self.draw_message_bar();
 
-                    // let s_key = create_event(Key::Char('s'), Mod::NONE);
-                    // self.normal_mode(s_key);
-                }
-
-                Key::Char('h') => {
-                    // lets the user move to the end of the previous line,
-                    // if cursor at the start of a line.
-                    if x > 0 {
-                        x -= 1;
-                    } else if y > 0 {
-                        y -= 1;
-                        if let Some(buffer) = self.document.buffer(y) {
-                            x = buffer.len.saturating_sub(1);
-                        } else {
-                            x = 0;
-                        }
-                    }
-                }
+        let pos = match &self.prompt {
+            Some(Prompt::Filename { input, .. }) => Position {
+                x: FILENAME_PROMPT.len() + input.width(),
+                y: message_bar,
+            },
+            _ => self.cursor_on_screen(),
+        };
 
-                Key::Char('l') => {
-                    if x < width {
-                        x += 1;
-                    } else if y < doc_height.saturating_sub(1) && x >= width {
-                        y += 1;
-                        x = 0;
-                    }
-                }
+        self.terminal.set_cursor_position(&pos);
+        self.terminal.show_cursor();
+    }
 
-                Key::Char('b') => {
-                    if let Some(buffer) = self.document.buffer(y) {
-                        if let Some(contents) = buffer.line().get(..x.saturating_add(1)) {
-                            let mut index = 0;
+    /// Writes the document to its file, asks for a filename first if it doesn't have one.
+    fn save(&mut self) {
+        if self.document.filename.is_empty() {
+            self.prompt = Some(Prompt::Filename {
+                input: String::new(),
+                quit: false,
+            });
+            return;
+        }
 
-                            // count starts at 0
-                            for (count, ch) in contents.chars().rev().enumerate() {
-                                // NOTE: Still buggy, run once in nvim then in hecto to see problem.
-                                if ch == ' ' {
-                                    index = count + 1;
-                                    break;
-                                }
-                            }
+        // Writing over changes made by another program needs an answer first.
+        if self.check_file() && self.prompt.is_some() {
+            return;
+        }
 
-                            if y > 0 && x == 0 {
-                                y -= 1;
-                                let buffer = self.document.buffer(y).unwrap();
-                                x = buffer.len.saturating_sub(1);
-                            } else if index == 0 {
-                                x = 0;
-                            } else {
-                                x = x.saturating_sub(index);
-                            }
-                        } else {
-                            // NOTE: If there is white space at the front, it goes through one character by one character.
-                            // It doesn't skip straight to the non white-space character
-                            y = y.saturating_sub(1);
-                            let buffer = self.document.buffer(y).unwrap();
-                            x = buffer.len.saturating_sub(1);
-                        }
-                    }
-                }
+        let result = self.document.save_file(self.options.backup);
+        let filename = self.document.filename.clone();
+        self.report_write(&filename, result);
+    }
 
-                Key::Char('w') => {
-                    if event.modifiers.contains(Mod::ALT) {
-                        let filename = &self.document.filename;
-                        if filename == "[NO FILE OPENED]" {
-                            // NOTE: Make a window show up, and let them type the file name, but only the text box this time.
-                            todo!("This is a shortcut key to save a file.");
-                        } else {
-                            let status = StatusMessage::from("File written.");
-                            self.document.save_file();
-                            self.status = status;
-                            self.app.state = State::Success;
-                        }
-                    } else {
-                        if let Some(row) = self.document.buffer(y) {
-                            // NOTE: If there is white space at the front, it goes through one character by one character.
-                            // It doesn't skip straight to the non white-space character
-                            if let Some(contents) = row.line().get(x..) {
-                                let mut index = 0;
-
-                                for (count, currrent_ch) in contents.chars().enumerate() {
-                                    if currrent_ch == ' ' {
-                                        index = count + 1;
-                                        break;
-                                    }
-                                }
+    /// Saves the document after the user stopped typing for `autosave` seconds, if there's
+    /// anything to save and a file to save it to. Returns whether it tried.
+    fn auto_save(&mut self) -> bool {
+        let document = &self.document;
+        if !document.is_modified() || document.filename.is_empty() || document.read_only {
+            return false;
+        }
+        if self.prompt.is_some() || self.mode == Mode::Command {
+            return false;
+        }
 
-                                if y < doc_height.saturating_sub(1) && x >= width.saturating_sub(1)
-                                {
-                                    y += 1;
-                                    x = 0;
-                                } else if index == 0 {
-                                    x = width.saturating_sub(1);
-                                } else {
-                                    x = x.saturating_add(index);
-                                }
-                            }
-                        }
-                    }
-                }
+        self.save();
+        true
+    }
 
-                Key::Char('K') => {
-                    // first if only happens on the 1st screen.
-                    y = if y > terminal_height {
-                        // saturating_add/sub not used because y and terminal_height
-                        // have the same type.
-                        y - terminal_height
-                    } else {
-                        0
-                    }
-                }
-                Key::Char('J') => {
-                    // terminal_height is the number of visible rows on the screen.
-                    // height is the number of rows in the entire file
-                    y = if y.saturating_add(terminal_height) < doc_height.saturating_sub(1) {
-                        y + terminal_height as usize
-                    } else {
-                        // This is only true when it's at the last page
-                        doc_height.saturating_sub(1)
-                    }
-                }
+    /// Writes the document to `filename`. A document without a name is bound to it,
+    /// with `bind` it's bound to it either way. Existing files are only overwritten with `force`.
+    fn save_to(&mut self, filename: &str, bind: bool, force: bool) {
+        if !force && filename != self.document.filename && Path::new(filename).exists() {
+            self.status = StatusMessage::from(format!(
+                "ERR: {} already exists, add ! to overwrite it",
+                filename
+            ));
+            self.app.state = State::InvalidCommand;
+            return;
+        }
 
-                Key::Char('G') => y = doc_height.saturating_sub(1),
+        let backup = self.options.backup;
+        let result = if bind || self.document.filename.is_empty() {
+            self.document.save_as(filename, backup)
+        } else if filename == self.document.filename {
+            self.document.save_file(backup)
+        } else {
+            self.document.write_copy(filename, backup)
+        };
+        self.report_write(filename, result);
+    }
 
-                Key::Char('g') => {
-                    let has_event = self.has_event(Duration::from_millis(500));
+    fn report_write(&mut self, filename: &str, result: Result<(), std::io::Error>) {
+        match result {
+            Ok(()) => {
+                self.status = StatusMessage::from(format!("\"{}\" written", filename));
+                self.app.state = State::Success;
+            }
+            Err(error) => {
+                let message = format!("ERR: Could not write {}: {}", filename, error);
+                self.status = StatusMessage::from(message);
+                self.app.state = State::InvalidCommand;
+            }
+        }
+    }
 
-                    if has_event {
-                        match event.code {
-                            Key::Char('g') => y = 0,
-                            _ => (),
-                        }
-                    }
-                }
-                Key::Char('0') => x = 0,
-                Key::Char('S') => {
-                    // Moves to start of the line, minus the whitespace.
-                    if let Some(buffer) = self.document.buffer(y) {
-                        let contents = buffer.line();
-                        x = width.saturating_sub(contents.trim_start().len());
-                        x = x.saturating_sub(1);
-                    }
+    /// Quits, unless a buffer has unsaved changes. Then every such buffer is shown in turn,
+    /// asking the user what to do with it.
+    fn quit(&mut self) {
+        if !self.document.is_modified() {
+            let modified = self.buffers.iter().find(|b| b.document.is_modified());
+            match modified.map(|b| b.id) {
+                Some(id) => self.switch_to(id),
+                None => {
+                    self.should_quit = true;
+                    return;
                 }
-                Key::Char('s') => x = width,
-
-                // changing modes
-                Key::Char('i') => self.change_mode(Mode::Insert),
+            }
+        }
 
-                Key::Char(':') => self.change_mode(Mode::Command),
+        self.prompt = Some(Prompt::Quit);
+    }
 
-                Key::Char('a') => {
-                    x = x.saturating_add(1);
-                    self.change_mode(Mode::Insert);
-                }
+    /// Saves the document and goes on quitting, unless saving fails.
+    fn write_and_quit(&mut self) {
+        if self.document.filename.is_empty() {
+            self.prompt = Some(Prompt::Filename {
+                input: String::new(),
+                quit: true,
+            });
+            return;
+        }
 
-                Key::Char('A') => {
-                    let buffer = self.document.buffer(y).unwrap();
-                    x = buffer.len;
+        self.save();
+        if !self.document.is_modified() {
+            self.quit();
+        }
+    }
 
-                    self.change_mode(Mode::Insert);
-                }
+    /// Saves every buffer with unsaved changes and quits. Stops at the first one that fails.
+    fn write_all_and_quit(&mut self) {
+        let backup = self.options.backup;
+        for buffer in self.buffers.iter_mut() {
+            let document = &mut buffer.document;
+            if !document.is_modified() {
+                continue;
+            }
 
-                Key::Char('q') => {
-                    if event.modifiers.contains(Mod::CONTROL) {
-                        self.should_quit = true;
-                    }
-                }
+            let result = if document.filename.is_empty() {
+                Err(std::io::Error::other("No file name"))
+            } else {
+                document.save_file(backup)
+            };
 
-                _ => (),
+            if let Err(error) = result {
+                let message = format!("ERR: Could not write buffer {}: {}", buffer.id, error);
+                self.status = StatusMessage::from(message);
+                self.app.state = State::InvalidCommand;
+                return;
             }
         }
 
-        // adjusts the width the the length of the row
-        width = if let Some(buffer) = self.document.buffer(y) {
-            buffer.len
-        } else {
-            0
-        };
+        self.write_and_quit();
+    }
 
-        // if the cursor is further than the width
-        // the x pos of the cursor will be set to the width
-        // snapping it to the end of the line.
-        if x > width {
-            x = width
+    /// Notices when another program changed the file. With `autoread` a document without unsaved
+    /// changes is reloaded right away, otherwise the user is asked. Returns true if it changed.
+    fn check_file(&mut self) -> bool {
+        if self.prompt.is_some() || self.diff_view.is_some() {
+            return false;
+        }
+
+        match self.document.check_disk() {
+            Some(FileChange::Modified) if self.options.autoread && !self.document.is_modified() => {
+                self.reload()
+            }
+            Some(FileChange::Modified) => self.prompt = Some(Prompt::Changed),
+            Some(FileChange::Removed) => {
+                // Saving creates the file again.
+                self.document.keep_changes();
+                self.document.new_file = true;
+                let message = format!("\"{}\" was removed from disk", self.document.filename);
+                self.status = StatusMessage::from(message);
+            }
+            None => return false,
         }
 
-        self.cursor_position = Position { x, y }
+        true
     }
 
-    fn command_mode(&mut self, key: Event) {
-        let stdout = io::stdout();
-        let backend = CrosstermBackend::new(stdout);
-        let mut terminal = tui::Terminal::new(backend).unwrap();
+    /// Replaces the document with the file on disk, unsaved changes are lost but can be undone.
+    fn reload(&mut self) {
+        match self.document.reload(&self.cursor_position) {
+            Ok(()) => {
+                let Position { x, y } = self.cursor_position;
+                let y = y.min(self.document.len().saturating_sub(1));
+                let x = x.min(self.document.buffer(y).map_or(0, |b| b.len_graphemes()));
+                self.cursor_position = Position { x, y };
 
-        match run_command_mode(&mut terminal, &mut self.app, key) {
-            Command::Instruction(mode, (key, modifier)) => {
-                let event = create_event(key, modifier);
-                if mode == Mode::Normal {
-                    self.normal_mode(event);
-                }
+                let message = format!("\"{}\" reloaded", self.document.filename);
+                self.status = StatusMessage::from(message);
+            }
+            Err(error) => {
+                let message = format!(
+                    "ERR: Could not reload {}: {}",
+                    self.document.filename, error
+                );
+                self.status = StatusMessage::from(message);
             }
-            _ => (),
         }
     }
 
-    fn insert_mode(&mut self, key: Event) {
-        let Position { mut x, mut y } = &self.cursor_position;
-        if let Event::Key(event) = key {
-            match event.code {
-                Key::Left => {
-                    let h_key = create_event(Key::Char('h'), Mod::NONE);
-                    self.normal_mode(h_key);
-                }
-                Key::Right => {
-                    let l_key = create_event(Key::Char('l'), Mod::NONE);
-                    self.normal_mode(l_key);
-                }
+    fn unwritten_changes(&self) -> usize {
+        let hidden = self.buffers.iter().map(|b| b.document.unwritten_changes());
+        self.document.unwritten_changes() + hidden.sum::<usize>()
+    }
 
-                Key::Up => {
-                    let k_key = create_event(Key::Char('k'), Mod::NONE);
-                    self.normal_mode(k_key);
-                }
+    /// Writes the swap file of every buffer that has changes the swap file doesn't have yet.
+    fn write_swap(&mut self) {
+        let hidden = self.buffers.iter_mut().map(|b| &mut b.document);
+        for document in iter::once(&mut self.document).chain(hidden) {
+            if document.unwritten_changes() == 0 {
+                continue;
+            }
 
-                Key::Down => {
-                    let j_key = create_event(Key::Char('j'), Mod::NONE);
-                    self.normal_mode(j_key);
-                }
+            if let Err(error) = document.write_swap() {
+                let message = format!("ERR: Could not write swap file: {}", error);
+                self.status = StatusMessage::from(message);
+            }
+        }
+    }
 
-                Key::Esc => {
-                    self.cursor_position.x = self.cursor_position.x.saturating_sub(1);
-                    self.terminal.set_cursor_position(&self.cursor_position);
-                    self.change_mode(Mode::Normal);
-                }
+    /// The question shown in the message bar while a prompt waits for an answer.
+    fn prompt_message(&self) -> Option<String> {
+        let message = match self.prompt.as_ref()? {
+            Prompt::Recovery(swap) => {
+                let document = self.diff_view.as_ref().unwrap_or(&self.document);
+                let changed = if document.swap_matches(swap) {
+                    ""
+                } else {
+                    ", but the file changed since"
+                };
 
-                Key::Backspace => {
-                    // NOTE: `above` is called here because after `delete`, `above` will have been modified.
-                    let above = self.document.buffer(y - 1).unwrap().clone();
-                    let shift = self.document.delete(&self.cursor_position);
-                    if !shift {
-                        x -= 1;
-                    } else {
-                        x = above.len;
-                        y = y.saturating_sub(1);
-                    }
-                }
+                format!(
+                    "Found a swap file with {} unsaved changes{}. [r]ecover, [d]iscard, d[i]ff",
+                    swap.changes.len(),
+                    changed
+                )
+            }
+            Prompt::Filename { input, .. } => format!("{}{}", FILENAME_PROMPT, input),
+            Prompt::Changed => {
+                let document = self.diff_view.as_ref().unwrap_or(&self.document);
+                format!(
+                    "\"{}\" changed on disk. [r]eload, [k]eep mine, d[i]ff",
+                    document.filename
+                )
+            }
+            Prompt::Quit => format!(
+                "Save changes to \"{}\"? [y]es, [n]o, [c]ancel",
+                buffer::name(&self.document)
+            ),
+        };
 
-                Key::Enter => {
-                    // let width = self.terminal.size().width as usize; yay
-                    self.document.enter(&self.cursor_position);
-                    let ws_count = self.check_current_then_below_for_whitespace(y);
+        Some(message)
+    }
 
-                    x = ws_count;
-                    y += 1;
-                    if ws_count != 0 {
-                        if let Some(buffer) = self.document.buffer_mut(y) {
-                            for ws in 0..(ws_count as u8) {
-                                buffer.insert(' ', ws as usize);
-                            }
-                        }
+    /// Hands a key to the prompt that is waiting for an answer.
+    /// Every prompt puts itself back if the key didn't answer it.
+    fn answer_prompt(&mut self, key: Event) {
+        let event = match key {
+            Event::Key(event) => event,
+            _ => return,
+        };
+
+        match self.prompt.take() {
+            Some(Prompt::Recovery(swap)) => self.recovery_prompt(swap, event),
+            Some(Prompt::Filename { input, quit }) => self.filename_prompt(input, quit, event),
+            Some(Prompt::Quit) => self.quit_prompt(event),
+            Some(Prompt::Changed) => self.changed_prompt(event),
+            None => (),
+        }
+    }
+
+    /// Reads a filename in the message bar, for saving a document that doesn't have one yet.
+    fn filename_prompt(&mut self, mut input: String, quit: bool, event: KeyEvent) {
+        match event.code {
+            Key::Char(c) => input.push(c),
+            Key::Backspace => {
+                input.pop();
+            }
+            Key::Esc => {
+                self.status = StatusMessage::from("Not saved.");
+                return;
+            }
+            Key::Enter => {
+                let filename = input.trim().to_string();
+                if !filename.is_empty() {
+                    self.save_to(&filename, true, false);
+                    if quit && !self.document.is_modified() {
+                        self.quit();
                     }
                 }
+                return;
+            }
+            _ => (),
+        }
 
-                Key::Tab => {
-                    x += 4;
-                }
+        self.prompt = Some(Prompt::Filename { input, quit });
+    }
 
-                Key::Char(c) => {
-                    self.document.insert(c, &self.cursor_position);
-                    x += 1;
+    fn quit_prompt(&mut self, event: KeyEvent) {
+        match event.code {
+            Key::Char('y') => self.write_and_quit(),
+            Key::Char('n') => {
+                // The changes are thrown away along with the buffer.
+                self.delete_buffer(self.buffer_id);
+                if self.buffers.is_empty() && !self.document.is_modified() {
+                    self.should_quit = true;
+                } else {
+                    self.quit();
                 }
-                _ => (),
             }
+            Key::Char('c') | Key::Esc => (),
+            _ => self.prompt = Some(Prompt::Quit),
         }
+    }
 
-        self.cursor_position.x = x;
-        self.cursor_position.y = y;
+    /// Answers the question of what to do with a swap file left behind by a crash.
+    /// While the diff is shown it can be scrolled through with the usual motions.
+    fn recovery_prompt(&mut self, swap: Swap, event: KeyEvent) {
+        match event.code {
+            Key::Char('r') if event.modifiers.is_empty() => {
+                self.close_diff();
+                let recovered = self.document.recover(&swap, &self.cursor_position);
+                let message = if recovered == swap.changes.len() {
+                    format!(
+                        "Recovered {} changes, save the file to keep them.",
+                        recovered
+                    )
+                } else {
+                    format!(
+                        "Recovered {} of {} changes, the rest didn't fit the file.",
+                        recovered,
+                        swap.changes.len()
+                    )
+                };
+                self.status = StatusMessage::from(message);
+            }
+            Key::Char('d') => {
+                self.close_diff();
+                self.document.discard_swap();
+                self.status = StatusMessage::from("Swap file discarded.");
+            }
+            Key::Char('q') if event.modifiers.contains(Mod::CONTROL) => {
+                // Quitting leaves the swap file alone, it's found again next time.
+                self.close_diff();
+                self.should_quit = true;
+            }
+            Key::Char('i') if self.diff_view.is_none() => {
+                let old = self.document.text(0, self.document.len_chars());
+                let new = self.document.recovered_text(&swap);
+                self.show_diff(&old, &new);
+                self.prompt = Some(Prompt::Recovery(swap));
+            }
+            _ => {
+                self.prompt = Some(Prompt::Recovery(swap));
+                self.scroll_diff(event);
+            }
+        }
     }
 
-    fn check_current_then_below_for_whitespace(&self, y: usize) -> usize {
-        let mut ws_count = 0;
-        if let Some(buffer) = self.document.buffer(y) {
-            let contents = buffer.line();
-            for ch in contents.as_bytes() {
-                let ch = *ch as char;
-                if ws_count == 0 && !ch.is_whitespace() {
-                    ws_count = self.check_current_then_below_for_whitespace(y + 1);
-                    break;
-                } else if ch.is_whitespace() {
-                    ws_count += 1;
-                } else if ws_count > 0 && !ch.is_whitespace() {
-                    // This one is used to break after the first non-ws character is reached.
-                    break;
+    /// Answers the question of what to do after another program changed the file.
+    fn changed_prompt(&mut self, event: KeyEvent) {
+        match event.code {
+            Key::Char('r') if event.modifiers.is_empty() => {
+                self.close_diff();
+                self.reload();
+            }
+            Key::Char('k') if event.modifiers.is_empty() => {
+                self.close_diff();
+                self.document.keep_changes();
+                self.status =
+                    StatusMessage::from("Kept your version, saving it overwrites the file.");
+            }
+            Key::Char('i') if self.diff_view.is_none() => {
+                self.prompt = Some(Prompt::Changed);
+                let mine = self.document.text(0, self.document.len_chars());
+                match self.document.disk_text() {
+                    Ok(disk) => self.show_diff(&mine, &disk),
+                    Err(error) => {
+                        let message = format!("ERR: Could not read the file: {}", error);
+                        self.status = StatusMessage::from(message);
+                    }
                 }
             }
+            _ => {
+                self.prompt = Some(Prompt::Changed);
+                self.scroll_diff(event);
+            }
         }
-
-        ws_count
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("Hecto -- version {}\r", VERSION);
+    /// The usual motions move through a diff while it's shown.
+    fn scroll_diff(&mut self, event: KeyEvent) {
+        if self.diff_view.is_none() || !event.modifiers.is_empty() {
+            return;
+        }
 
-        let width = self.terminal.size().width as usize;
-        let len = welcome_message.len();
+        if let Key::Char('h' | 'j' | 'k' | 'l' | 'J' | 'K' | 'G' | 'g' | '0' | 's' | 'S') =
+            event.code
+        {
+            self.normal_mode(Event::Key(event));
+        }
+    }
 
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
+    /// Shows a diff in place of the document, until a prompt closes it again.
+    fn show_diff(&mut self, old: &str, new: &str) {
+        let lines = diff::diff(old, new);
 
-        welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
+        let view = Document {
+            rope: Rope::from_lines(lines.iter().map(|line| GapBuffer::new(line))),
+            filename: format!("[diff] {}", self.document.filename),
+            read_only: true,
+            ..Document::default()
+        };
+
+        self.diff_view = Some(mem::replace(&mut self.document, view));
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+    }
+
+    fn close_diff(&mut self) {
+        if let Some(document) = self.diff_view.take() {
+            self.document = document;
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+        }
+    }
+
+    fn change_mode(&mut self, change_to: Mode) {
+        if change_to == Mode::Insert && self.mode != Mode::Insert {
+            self.typed.clear();
+        }
+        // The selection starts at the cursor, and is kept for `gv` once it's gone.
+        if change_to.is_visual() && !self.mode.is_visual() {
+            self.anchor = self.cursor_position;
+        } else if !change_to.is_visual() && self.mode.is_visual() {
+            self.visual = self.selection();
+        }
+        self.mode = change_to;
+    }
+
+    /// Adds a key to the command being typed. Returns the command and what was typed in front
+    /// of it once all of its keys are in, until then the keys wait in `pending` for the rest.
+    fn read_command(
+        &mut self,
+        key: Event,
+        parse: fn(&[KeyEvent]) -> Parse,
+    ) -> Option<(Action, Prefix)> {
+        let event = match key {
+            Event::Key(event) => event,
+            _ => return None,
+        };
+
+        self.pending.push(event);
+        self.timers.cancel(Timer::PendingKeys);
+        match parse(&self.pending) {
+            Parse::Done(action, prefix) => {
+                self.pending.clear();
+                Some((action, prefix))
+            }
+            Parse::Pending => {
+                let timeout = Duration::from_millis(self.options.timeoutlen as u64);
+                self.timers
+                    .schedule(Timer::PendingKeys, Instant::now() + timeout);
+                None
+            }
+            Parse::Invalid => {
+                self.pending.clear();
+                None
+            }
+        }
+    }
+
+    fn normal_mode(&mut self, key: Event) {
+        let Position { mut x, mut y } = self.cursor_position;
+        let (action, prefix) = match self.read_command(key, operator::parse) {
+            Some(command) => command,
+            None => return,
+        };
+        let count = prefix.count;
+        // Most commands that aren't motions do the same thing `count` times.
+        let times = count.unwrap_or(1);
+
+        match action {
+            Action::Move(motion) => Position { x, y } = self.motion_target(motion, count),
+
+            Action::Operate(operator, target) => {
+                self.operate(operator, target, prefix);
+                Position { x, y } = self.cursor_position;
+            }
+
+            // Only visual mode selects text objects.
+            Action::Select(_) => (),
+
+            Action::Other(event, next) => match event.code {
+                Key::Char('w') if event.modifiers.contains(Mod::CONTROL) => {
+                    if let Some(next) = next {
+                        self.window_command(next, times);
+                    }
+                    return;
+                }
+
+                Key::Char('w') if event.modifiers.contains(Mod::ALT) => self.save(),
+
+                Key::Char('g') if event.modifiers.contains(Mod::CONTROL) => {
+                    let offset = self.document.offset(&self.cursor_position);
+                    let message = format!(
+                        "Line {} of {}; Char {} of {}",
+                        y.saturating_add(1),
+                        self.document.len(),
+                        offset.saturating_add(1),
+                        self.document.len_chars()
+                    );
+                    self.status = StatusMessage::from(message);
+                }
+
+                Key::Char('g') => {
+                    if let Some(next) = next {
+                        match next.code {
+                            // `3gt` goes to the third tab page, `3gT` goes back three.
+                            Key::Char('t') if count.is_some() => {
+                                self.switch_tab(times - 1);
+                                return;
+                            }
+                            Key::Char(c @ ('t' | 'T')) => {
+                                for _ in 0..times {
+                                    self.cycle_tab(c == 't');
+                                }
+                                return;
+                            }
+                            Key::Char('-') => {
+                                let target = self.document.history.current.saturating_sub(times);
+                                if let Some(pos) = self.document.goto_revision(target) {
+                                    Position { x, y } = pos;
+                                }
+                            }
+                            Key::Char('+') => {
+                                let target = self.document.history.current.saturating_add(times);
+                                if let Some(pos) = self.document.goto_revision(target) {
+                                    Position { x, y } = pos;
+                                }
+                            }
+                            Key::Char('v') => {
+                                self.reselect();
+                                Position { x, y } = self.cursor_position;
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+
+                Key::Char('u') => {
+                    for _ in 0..times {
+                        match self.document.undo() {
+                            Some(pos) => Position { x, y } = pos,
+                            None => {
+                                self.status = StatusMessage::from("Already at oldest change");
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                Key::Char('r') if event.modifiers.contains(Mod::CONTROL) => {
+                    for _ in 0..times {
+                        match self.document.redo() {
+                            Some(pos) => Position { x, y } = pos,
+                            None => {
+                                self.status = StatusMessage::from("Already at newest change");
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                Key::Char(c @ ('p' | 'P')) => {
+                    self.put(c == 'p', prefix);
+                    Position { x, y } = self.cursor_position;
+                }
+
+                // changing modes
+                Key::Char('i') => self.change_mode(Mode::Insert),
+
+                Key::Char(':') => self.change_mode(Mode::Command),
+
+                Key::Char('v') if event.modifiers.contains(Mod::CONTROL) => {
+                    self.change_mode(Mode::VisualBlock)
+                }
+                Key::Char('v') => self.change_mode(Mode::Visual),
+                Key::Char('V') => self.change_mode(Mode::VisualLine),
+
+                Key::Char('a') => {
+                    x = x.saturating_add(1);
+                    self.change_mode(Mode::Insert);
+                }
+
+                Key::Char('A') => {
+                    x = self.document.buffer(y).map_or(0, |b| b.len_graphemes());
+
+                    self.change_mode(Mode::Insert);
+                }
+
+                Key::Char('q') if event.modifiers.contains(Mod::CONTROL) => self.quit(),
+
+                // Terminals send Ctrl-^ as Ctrl-6. With a count it goes to that buffer.
+                Key::Char('6' | '^') if event.modifiers.contains(Mod::CONTROL) => {
+                    match count.or(self.alternate) {
+                        Some(id) if id == self.buffer_id => (),
+                        Some(id) if self.buffers.iter().any(|b| b.id == id) => self.switch_to(id),
+                        Some(id) => {
+                            let message = format!("ERR: Buffer {} does not exist", id);
+                            self.status = StatusMessage::from(message);
+                        }
+                        None => self.status = StatusMessage::from("ERR: No alternate file"),
+                    }
+                    return;
+                }
+
+                _ => (),
+            },
+        }
+
+        // if the cursor is further than the width
+        // the x pos of the cursor will be set to the width
+        // snapping it to the end of the line.
+        let width = self.document.buffer(y).map_or(0, |b| b.len_graphemes());
+        if x > width {
+            x = width
+        }
+
+        self.cursor_position = Position { x, y };
+        // Every normal mode command is undone on its own. A change goes on until insert mode
+        // is left, so the text typed for `cw` is undone along with the word.
+        if self.mode != Mode::Insert {
+            self.document.commit(&self.cursor_position);
+        }
+    }
+
+    fn visual_mode(&mut self, key: Event) {
+        let (action, prefix) = match self.read_command(key, operator::parse_visual) {
+            Some(command) => command,
+            None => return,
+        };
+
+        match action {
+            Action::Move(motion) => {
+                self.cursor_position = self.motion_target(motion, prefix.count);
+            }
+
+            Action::Select(object) => self.select_object(object, prefix.count),
+
+            Action::Operate(operator, target) => {
+                self.change_mode(Mode::Normal);
+                self.operate(operator, target, prefix);
+            }
+
+            Action::Other(event, next) => match event.code {
+                // The key of the mode that is on turns it off, the others switch to theirs.
+                Key::Char('v') if event.modifiers.contains(Mod::CONTROL) => {
+                    self.toggle_visual(Mode::VisualBlock)
+                }
+                Key::Char('v') => self.toggle_visual(Mode::Visual),
+                Key::Char('V') => self.toggle_visual(Mode::VisualLine),
+
+                Key::Char('o') => mem::swap(&mut self.anchor, &mut self.cursor_position),
+
+                // `gv` swaps the selection with the previous one.
+                Key::Char('g') if next.is_some_and(|next| next.code == Key::Char('v')) => {
+                    let current = self.selection();
+                    if self.visual.is_some() {
+                        self.reselect();
+                        self.visual = current;
+                    }
+                }
+
+                Key::Char('q') if event.modifiers.contains(Mod::CONTROL) => self.quit(),
+
+                _ => (),
+            },
+        }
+
+        if self.mode != Mode::Insert {
+            self.document.commit(&self.cursor_position);
+        }
+    }
+
+    fn toggle_visual(&mut self, mode: Mode) {
+        if self.mode == mode {
+            self.change_mode(Mode::Normal);
+        } else {
+            self.change_mode(mode);
+        }
+    }
+
+    /// What is selected right now, outside of visual mode nothing is.
+    fn selection(&self) -> Option<Visual> {
+        if !self.mode.is_visual() {
+            return None;
+        }
+
+        Some(Visual {
+            mode: self.mode,
+            anchor: self.anchor,
+            cursor: self.cursor_position,
+        })
+    }
+
+    /// Selects what was selected last again, `gv`. The text may have changed since, the ends
+    /// are kept inside of it.
+    fn reselect(&mut self) {
+        let visual = match self.visual {
+            Some(visual) => visual,
+            None => return,
+        };
+
+        let document = &self.document;
+        let clamp = |position: Position| {
+            let y = position.y.min(document.len().saturating_sub(1));
+            let len = document.buffer(y).map_or(0, |b| b.len_graphemes());
+            Position::new(position.x.min(len), y)
+        };
+        let (anchor, cursor) = (clamp(visual.anchor), clamp(visual.cursor));
+
+        self.change_mode(visual.mode);
+        self.anchor = anchor;
+        self.cursor_position = cursor;
+    }
+
+    /// Selects a text object, like `viw`. Objects of whole lines switch to selecting lines.
+    fn select_object(&mut self, object: TextObject, count: Option<usize>) {
+        let at = self.cursor_position;
+        let range = match motion::object_range(&self.document, object, at, count.unwrap_or(1)) {
+            Some(range) => range,
+            None => return,
+        };
+
+        if range.linewise {
+            self.change_mode(Mode::VisualLine);
+            self.anchor = Position::new(0, range.start.y);
+            self.cursor_position = Position::new(0, range.end.y);
+            return;
+        }
+
+        // The range ends after its last character, the selection on it.
+        let start = self.document.offset(&range.start);
+        let end = self.document.offset(&range.end);
+        self.anchor = range.start;
+        self.cursor_position = if end > start {
+            self.document.position(end - 1)
+        } else {
+            range.start
+        };
+    }
+
+    /// The columns of line `y` that are selected, from the first one up to the one after the
+    /// last. A selected newline takes up a column after the end of the line.
+    fn selected_columns(&self, y: usize) -> Option<(usize, usize)> {
+        let visual = self.selection()?;
+        let (start, end) = ordered(visual.anchor, visual.cursor);
+        if y < start.y || y > end.y {
+            return None;
+        }
+
+        let tabstop = self.options.tabstop;
+        let len = self.document.buffer(y).map_or(0, |b| b.len_graphemes());
+        let width = column_of(&self.document, Position::new(len, y), tabstop);
+        let columns = match visual.mode {
+            Mode::VisualLine => (0, width + 1),
+            Mode::VisualBlock => {
+                let (left, right) = self.block_columns(visual);
+                (left, right.min(width))
+            }
+            _ => {
+                let first = if y == start.y {
+                    column_of(&self.document, start, tabstop)
+                } else {
+                    0
+                };
+                let last = if y == end.y {
+                    self.column_edges(end).1
+                } else {
+                    width + 1
+                };
+                (first, last)
+            }
+        };
+
+        Some(columns)
+    }
+
+    /// The columns grapheme `at` takes up, from its left edge to its right edge. The end of a
+    /// line is one column wide.
+    fn column_edges(&self, at: Position) -> (usize, usize) {
+        let tabstop = self.options.tabstop;
+        let left = column_of(&self.document, at, tabstop);
+        let right = column_of(&self.document, Position::new(at.x + 1, at.y), tabstop);
+        (left, right.max(left + 1))
+    }
+
+    /// The columns a block covers, from the left edge of one end to the right edge of the
+    /// other.
+    fn block_columns(&self, visual: Visual) -> (usize, usize) {
+        let (anchor_left, anchor_right) = self.column_edges(visual.anchor);
+        let (cursor_left, cursor_right) = self.column_edges(visual.cursor);
+        (anchor_left.min(cursor_left), anchor_right.max(cursor_right))
+    }
+
+    /// The text a selection covers. Characters go up to and including the last one, and take
+    /// the newline along when the selection ends past the end of a line.
+    fn selection_range(&self, visual: Visual) -> Range {
+        let (start, end) = ordered(visual.anchor, visual.cursor);
+        if visual.mode == Mode::VisualLine {
+            return Range::lines(start.y, end.y);
+        }
+
+        let len = self.document.buffer(end.y).map_or(0, |b| b.len_graphemes());
+        let end = if end.x >= len && end.y + 1 < self.document.len() {
+            Position::new(0, end.y + 1)
+        } else {
+            Position::new((end.x + 1).min(len), end.y)
+        };
+
+        Range {
+            start,
+            end,
+            linewise: false,
+        }
+    }
+
+    /// The part of every line a block covers. Lines that end before the block are left out.
+    fn block_ranges(&self, visual: Visual) -> Vec<Range> {
+        let (left, right) = self.block_columns(visual);
+        let (start, end) = ordered(visual.anchor, visual.cursor);
+        let tabstop = self.options.tabstop;
+
+        (start.y..=end.y)
+            .filter_map(|y| {
+                let buffer = self.document.buffer(y)?;
+                let len = buffer.len_graphemes();
+                let first = buffer.grapheme_at_column(left, tabstop);
+                if first >= len {
+                    return None;
+                }
+
+                let last = buffer.grapheme_at_column(right, tabstop);
+                Some(Range {
+                    start: Position::new(first, y),
+                    end: Position::new(last.clamp(first + 1, len), y),
+                    linewise: false,
+                })
+            })
+            .collect()
+    }
+
+    /// Where a motion takes the cursor, with the count typed for it.
+    fn motion_target(&self, motion: Motion, count: Option<usize>) -> Position {
+        motion::target(
+            &self.document,
+            motion,
+            count,
+            self.cursor_position,
+            self.options.tabstop,
+            self.text_height(),
+        )
+    }
+
+    /// Draws the command window over the windows.
+    fn draw_command_window(&mut self) {
+        let (width, height) = self.terminal.screen_size();
+        let popup = command_window(width, height, &mut self.app);
+        self.terminal.overlay(&popup.buffer, &popup.areas);
+        self.terminal
+            .set_cursor_position(&Position::from(popup.cursor));
+        self.terminal.show_cursor();
+    }
+
+    fn command_mode(&mut self, key: Event) {
+        match run_command_mode(&mut self.app, key) {
+            Command::Instruction(mode, (key, modifier)) => {
+                let event = create_event(key, modifier);
+                if mode == Mode::Normal {
+                    self.normal_mode(event);
+                }
+            }
+            Command::Ex(command) => {
+                self.registers.command = command.clone();
+                self.execute_command(&command);
+                self.change_mode(Mode::Normal);
+            }
+            Command::None => (),
+        }
+    }
+
+    fn execute_command(&mut self, command: &str) {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("undolist"), _) => {
+                let history = &self.document.history;
+                let leaves: Vec<String> = history
+                    .leaves()
+                    .iter()
+                    .map(|id| {
+                        let revision = &history.revisions[*id];
+                        let seconds = revision.time.elapsed().unwrap_or_default().as_secs();
+                        format!(
+                            "#{} {} changes {}s ago",
+                            id,
+                            revision.changes.len(),
+                            seconds
+                        )
+                    })
+                    .collect();
+
+                let message = if leaves.is_empty() {
+                    "Nothing to undo".to_string()
+                } else {
+                    leaves.join(", ")
+                };
+                self.status = StatusMessage::from(message);
+            }
+            (Some("undo"), Some(target)) => match target.parse() {
+                Ok(target) => {
+                    if let Some(pos) = self.document.goto_revision(target) {
+                        self.cursor_position = pos;
+                    }
+                }
+                Err(_) => self.status = StatusMessage::from("ERR: Invalid revision number"),
+            },
+            (Some("set"), Some(_)) => {
+                for argument in command.split_whitespace().skip(1) {
+                    let result = self
+                        .document
+                        .set_option(argument)
+                        .or_else(|| self.clipboard.set_option(argument))
+                        .unwrap_or_else(|| self.options.set(argument));
+
+                    match result {
+                        Ok(Some(value)) => self.status = StatusMessage::from(value),
+                        Ok(None) => (),
+                        Err(error) => {
+                            self.status = StatusMessage::from(format!("ERR: {}", error));
+                            break;
+                        }
+                    }
+                }
+            }
+            (Some("write"), None) => self.save(),
+            (Some(name @ ("write" | "write!" | "saveas" | "saveas!")), Some(_)) => {
+                // Everything after the command is the filename, spaces included.
+                let filename = command.split_once(' ').map_or("", |(_, rest)| rest.trim());
+                self.save_to(filename, name.starts_with("saveas"), name.ends_with('!'));
+            }
+            (Some("edit!"), None) => self.reload(),
+            (Some("edit"), None) => {
+                if self.document.is_modified() {
+                    let message = "ERR: No write since last change, add ! to discard it";
+                    self.status = StatusMessage::from(message);
+                } else {
+                    self.reload();
+                }
+            }
+            (Some("saveas"), None) => {
+                self.prompt = Some(Prompt::Filename {
+                    input: String::new(),
+                    quit: false,
+                })
+            }
+            // With more than one window, quitting only closes the window.
+            (Some("quit" | "quit!" | "close"), _) if !self.is_last_window() => self.close_window(),
+            (Some("close"), _) => self.close_window(),
+            (Some("quit" | "qall"), _) => self.quit(),
+            (Some("quit!" | "qall!"), _) => self.should_quit = true,
+            (Some("wq"), _) if !self.is_last_window() => {
+                self.save();
+                if !self.document.is_modified() {
+                    self.close_window();
+                }
+            }
+            (Some("wq"), _) => self.write_and_quit(),
+            (Some(name @ ("split" | "vsplit")), argument) => {
+                let split = if name == "split" {
+                    Split::Horizontal
+                } else {
+                    Split::Vertical
+                };
+                if self.split(split) && argument.is_some() {
+                    let filename = command.split_once(' ').map_or("", |(_, rest)| rest.trim());
+                    self.edit(filename);
+                }
+            }
+            (Some("new"), _) if self.split(Split::Horizontal) => {
+                self.add_buffer(Document::default())
+            }
+            (Some("vnew"), _) if self.split(Split::Vertical) => {
+                self.add_buffer(Document::default())
+            }
+            (Some("only"), _) => self.only_window(),
+            (Some("tabnew"), argument) => {
+                self.new_tab();
+                if argument.is_some() {
+                    let filename = command.split_once(' ').map_or("", |(_, rest)| rest.trim());
+                    self.edit(filename);
+                } else {
+                    self.add_buffer(Document::default());
+                }
+            }
+            (Some("tabclose"), _) => self.close_tab(),
+            (Some("tabnext"), None) => self.cycle_tab(true),
+            (Some("tabnext"), Some(number)) => match number.parse::<usize>() {
+                Ok(number) if (1..=self.tabs.len() + 1).contains(&number) => {
+                    self.switch_tab(number - 1)
+                }
+                _ => {
+                    let message = format!("ERR: Tab page {} does not exist", number);
+                    self.status = StatusMessage::from(message);
+                }
+            },
+            (Some("tabprevious"), _) => self.cycle_tab(false),
+            (Some("wqall"), _) => self.write_all_and_quit(),
+            (Some("edit" | "edit!"), Some(_)) => {
+                let filename = command.split_once(' ').map_or("", |(_, rest)| rest.trim());
+                self.edit(filename);
+            }
+            (Some("bnext"), _) => self.cycle_buffer(true),
+            (Some("bprevious"), _) => self.cycle_buffer(false),
+            (Some("buffer"), Some(_)) => {
+                let name = command.split_once(' ').map_or("", |(_, rest)| rest.trim());
+                match self.find_buffer(name) {
+                    Ok(id) => self.switch_to(id),
+                    Err(error) => self.status = StatusMessage::from(format!("ERR: {}", error)),
+                }
+            }
+            (Some("buffers"), _) => self.list_buffers(),
+            (Some("registers"), names) => self.list_registers(names.unwrap_or_default()),
+            (Some(name @ ("bdelete" | "bdelete!")), argument) => {
+                let id = match argument.map(|name| self.find_buffer(name)) {
+                    Some(Ok(id)) => id,
+                    Some(Err(error)) => {
+                        self.status = StatusMessage::from(format!("ERR: {}", error));
+                        return;
+                    }
+                    None => self.buffer_id,
+                };
+
+                let modified = if id == self.buffer_id {
+                    self.document.is_modified()
+                } else {
+                    self.buffers
+                        .iter()
+                        .any(|b| b.id == id && b.document.is_modified())
+                };
+
+                if modified && !name.ends_with('!') {
+                    let message = format!(
+                        "ERR: Buffer {} has unsaved changes, add ! to delete it anyway",
+                        id
+                    );
+                    self.status = StatusMessage::from(message);
+                } else {
+                    self.delete_buffer(id);
+                }
+            }
+            (Some("undo"), None) => {
+                if let Some(pos) = self.document.undo() {
+                    self.cursor_position = pos;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn insert_mode(&mut self, key: Event) {
+        let Position { mut x, mut y } = &self.cursor_position;
+        if let Event::Key(event) = key {
+            match event.code {
+                Key::Left => Position { x, y } = self.motion_target(Motion::Left, None),
+                Key::Right => Position { x, y } = self.motion_target(Motion::Right, None),
+                Key::Up => Position { x, y } = self.motion_target(Motion::Up, None),
+                Key::Down => Position { x, y } = self.motion_target(Motion::Down, None),
+
+                Key::Esc => {
+                    self.registers.inserted = mem::take(&mut self.typed);
+                    if let Some((column, lines)) = self.block_change.take() {
+                        self.change_block_lines(column, &lines);
+                    }
+                    self.document.commit(&self.cursor_position);
+                    self.cursor_position.x = self.cursor_position.x.saturating_sub(1);
+                    self.terminal.set_cursor_position(&self.cursor_position);
+                    self.change_mode(Mode::Normal);
+                }
+
+                Key::Backspace => {
+                    for _ in 0..self.backspace_count() {
+                        let at = Position::new(x, y);
+                        if x > 0 {
+                            x -= 1;
+                        } else if y > 0 {
+                            // Lands at the end of the line above, where the two lines get joined.
+                            y -= 1;
+                            x = self.document.buffer(y).map_or(0, |b| b.len_graphemes());
+                        }
+                        self.document.delete(&at);
+                        self.typed.pop();
+                    }
+                }
+
+                Key::Enter => {
+                    // let width = self.terminal.size().width as usize; yay
+                    self.document.enter(&self.cursor_position);
+                    let ws = self.check_current_then_below_for_whitespace(y);
+                    self.typed.push('\n');
+
+                    x = 0;
+                    y += 1;
+                    for ch in ws.chars() {
+                        self.document.insert(ch, &Position::new(x, y));
+                        x += 1;
+                    }
+                }
+
+                Key::Tab => {
+                    // Fills up to the next soft tab stop, with tabs where they fit unless `expandtab` is on.
+                    let column = self.cursor_column();
+                    let softtabstop = self.options.softtabstop();
+                    let next_stop = column + softtabstop - column % softtabstop;
+                    for ch in self.options.whitespace(column, next_stop).chars() {
+                        self.document.insert(ch, &Position::new(x, y));
+                        self.typed.push(ch);
+                        x += 1;
+                    }
+                }
+
+                Key::Char(c) => {
+                    // A combining character merges with the grapheme in front of it,
+                    // so the cursor doesn't always move.
+                    let offset = self.document.offset(&self.cursor_position);
+                    self.document.insert(c, &self.cursor_position);
+                    self.typed.push(c);
+                    Position { x, y } = self.document.position(offset + 1);
+                }
+                _ => (),
+            }
+        }
+
+        self.cursor_position.x = x;
+        self.cursor_position.y = y;
+    }
+
+    /// The indentation for a new line: the whitespace line `y` starts with, or when it starts
+    /// with text, that of the first line below it that doesn't.
+    fn check_current_then_below_for_whitespace(&self, y: usize) -> String {
+        for buffer in (y..).map_while(|y| self.document.buffer(y)) {
+            if buffer.chars().next().is_some_and(|ch| !ch.is_whitespace()) {
+                continue;
+            }
+            // Tabs are copied as they are, so the indentation keeps its style.
+            return buffer.chars().take_while(|ch| ch.is_whitespace()).collect();
+        }
+
+        String::new()
+    }
+
+    /// Indents or unindents a line by `shiftwidth`, returns where its text starts afterwards.
+    fn shift_line(&mut self, y: usize, right: bool) -> usize {
+        let width = self.indent_width(y);
+        let shiftwidth = self.options.shiftwidth();
+        let new_width = if right {
+            width + shiftwidth
+        } else {
+            width.saturating_sub(shiftwidth)
+        };
+
+        self.set_indent(y, new_width)
+    }
+
+    /// How many columns the indentation of a line takes up.
+    fn indent_width(&self, y: usize) -> usize {
+        self.document.buffer(y).map_or(0, |buffer| {
+            let indent = buffer
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            buffer.column(indent, self.options.tabstop)
+        })
+    }
+
+    /// Replaces the indentation of a line with `width` columns of it, returns where its text
+    /// starts afterwards.
+    fn set_indent(&mut self, y: usize, width: usize) -> usize {
+        let buffer = match self.document.buffer(y) {
+            Some(buffer) => buffer,
+            None => return 0,
+        };
+
+        // Like vim, empty lines are left alone.
+        if buffer.is_empty() {
+            return 0;
+        }
+
+        let indent = buffer
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        let whitespace = self.options.whitespace(0, width);
+        let start = self.document.offset(&Position::new(0, y));
+        self.document
+            .remove(start, start + indent, &self.cursor_position);
+        self.document.insert_text(&whitespace, &Position::new(0, y));
+
+        whitespace.chars().count()
+    }
+
+    /// How many characters Backspace removes. Spaces that line up with `softtabstop`
+    /// are removed together, as if they were a tab.
+    fn backspace_count(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        let column = self.cursor_column();
+        let softtabstop = self.options.softtabstop();
+        if x == 0 || column == 0 {
+            return 1;
+        }
+
+        let stop = (column - 1) / softtabstop * softtabstop;
+        let spaces = match self.document.buffer(y) {
+            Some(buffer) => buffer
+                .chars()
+                .take(buffer.char_index(x))
+                .collect::<Vec<char>>()
+                .iter()
+                .rev()
+                .take_while(|c| **c == ' ')
+                .take(column - stop)
+                .count(),
+            None => 0,
+        };
+
+        if spaces == column - stop {
+            spaces
+        } else {
+            1
+        }
+    }
+
+    fn welcome_message(width: usize) -> String {
+        let mut welcome_message = format!("Hecto -- version {}", VERSION);
+        let len = welcome_message.len();
+
+        let padding = width.saturating_sub(len) / 2;
+        let spaces = " ".repeat(padding.saturating_sub(1));
+
+        welcome_message = format!("~{}{}", spaces, welcome_message);
+        width::truncate(&welcome_message, width).to_string()
+    }
+
+    /// The text of a window, one string per line of the window, not counting the status line.
+    fn window_rows(&self, document: &Document, offset: Position, rect: Rect) -> Vec<String> {
+        let width = rect.width as usize;
+        let height = rect.height.saturating_sub(1) as usize;
+
+        (0..height)
+            .map(|row| {
+                // NOTE: index = row + offset.y
+                let line = if let Some(buffer) = document.buffer(row + offset.y) {
+                    let end = offset.x.saturating_add(width);
+                    buffer.render(offset.x, end, self.options.tabstop)
+                } else if document.is_empty() && row == height / 3 {
+                    Self::welcome_message(width)
+                } else {
+                    "~".to_string()
+                };
+
+                // Pads the line so it covers whatever was drawn there before.
+                let padding = width.saturating_sub(line.width());
+                format!("{}{}", line, " ".repeat(padding))
+            })
+            .collect()
+    }
+
+    /// The terminal column the cursor is drawn at, before scrolling.
+    fn cursor_column(&self) -> usize {
+        column_of(&self.document, self.cursor_position, self.options.tabstop)
+    }
+
+    fn scroll(&mut self) {
+        let Position { y, .. } = self.cursor_position;
+        // `offset.x` is counted in terminal columns, `offset.y` in lines.
+        let x = self.cursor_column();
+        let width = self.window_rect().width as usize;
+        let height = self.text_height();
+
+        follow(&mut self.offset, x, y, width, height);
+    }
+
+    fn status_line(
+        &self,
+        document: &Document,
+        cursor: Position,
+        active: bool,
+        width: usize,
+    ) -> String {
+        let mut flags = String::new();
+        if !document.final_newline {
+            flags.push_str(" [noeol]");
+        }
+        if document.read_only {
+            flags.push_str(" [RO]");
+        }
+        if document.new_file {
+            flags.push_str(" [New]");
+        }
+        if document.is_modified() {
+            flags.push_str(" [+]");
+        }
+
+        let rows = document.len() as f32;
+        let current_line = (cursor.y + 1) as f32;
+
+        let percentage = if rows > 0.0 {
+            (current_line / rows * 100.0).trunc()
+        } else {
+            0 as f32
+        };
+
+        let line_number = format!("{}/{}: {}%", current_line, rows, percentage);
+        let room = width.saturating_sub(line_number.len() + 1);
+
+        // Narrow windows leave out the parts that matter least, instead of cutting one of them
+        // in half. Only the window with the cursor shows the mode.
+        let name = buffer::name(document);
+        let mode = if active {
+            self.mode.to_string()
+        } else {
+            String::new()
+        };
+        let format = format!(
+            "{} | {}",
+            document.encoding.name().to_lowercase(),
+            document.line_ending
+        );
+        let choices = [vec![&mode, name, &format], vec![&mode, name], vec![name]];
+        let status = choices
+            .iter()
+            .map(|parts| {
+                let parts: Vec<&str> = parts
+                    .iter()
+                    .filter(|part| !part.is_empty())
+                    .copied()
+                    .collect();
+                parts.join(" | ") + &flags
+            })
+            .find(|status| status.width() <= room)
+            .unwrap_or_else(|| {
+                // The end of the path is what tells files apart.
+                let room = room.saturating_sub(flags.width() + 1);
+                let mut tail: Vec<&str> = name
+                    .graphemes(true)
+                    .rev()
+                    .scan(0, |used, grapheme| {
+                        *used += grapheme.width();
+                        (*used <= room).then_some(grapheme)
+                    })
+                    .collect();
+                tail.reverse();
+                format!("<{}{}", tail.concat(), flags)
+            });
+        let left_half = width.saturating_sub(status.width());
+        let right_half = left_half.saturating_sub(line_number.len());
+        let spaces = " ".repeat(right_half);
+
+        format!("{}{}{}", status, spaces, line_number)
+            .chars()
+            .take(width)
+            .collect()
+    }
+
+    fn draw_message_bar(&mut self) {
+        self.terminal.clear_current_line();
+        // A prompt stays until it's answered.
+        let columns = self.terminal.size().width as usize;
+        if let Some(message) = self.prompt_message() {
+            self.terminal.print(width::truncate(&message, columns));
+            return;
+        }
+
+        let message = &self.status;
+        if message.time.elapsed() < STATUS_DURATION {
+            self.terminal.print(width::truncate(&message.text, columns));
+        }
+    }
+
+    /// Draws every window along with its status line, and the separators between windows
+    /// that are side by side.
+    fn draw_windows(&mut self) {
+        let area = self.area();
+        let rects = self.layout.rects(area);
+        for &(id, rect) in &rects {
+            self.scroll_window(id, rect, area);
+
+            let (document, cursor, offset) = self.view(id);
+            let rows = self.window_rows(document, offset, rect);
+            let status =
+                self.status_line(document, cursor, id == self.window_id, rect.width as usize);
+
+            for (row, line) in rows.iter().enumerate() {
+                let position = Position::new(rect.x as usize, rect.y as usize + row);
+                self.terminal.set_cursor_position(&position);
+                self.terminal.print(line);
+            }
+            if id == self.window_id {
+                self.draw_selection(rect, offset);
+            }
+
+            let bottom = rect.y.saturating_add(rect.height).saturating_sub(1);
+            self.terminal
+                .set_cursor_position(&Position::new(rect.x as usize, bottom as usize));
+            if id == self.window_id {
+                self.terminal.set_bg_color(STATUS_BAR_BG_COLOUR);
+            } else {
+                self.terminal.set_bg_color(INACTIVE_STATUS_BAR_BG_COLOUR);
+            }
+            self.terminal.set_fg_color(STATUS_FG_COLOUR);
+            self.terminal.print(&status);
+
+            let right = rect.x.saturating_add(rect.width);
+            if right < area.width {
+                self.terminal.set_bg_color(INACTIVE_STATUS_BAR_BG_COLOUR);
+                for row in rect.y..rect.y.saturating_add(rect.height) {
+                    self.terminal
+                        .set_cursor_position(&Position::new(right as usize, row as usize));
+                    self.terminal.print("|");
+                }
+            }
+
+            self.terminal.reset_fg_color();
+            self.terminal.reset_bg_color();
+        }
+
+        let drawn = rects
+            .into_iter()
+            .map(|(id, rect)| (id, rect, self.view(id).2));
+        self.drawn = drawn.collect();
+    }
+
+    /// Draws the selection of visual mode over the text of the window with the cursor.
+    fn draw_selection(&mut self, rect: Rect, offset: Position) {
+        let width = rect.width as usize;
+        let height = rect.height.saturating_sub(1) as usize;
+        for row in 0..height {
+            let y = row + offset.y;
+            let (start, end) = match self.selected_columns(y) {
+                Some(columns) => columns,
+                None => continue,
+            };
+            let start = start.max(offset.x);
+            let end = end.min(offset.x.saturating_add(width));
+            if start >= end {
+                continue;
+            }
+
+            let text = self.document.buffer(y).map_or_else(String::new, |buffer| {
+                buffer.render(start, end, self.options.tabstop)
+            });
+            // A selected newline is drawn as a space.
+            let padding = (end - start).saturating_sub(text.width());
+            let position = Position::new(rect.x as usize + start - offset.x, rect.y as usize + row);
+            self.terminal.set_cursor_position(&position);
+            self.terminal.set_bg_color(SELECTION_BG_COLOUR);
+            self.terminal
+                .print(&format!("{}{}", text, " ".repeat(padding)));
+            self.terminal.reset_bg_color();
+        }
+    }
+
+    /// Lets the terminal scroll the lines of a window that were on the screen already, when it
+    /// shows the same lines further up or down than last time. Terminals can only scroll whole
+    /// rows, so this only works for windows as wide as the screen.
+    fn scroll_window(&mut self, id: usize, rect: Rect, area: Rect) {
+        if rect.x != area.x || rect.width != area.width {
+            return;
+        }
+
+        let offset = self.view(id).2;
+        let before = self
+            .drawn
+            .iter()
+            .find(|(window, drawn, _)| *window == id && *drawn == rect);
+        if let Some(&(_, _, before)) = before {
+            if before.x == offset.x {
+                let lines = offset.y as i64 - before.y as i64;
+                let lines = lines.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+                // The status line stays where it is.
+                let bottom = rect.y + rect.height.saturating_sub(1);
+                self.terminal.scroll(rect.y, bottom, lines);
+            }
+        }
+    }
+}
+
+// Functions related to operators
+impl Editor {
+    /// Runs an operator on the text a motion or text object covers.
+    fn operate(&mut self, operator: Operator, target: Target, prefix: Prefix) {
+        let name = prefix.register;
+        if let Some(name) = name.filter(|name| register::is_read_only(*name)) {
+            let message = format!("ERR: Register {} can't be written to", name);
+            self.status = StatusMessage::from(message);
+            return;
+        }
+
+        if target == Target::Selection {
+            if let Some(visual) = self.visual.filter(|v| v.mode == Mode::VisualBlock) {
+                self.operate_block(operator, visual, name);
+                return;
+            }
+        }
+
+        let range = match self.operator_range(operator, target, prefix.count) {
+            Some(range) => range,
+            None => return,
+        };
+
+        match operator {
+            Operator::Delete => self.delete_range(range, name),
+            Operator::Change => self.change_range(range, name),
+            Operator::Yank => self.yank_range(range, name),
+            Operator::Indent | Operator::Unindent | Operator::Reindent => {
+                self.indent_range(operator, range)
+            }
+            Operator::Lowercase | Operator::Uppercase => {
+                self.change_case(range, operator == Operator::Uppercase)
+            }
+        }
+    }
+
+    /// The text an operator acts on, `None` if the motion can't go anywhere.
+    fn operator_range(
+        &self,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> Option<Range> {
+        let from = self.cursor_position;
+        let times = count.unwrap_or(1);
+        let motion = match target {
+            Target::Lines => {
+                let last = from.y.saturating_add(times - 1);
+                let last = last.min(self.document.len().saturating_sub(1));
+                return Some(Range::lines(from.y, last));
+            }
+            Target::Object(object) => {
+                return motion::object_range(&self.document, object, from, times)
+            }
+            Target::Selection => return self.visual.map(|visual| self.selection_range(visual)),
+            Target::Motion(motion) => motion,
+        };
+
+        let len = self
+            .document
+            .buffer(from.y)
+            .map_or(0, |b| b.len_graphemes());
+        let (to, kind) = match motion {
+            // Under an operator, `h` and `l` stay on the line.
+            Motion::Left if from.x == 0 => return None,
+            Motion::Left => (
+                Position::new(from.x.saturating_sub(times), from.y),
+                Kind::Exclusive,
+            ),
+            Motion::Right if from.x >= len => return None,
+            Motion::Right => {
+                let x = from.x.saturating_add(times).min(len);
+                (Position::new(x, from.y), Kind::Exclusive)
+            }
+            // Like vim, `cw` on a word only changes the word, as if it was `ce`.
+            Motion::WordForward
+                if operator == Operator::Change && self.on_word(from) && from.x < len =>
+            {
+                let end = motion::word_end(&self.document, from);
+                let end = motion::target(
+                    &self.document,
+                    Motion::WordEnd,
+                    Some(times - 1),
+                    end,
+                    self.options.tabstop,
+                    self.text_height(),
+                );
+                (end, Kind::Inclusive)
+            }
+            Motion::WordForward => {
+                let to = motion::next_word(&self.document, from, times);
+                (to, Kind::Exclusive)
+            }
+            _ => (self.motion_target(motion, count), motion.kind()),
+        };
+
+        if kind == Kind::Linewise {
+            if matches!(motion, Motion::Up | Motion::Down) && to.y == from.y {
+                return None;
+            }
+            return Some(Range::lines(from.y.min(to.y), from.y.max(to.y)));
+        }
+
+        let (start, mut end) = ordered(from, to);
+        if kind == Kind::Inclusive {
+            let len = self.document.buffer(end.y).map_or(0, |b| b.len_graphemes());
+            end.x = (end.x + 1).min(len);
+        } else if end.x == 0 && end.y > start.y {
+            // Like vim, a motion that ends at the start of a line leaves the line alone.
+            end.y -= 1;
+            end.x = self.document.buffer(end.y).map_or(0, |b| b.len_graphemes());
+        }
+
+        Some(Range {
+            start,
+            end,
+            linewise: false,
+        })
+    }
+
+    /// Operators on a block act on the part of every line in it. A change is typed into the
+    /// first line, and goes to the others when insert mode is left.
+    fn operate_block(&mut self, operator: Operator, visual: Visual, name: Option<char>) {
+        let ranges = self.block_ranges(visual);
+        let (start, end) = ordered(visual.anchor, visual.cursor);
+        let (left, _) = self.block_columns(visual);
+
+        // Lines that end before the block still get a line in it, an empty one.
+        let text: Vec<String> = (start.y..=end.y)
+            .map(|y| {
+                let range = ranges.iter().find(|range| range.start.y == y);
+                range.map_or_else(String::new, |range| self.range_text(*range))
+            })
+            .collect();
+        let register = Register::block(&text);
+        match operator {
+            Operator::Yank => {
+                self.registers.yank(name, register);
+                self.copy_to_clipboard(name);
+            }
+            Operator::Delete | Operator::Change => {
+                self.registers.delete(name, register);
+                self.copy_to_clipboard(name);
+                for range in ranges.iter().rev() {
+                    let (start, end) = self.range_offsets(*range);
+                    self.document.remove(start, end, &self.cursor_position);
+                }
+
+                if operator == Operator::Change {
+                    let lines = ranges.iter().map(|range| range.start.y);
+                    let lines = lines.filter(|y| *y != start.y).collect();
+                    self.change_mode(Mode::Insert);
+                    self.block_change = Some((left, lines));
+                }
+            }
+            Operator::Indent | Operator::Unindent | Operator::Reindent => {
+                self.indent_range(operator, Range::lines(start.y, end.y));
+                return;
+            }
+            Operator::Lowercase | Operator::Uppercase => {
+                for range in &ranges {
+                    self.change_case(*range, operator == Operator::Uppercase);
+                }
+            }
+        }
+
+        let x = self
+            .document
+            .buffer(start.y)
+            .map_or(0, |b| b.grapheme_at_column(left, self.options.tabstop));
+        self.cursor_position = Position::new(x, start.y);
+    }
+
+    /// Types the text that was typed into the first line of a block change into the other
+    /// lines too. Like in vim, text that goes across lines stays on the first one.
+    fn change_block_lines(&mut self, column: usize, lines: &[usize]) {
+        let text = self.registers.inserted.clone();
+        if text.is_empty() || text.contains('\n') {
+            return;
+        }
+
+        for &y in lines {
+            let x = self
+                .document
+                .buffer(y)
+                .map_or(0, |b| b.grapheme_at_column(column, self.options.tabstop));
+            self.document.insert_text(&text, &Position::new(x, y));
+        }
+    }
+
+    fn on_word(&self, at: Position) -> bool {
+        let line = self.document.buffer(at.y).map(|buffer| buffer.line());
+        let line = line.unwrap_or_default();
+        line.graphemes(true)
+            .nth(at.x)
+            .is_some_and(|grapheme| !grapheme.trim().is_empty())
+    }
+
+    /// The character offsets a range starts and ends at. Whole lines end after their newline.
+    fn range_offsets(&self, range: Range) -> (usize, usize) {
+        if !range.linewise {
+            let start = self.document.offset(&range.start);
+            return (start, self.document.offset(&range.end));
+        }
+
+        let (first, last) = (range.start.y, range.end.y);
+        if last + 1 < self.document.len() {
+            let start = self.document.offset(&Position::new(0, first));
+            (start, self.document.offset(&Position::new(0, last + 1)))
+        } else if first > 0 {
+            // The last line doesn't have a newline, the one in front of it goes instead.
+            let above = self
+                .document
+                .buffer(first - 1)
+                .map_or(0, |b| b.len_graphemes());
+            let start = self.document.offset(&Position::new(above, first - 1));
+            (start, self.document.len_chars())
+        } else {
+            (0, self.document.len_chars())
+        }
+    }
+
+    /// The text of a range, whole lines end with a newline.
+    fn range_text(&self, range: Range) -> String {
+        if !range.linewise {
+            let (start, end) = self.range_offsets(range);
+            return self.document.text(start, end);
+        }
+
+        let start = self.document.offset(&Position::new(0, range.start.y));
+        let len = self
+            .document
+            .buffer(range.end.y)
+            .map_or(0, |b| b.len_graphemes());
+        let end = self.document.offset(&Position::new(len, range.end.y));
+        format!("{}\n", self.document.text(start, end))
+    }
+
+    fn yank_range(&mut self, range: Range, name: Option<char>) {
+        let text = self.range_text(range);
+        self.registers
+            .yank(name, Register::new(text, range.linewise));
+        self.copy_to_clipboard(name);
+        if range.linewise {
+            let lines = range.end.y - range.start.y + 1;
+            self.report(lines, format!("{} lines yanked", lines));
+            self.cursor_position.y = self.cursor_position.y.min(range.start.y);
+        } else {
+            self.cursor_position = range.start;
+        }
+    }
+
+    fn delete_range(&mut self, range: Range, name: Option<char>) {
+        let text = self.range_text(range);
+        self.registers
+            .delete(name, Register::new(text, range.linewise));
+        self.copy_to_clipboard(name);
+        let (start, end) = self.range_offsets(range);
+        self.document.remove(start, end, &self.cursor_position);
+
+        if range.linewise {
+            let lines = range.end.y - range.start.y + 1;
+            self.report(lines, format!("{} fewer lines", lines));
+            let y = range.start.y.min(self.document.len().saturating_sub(1));
+            let x = motion::first_non_blank(&self.document, y);
+            self.cursor_position = Position { x, y };
+        } else {
+            self.cursor_position = range.start;
+        }
+    }
+
+    fn change_range(&mut self, range: Range, name: Option<char>) {
+        let text = self.range_text(range);
+        self.registers
+            .delete(name, Register::new(text, range.linewise));
+        self.copy_to_clipboard(name);
+        if range.linewise {
+            // The lines are replaced by an empty one that keeps the indentation of the first.
+            let x = motion::first_non_blank(&self.document, range.start.y);
+            let len = self
+                .document
+                .buffer(range.end.y)
+                .map_or(0, |b| b.len_graphemes());
+            let start = self.document.offset(&Position::new(x, range.start.y));
+            let end = self.document.offset(&Position::new(len, range.end.y));
+            self.document.remove(start, end, &self.cursor_position);
+            self.cursor_position = Position::new(x, range.start.y);
+        } else {
+            let (start, end) = self.range_offsets(range);
+            self.document.remove(start, end, &self.cursor_position);
+            self.cursor_position = range.start;
+        }
+
+        self.change_mode(Mode::Insert);
+    }
+
+    fn indent_range(&mut self, operator: Operator, range: Range) {
+        let (first, last) = (range.start.y, range.end.y);
+        for y in first..=last {
+            match operator {
+                Operator::Indent => self.shift_line(y, true),
+                Operator::Unindent => self.shift_line(y, false),
+                // Like vim without an indent program, a line gets the indentation of the first
+                // line with text above it.
+                _ => {
+                    let above = (0..y).rev().find(|above| {
+                        let buffer = self.document.buffer(*above);
+                        buffer.is_some_and(|buffer| !buffer.line().trim().is_empty())
+                    });
+                    let width = above.map_or(0, |above| self.indent_width(above));
+                    self.set_indent(y, width)
+                }
+            };
+        }
+
+        let lines = last - first + 1;
+        let message = match operator {
+            Operator::Indent => format!("{} lines >ed 1 time", lines),
+            Operator::Unindent => format!("{} lines <ed 1 time", lines),
+            _ => format!("{} lines indented", lines),
+        };
+        self.report(lines, message);
+        let x = motion::first_non_blank(&self.document, first);
+        self.cursor_position = Position::new(x, first);
+    }
+
+    fn change_case(&mut self, range: Range, upper: bool) {
+        let (start, end) = if range.linewise {
+            let len = self
+                .document
+                .buffer(range.end.y)
+                .map_or(0, |b| b.len_graphemes());
+            let start = self.document.offset(&Position::new(0, range.start.y));
+            (
+                start,
+                self.document.offset(&Position::new(len, range.end.y)),
+            )
+        } else {
+            self.range_offsets(range)
+        };
+
+        let text = self.document.text(start, end);
+        let changed = if upper {
+            text.to_uppercase()
+        } else {
+            text.to_lowercase()
+        };
+        if changed != text {
+            let at = self.document.position(start);
+            self.document.remove(start, end, &self.cursor_position);
+            self.document.insert_text(&changed, &at);
+        }
+
+        if range.linewise {
+            let lines = range.end.y - range.start.y + 1;
+            self.report(lines, format!("{} lines changed", lines));
+            self.cursor_position.y = range.start.y;
+        } else {
+            self.cursor_position = range.start;
+        }
+    }
+
+    /// A register's text, along with the ones the editor keeps itself. `+` and `*` are read
+    /// from the system clipboard.
+    fn register(&mut self, name: char) -> Result<Register, String> {
+        let register = match (name, Selection::from_register(name)) {
+            ('%', _) => Some(Register::new(self.document.filename.clone(), false)),
+            (_, Some(selection)) => {
+                // What was copied last is used when the clipboard can't be read, and tells
+                // whether the text on it is still whole lines. Text copied by other programs
+                // is lines if it ends with a newline.
+                let copied = self.registers.get(name);
+                match self.clipboard.paste(selection) {
+                    Ok(Some(text)) => match copied {
+                        Some(copied) if copied.text == text => Some(copied),
+                        _ => {
+                            let linewise = text.ends_with('\n');
+                            Some(Register::new(text, linewise))
+                        }
+                    },
+                    Ok(None) => copied,
+                    Err(error) => return Err(format!("Clipboard: {}", error)),
+                }
+            }
+            _ => self.registers.get(name),
+        };
+
+        register
+            .filter(|register| !register.text.is_empty())
+            .ok_or_else(|| format!("Nothing in register {}", name))
+    }
+
+    /// Sends what was just yanked or deleted into `+` or `*` to the system clipboard.
+    fn copy_to_clipboard(&mut self, name: Option<char>) {
+        let selection = match name.and_then(Selection::from_register) {
+            Some(selection) => selection,
+            None => return,
+        };
+        let text = name
+            .and_then(|name| self.registers.get(name))
+            .unwrap_or_default()
+            .text;
+
+        let result = match &self.clipboard {
+            Provider::Osc52 => self
+                .terminal
+                .copy(selection, &text)
+                .map_err(|error| error.to_string()),
+            provider => provider.copy(selection, &text),
+        };
+        if let Err(error) = result {
+            self.status = StatusMessage::from(format!("ERR: Clipboard: {}", error));
+        }
+    }
+
+    /// Puts the text of a register after or before the cursor, as many times as the count.
+    /// Whole lines go below or above the cursor's line.
+    fn put(&mut self, after: bool, prefix: Prefix) {
+        let name = prefix.register.unwrap_or('"');
+        let register = match self.register(name) {
+            Ok(register) => register,
+            Err(error) => {
+                self.status = StatusMessage::from(format!("ERR: {}", error));
+                return;
+            }
+        };
+
+        if register.blockwise {
+            self.put_block(&register.text, after, prefix.count.unwrap_or(1));
+            return;
+        }
+
+        let text = register.text.repeat(prefix.count.unwrap_or(1));
+        let Position { x, y } = self.cursor_position;
+        let len = self.document.buffer(y).map_or(0, |b| b.len_graphemes());
+
+        if register.linewise {
+            let below = after && !self.document.is_empty();
+            if below && y + 1 >= self.document.len() {
+                // The last line doesn't end with a newline, so the lines go after one.
+                let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                self.document.insert_text(&text, &Position::new(len, y));
+            } else {
+                let at = if below { y + 1 } else { y };
+                self.document.insert_text(&text, &Position::new(0, at));
+            }
+
+            let y = if below { y + 1 } else { y };
+            let x = motion::first_non_blank(&self.document, y);
+            self.cursor_position = Position { x, y };
+            return;
+        }
+
+        let at = Position::new(if after { x + 1 } else { x }.min(len), y);
+        let start = self.document.offset(&at);
+        self.document.insert_text(&text, &at);
+        // The cursor ends up on the last character put in, or at the start of text that takes
+        // up more than one line.
+        self.cursor_position = if text.contains('\n') {
+            at
+        } else {
+            let end = start + text.chars().count();
+            self.document.position(end.saturating_sub(1))
+        };
+    }
+
+    /// Puts the lines of a block at the cursor's column, each on the line below the one before.
+    /// Lines that end before the column are filled up with spaces and lines past the end of the
+    /// document are added, like in vim.
+    fn put_block(&mut self, text: &str, after: bool, count: usize) {
+        let tabstop = self.options.tabstop;
+        let Position { x, y } = self.cursor_position;
+        let column = match self.document.buffer(y) {
+            Some(buffer) if after && x < buffer.len_graphemes() => buffer.column(x + 1, tabstop),
+            Some(buffer) => buffer.column(x, tabstop),
+            None => 0,
+        };
+
+        // Every line of the block is as wide as the widest one, so text after it stays lined up.
+        let lines: Vec<&str> = text.split('\n').collect();
+        let width = lines.iter().map(|line| line.width()).max().unwrap_or(0);
+        for (i, line) in lines.iter().enumerate() {
+            let y = y + i;
+            if y >= self.document.len() && !self.document.is_empty() {
+                let last = self.document.len() - 1;
+                let len = self.document.buffer(last).map_or(0, |b| b.len_graphemes());
+                self.document.insert_text("\n", &Position::new(len, last));
+            }
+
+            let padded = format!("{}{}", line, " ".repeat(width - line.width()));
+            let (len, end) = self.document.buffer(y).map_or((0, 0), |b| {
+                (b.len_graphemes(), b.column(b.len_graphemes(), tabstop))
+            });
+            let (x, text) = if end <= column {
+                // Nothing comes after it, so it doesn't need to be filled up to the full width.
+                let fill = " ".repeat(column - end);
+                (len, fill + &padded.repeat(count.saturating_sub(1)) + line)
+            } else {
+                let x = self
+                    .document
+                    .buffer(y)
+                    .map_or(0, |b| b.grapheme_at_column(column, tabstop));
+                (x, padded.repeat(count))
+            };
+            self.document.insert_text(&text, &Position::new(x, y));
+        }
+
+        // The cursor ends up at the top left corner of the block.
+        let x = self
+            .document
+            .buffer(y)
+            .map_or(0, |b| b.grapheme_at_column(column, tabstop));
+        self.cursor_position = Position::new(x, y);
+    }
+
+    /// Lists the registers that have text in them, or the ones in `names`.
+    fn list_registers(&mut self, names: &str) {
+        let mut registers = self.registers.list();
+        if !self.document.filename.is_empty() {
+            registers.push(('%', Register::new(self.document.filename.clone(), false)));
+        }
+
+        let list: Vec<String> = registers
+            .iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(*name))
+            .map(|(name, register)| {
+                let kind = if register.linewise {
+                    "l"
+                } else if register.blockwise {
+                    "b"
+                } else {
+                    "c"
+                };
+                let text = register.text.replace('\n', "^J").replace('\t', "^I");
+                format!("{} \"{} {}", kind, name, text)
+            })
+            .collect();
+
+        let message = if list.is_empty() {
+            "Nothing in the registers".to_string()
+        } else {
+            list.join(", ")
+        };
+        self.status = StatusMessage::from(message);
+    }
+
+    /// Like vim's `report` option, says what happened when an operator changed more than two
+    /// lines.
+    fn report(&mut self, lines: usize, message: String) {
+        if lines > 2 {
+            self.status = StatusMessage::from(message);
+        }
+    }
+}
+
+// Functions related to buffers
+impl Editor {
+    /// Catches up on what happened to the document while it wasn't shown.
+    fn enter_buffer(&mut self) {
+        if self.prompt.is_none() {
+            if let Some(swap) = self.document.stale_swap.take() {
+                self.prompt = Some(Prompt::Recovery(swap));
+                return;
+            }
+        }
+
+        self.check_file();
+    }
+
+    /// Shows another buffer, the one that was shown keeps its cursor for when it comes back.
+    fn switch_to(&mut self, id: usize) {
+        if id == self.buffer_id {
+            return;
+        }
+
+        let previous = self.buffer_id;
+        if self.swap_buffer(id) {
+            self.alternate = Some(previous);
+            self.enter_buffer();
+        }
+    }
+
+    /// Puts a hidden buffer in the place of the one that is shown, returns whether it exists.
+    fn swap_buffer(&mut self, id: usize) -> bool {
+        let index = match self.buffers.iter().position(|b| b.id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        self.document.commit(&self.cursor_position);
+        let buffer = self.buffers.remove(index);
+        let previous = Buffer {
+            id: self.buffer_id,
+            document: mem::replace(&mut self.document, buffer.document),
+            cursor: mem::replace(&mut self.cursor_position, buffer.cursor),
+            offset: mem::replace(&mut self.offset, buffer.offset),
+        };
+
+        let at = self.buffers.partition_point(|b| b.id < previous.id);
+        self.buffers.insert(at, previous);
+        self.buffer_id = id;
+        true
+    }
+
+    /// Adds a buffer and shows it.
+    fn add_buffer(&mut self, document: Document) {
+        let id = self.next_buffer_id;
+        self.next_buffer_id += 1;
+        self.buffers.push(Buffer::new(id, document));
+        self.switch_to(id);
+    }
+
+    /// Shows the buffer of a file, opening the file if it doesn't have one yet.
+    fn edit(&mut self, filename: &str) {
+        let same_file = |document: &Document| {
+            document.filename == filename
+                || matches!(
+                    (fs::canonicalize(&document.filename), fs::canonicalize(filename)),
+                    (Ok(a), Ok(b)) if a == b
+                )
+        };
+
+        if same_file(&self.document) {
+            return;
+        }
+
+        if let Some(id) = self
+            .buffers
+            .iter()
+            .find(|b| same_file(&b.document))
+            .map(|b| b.id)
+        {
+            self.switch_to(id);
+            return;
+        }
+
+        let (document, message) = open_document(filename);
+        self.add_buffer(document);
+        if let Some(message) = message {
+            self.status = StatusMessage::from(message);
+        }
+    }
+
+    /// Goes to the next or previous buffer, wrapping around at the ends.
+    fn cycle_buffer(&mut self, forward: bool) {
+        let id = if forward {
+            let next = self.buffers.iter().find(|b| b.id > self.buffer_id);
+            next.or_else(|| self.buffers.first())
+        } else {
+            let previous = self.buffers.iter().rev().find(|b| b.id < self.buffer_id);
+            previous.or_else(|| self.buffers.last())
+        };
+
+        if let Some(id) = id.map(|b| b.id) {
+            self.switch_to(id);
+        }
+    }
+
+    /// Finds a buffer by its number, its filename or a unique part of its filename.
+    fn find_buffer(&self, name: &str) -> Result<usize, String> {
+        let mut buffers = iter::once((self.buffer_id, &self.document))
+            .chain(self.buffers.iter().map(|b| (b.id, &b.document)));
+
+        if let Ok(id) = name.parse::<usize>() {
+            return buffers
+                .find(|(buffer_id, _)| *buffer_id == id)
+                .map(|(id, _)| id)
+                .ok_or_else(|| format!("Buffer {} does not exist", id));
+        }
+
+        let buffers: Vec<(usize, &Document)> = buffers.collect();
+        if let Some((id, _)) = buffers.iter().find(|(_, d)| d.filename == name) {
+            return Ok(*id);
+        }
+
+        let matches: Vec<usize> = buffers
+            .iter()
+            .filter(|(_, d)| d.filename.contains(name))
+            .map(|(id, _)| *id)
+            .collect();
+
+        match matches.as_slice() {
+            [id] => Ok(*id),
+            [] => Err(format!("No matching buffer for {}", name)),
+            _ => Err(format!("More than one match for {}", name)),
+        }
+    }
+
+    /// Shows every buffer like `1 % "main.rs" line 3`, `%` marks the buffer that is shown,
+    /// `#` the alternate buffer and `+` unsaved changes.
+    fn list_buffers(&mut self) {
+        let current = (self.buffer_id, &self.document, self.cursor_position);
+        let hidden = self.buffers.iter().map(|b| (b.id, &b.document, b.cursor));
+        let mut buffers: Vec<(usize, &Document, Position)> =
+            iter::once(current).chain(hidden).collect();
+        buffers.sort_by_key(|(id, _, _)| *id);
+
+        let list: Vec<String> = buffers
+            .iter()
+            .map(|(id, document, cursor)| {
+                let shown = if *id == self.buffer_id {
+                    "%"
+                } else if Some(*id) == self.alternate {
+                    "#"
+                } else {
+                    " "
+                };
+                let modified = if document.is_modified() { "+" } else { " " };
+                format!(
+                    "{} {}{} \"{}\" line {}",
+                    id,
+                    shown,
+                    modified,
+                    buffer::name(document),
+                    cursor.y + 1
+                )
+            })
+            .collect();
+
+        self.status = StatusMessage::from(list.join(", "));
+    }
+
+    /// Closes a buffer without saving it. When it's the one that is shown, the alternate buffer
+    /// takes its place, and when it's the last one an empty buffer does.
+    fn delete_buffer(&mut self, id: usize) {
+        self.close_windows_showing(id);
+        if id != self.buffer_id {
+            if let Some(index) = self.buffers.iter().position(|b| b.id == id) {
+                self.buffers.remove(index).document.close();
+            }
+            if self.alternate == Some(id) {
+                self.alternate = None;
+            }
+            return;
+        }
+
+        let next = self
+            .alternate
+            .filter(|alternate| self.buffers.iter().any(|b| b.id == *alternate))
+            .or_else(|| self.buffers.first().map(|b| b.id));
+
+        match next {
+            Some(next) => {
+                self.switch_to(next);
+                self.delete_buffer(id);
+            }
+            None => {
+                self.document.close();
+                self.document = Document::default();
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                self.buffer_id = self.next_buffer_id;
+                self.next_buffer_id += 1;
+            }
+        }
+    }
+}
+
+// Functions related to windows
+impl Editor {
+    /// The part of the terminal the windows share, everything but the message bar and the
+    /// tab line.
+    fn area(&self) -> Rect {
+        let size = self.terminal.size();
+        // The tab line is only there when there's more than one tab page.
+        let tab_line = if self.tabs.is_empty() { 0 } else { 1 };
+        Rect {
+            x: 0,
+            y: tab_line,
+            width: size.width,
+            height: size.height.saturating_add(1).saturating_sub(tab_line),
+        }
+    }
+
+    /// Whether the window with the cursor is the only one left, in every tab page.
+    fn is_last_window(&self) -> bool {
+        self.windows.is_empty() && self.tabs.is_empty()
+    }
+
+    fn window_rect(&self) -> Rect {
+        let rects = self.layout.rects(self.area());
+        let rect = rects.iter().find(|(id, _)| *id == self.window_id);
+        rect.map_or(self.area(), |(_, rect)| *rect)
+    }
+
+    /// The number of lines of text the window with the cursor shows.
+    fn text_height(&self) -> usize {
+        self.window_rect().height.saturating_sub(1) as usize
+    }
+
+    /// Where the cursor is drawn on the terminal.
+    fn cursor_on_screen(&self) -> Position {
+        let rect = self.window_rect();
+        Position {
+            x: rect.x as usize + self.cursor_column().saturating_sub(self.offset.x),
+            y: rect.y as usize + self.cursor_position.y.saturating_sub(self.offset.y),
+        }
+    }
+
+    fn document_of(&self, buffer_id: usize) -> &Document {
+        match self.buffers.iter().find(|b| b.id == buffer_id) {
+            Some(buffer) if buffer_id != self.buffer_id => &buffer.document,
+            _ => &self.document,
+        }
+    }
+
+    /// The document a window shows, along with its cursor and offset.
+    fn view(&self, id: usize) -> (&Document, Position, Position) {
+        match self.windows.iter().find(|w| w.id == id) {
+            Some(window) => (
+                self.document_of(window.buffer_id),
+                window.cursor,
+                window.offset,
+            ),
+            None => (&self.document, self.cursor_position, self.offset),
+        }
+    }
+
+    /// Keeps the cursors of the other windows inside their documents and their windows, the
+    /// documents may have been changed in another window, or the windows resized.
+    fn fit_windows(&mut self) {
+        let rects = self.layout.rects(self.area());
+        for window in self.windows.iter_mut() {
+            let document = match self.buffers.iter().find(|b| b.id == window.buffer_id) {
+                Some(buffer) if window.buffer_id != self.buffer_id => &buffer.document,
+                _ => &self.document,
+            };
+
+            let Position { x, y } = window.cursor;
+            let y = y.min(document.len().saturating_sub(1));
+            let x = x.min(document.buffer(y).map_or(0, |b| b.len_graphemes()));
+            window.cursor = Position { x, y };
+
+            if let Some((_, rect)) = rects.iter().find(|(id, _)| *id == window.id) {
+                let column = column_of(document, window.cursor, self.options.tabstop);
+                let height = rect.height.saturating_sub(1) as usize;
+                follow(&mut window.offset, column, y, rect.width as usize, height);
+            }
+        }
+    }
+
+    /// Moves the cursor to another window.
+    fn focus(&mut self, id: usize) {
+        if id == self.window_id || !self.windows.iter().any(|w| w.id == id) {
+            return;
+        }
+
+        self.stash_window();
+        self.previous_window = Some(self.window_id);
+        self.unstash_window(id);
+    }
+
+    /// Puts the window with the cursor in `windows`, with the other ones.
+    fn stash_window(&mut self) {
+        self.document.commit(&self.cursor_position);
+        self.windows.push(Window {
+            id: self.window_id,
+            buffer_id: self.buffer_id,
+            cursor: self.cursor_position,
+            offset: self.offset,
+        });
+    }
+
+    /// Takes a window out of `windows` and gives it the cursor.
+    fn unstash_window(&mut self, id: usize) {
+        let index = match self.windows.iter().position(|w| w.id == id) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let window = self.windows.remove(index);
+        self.window_id = window.id;
+
+        let entered = window.buffer_id != self.buffer_id && self.swap_buffer(window.buffer_id);
+        self.cursor_position = window.cursor;
+        self.offset = window.offset;
+        if entered {
+            self.enter_buffer();
+        }
+    }
+
+    /// Splits the window with the cursor in two, both show the same buffer. The cursor goes to
+    /// the new window, which is above or to the left of the old one.
+    fn split(&mut self, split: Split) -> bool {
+        let rect = self.window_rect();
+        let room = match split {
+            Split::Horizontal => rect.height >= 4,
+            Split::Vertical => rect.width >= 3,
+        };
+
+        if !room {
+            self.status = StatusMessage::from("ERR: Not enough room");
+            return false;
+        }
+
+        let area = self.area();
+        self.layout.normalize(area);
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.layout.split(self.window_id, id, split);
+
+        self.stash_window();
+        self.previous_window = Some(self.window_id);
+        self.window_id = id;
+        true
+    }
+
+    /// Closes the window with the cursor, its buffer stays open. Closing the last window of
+    /// a tab page closes the tab page.
+    fn close_window(&mut self) {
+        if self.is_last_window() {
+            self.status = StatusMessage::from("ERR: Cannot close the last window");
+            return;
+        } else if self.windows.is_empty() {
+            self.close_tab();
+            return;
+        }
+
+        let closed = self.window_id;
+        let next = self
+            .previous_window
+            .filter(|id| self.windows.iter().any(|w| w.id == *id))
+            .unwrap_or(self.windows[0].id);
+
+        self.layout.remove(closed);
+        self.focus(next);
+        self.windows.retain(|w| w.id != closed);
+        self.previous_window = None;
+    }
+
+    /// Closes every window but the one with the cursor.
+    fn only_window(&mut self) {
+        self.windows.clear();
+        self.layout = Layout::Window(self.window_id);
+        self.previous_window = None;
+    }
+
+    /// Closes the windows that show a buffer, other than the one with the cursor. Tab pages
+    /// that are left without windows are closed too.
+    fn close_windows_showing(&mut self, buffer_id: usize) {
+        let mut index = 0;
+        let tab_index = &mut self.tab_index;
+        self.tabs.retain_mut(|tab| {
+            let keep = tab.close_windows_showing(buffer_id);
+            if !keep && index < *tab_index {
+                *tab_index -= 1;
+            }
+            index += 1;
+            keep
+        });
 
-        println!("{}\r", welcome_message);
+        for window in self.windows.iter().filter(|w| w.buffer_id == buffer_id) {
+            self.layout.remove(window.id);
+        }
+        self.windows.retain(|w| w.buffer_id != buffer_id);
+
+        if !self
+            .windows
+            .iter()
+            .any(|w| Some(w.id) == self.previous_window)
+        {
+            self.previous_window = None;
+        }
     }
 
-    fn draw_row(&self, buffer: &GapBuffer) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = buffer.render(start, end);
+    /// Moves the cursor to the window next to this one, on the side `direction` points to
+    /// (one of `h`, `j`, `k`, `l`).
+    fn focus_neighbour(&mut self, direction: char) {
+        let rect = self.window_rect();
+        let cursor = self.cursor_on_screen();
+        let (x, y) = (cursor.x as u16, cursor.y as u16);
 
-        println!("{}\r", row)
-    }
+        let point = match direction {
+            // Skips the separator between the windows.
+            'h' => rect.x.checked_sub(2).map(|x| (x, y)),
+            'l' => Some((rect.x + rect.width + 1, y)),
+            'k' => rect.y.checked_sub(1).map(|y| (x, y)),
+            'j' => Some((x, rect.y + rect.height)),
+            _ => None,
+        };
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let rects = self.layout.rects(self.area());
+        let found = point.and_then(|(x, y)| {
+            // A point on a separator belongs to the window on its left.
+            rects.iter().find(|(id, rect)| {
+                *id != self.window_id
+                    && (rect.contains(x, y) || rect.contains(x.saturating_sub(1), y))
+            })
+        });
 
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
+        if let Some((id, _)) = found {
+            self.focus(*id);
         }
+    }
+
+    /// Moves the cursor to the next or previous window, in the order they're on the screen.
+    fn cycle_window(&mut self, forward: bool) {
+        let ids: Vec<usize> = self
+            .layout
+            .rects(self.area())
+            .iter()
+            .map(|(id, _)| *id)
+            .collect();
+        let index = ids.iter().position(|id| *id == self.window_id).unwrap_or(0);
+        let next = if forward {
+            (index + 1) % ids.len()
+        } else {
+            (index + ids.len() - 1) % ids.len()
+        };
+
+        self.focus(ids[next]);
+    }
+
+    fn resize_window(&mut self, split: Split, delta: i32) {
+        let area = self.area();
+        self.layout.normalize(area);
+        self.layout.resize(self.window_id, split, delta);
+    }
+
+    /// Runs the `Ctrl-W` command for the key that followed it. `count` is how much bigger or
+    /// smaller a window gets.
+    fn window_command(&mut self, event: KeyEvent, count: usize) {
+        let delta = count.min(i32::MAX as usize) as i32;
+        // `Ctrl-W Ctrl-S` does the same as `Ctrl-W s`.
+        match event.code {
+            Key::Char('s' | 'S') => {
+                self.split(Split::Horizontal);
+            }
+            Key::Char('v') => {
+                self.split(Split::Vertical);
+            }
+            Key::Char('n') if self.split(Split::Horizontal) => self.add_buffer(Document::default()),
+            Key::Char('c') => self.close_window(),
+            Key::Char('q') if self.is_last_window() => self.quit(),
+            Key::Char('q') => self.close_window(),
+            Key::Char('o') => self.only_window(),
+
+            Key::Char(direction @ ('h' | 'j' | 'k' | 'l')) => self.focus_neighbour(direction),
+            Key::Char('w') => self.cycle_window(true),
+            Key::Char('W') => self.cycle_window(false),
+            Key::Char('p') => {
+                if let Some(id) = self.previous_window {
+                    self.focus(id);
+                }
+            }
+            Key::Char(c @ ('t' | 'b')) => {
+                let rects = self.layout.rects(self.area());
+                let window = if c == 't' {
+                    rects.first()
+                } else {
+                    rects.last()
+                };
+                if let Some((id, _)) = window {
+                    self.focus(*id);
+                }
+            }
 
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+            Key::Char('+') => self.resize_window(Split::Horizontal, delta),
+            Key::Char('-') => self.resize_window(Split::Horizontal, -delta),
+            Key::Char('>') => self.resize_window(Split::Vertical, delta),
+            Key::Char('<') => self.resize_window(Split::Vertical, -delta),
+            Key::Char('=') => self.layout.equalize(),
+
+            Key::Char('r') => self.layout.rotate(self.window_id, true),
+            Key::Char('R') => self.layout.rotate(self.window_id, false),
+            _ => (),
         }
     }
+}
 
-    fn draw_status_bar(&mut self) {
-        // NOTE: The current issue is that the status bar will make space for the text in the document.
-        // That should not happen.
-        let width = self.terminal.size().width as usize;
-        let filename = if let Some(filename) = self.document.filename.get(..21) {
-            filename.to_string()
-        } else {
-            self.document.filename.clone()
+// Functions related to tab pages
+impl Editor {
+    /// Puts the tab page that is shown with the others, its place is kept by `tab_index`.
+    fn stash_tab(&mut self) {
+        self.stash_window();
+        let tab = Tab {
+            layout: mem::replace(&mut self.layout, Layout::Window(0)),
+            windows: mem::take(&mut self.windows),
+            window_id: self.window_id,
+            previous_window: self.previous_window.take(),
         };
+        self.tabs.insert(self.tab_index, tab);
+    }
 
-        let status = format!("{} | {}", self.mode, filename);
+    /// Takes a tab page out of `tabs` and shows it.
+    fn unstash_tab(&mut self, index: usize) {
+        let tab = self.tabs.remove(index);
+        self.tab_index = index;
+        self.layout = tab.layout;
+        self.windows = tab.windows;
+        self.previous_window = tab.previous_window;
+        self.unstash_window(tab.window_id);
+    }
 
-        let rows = self.document.len() as f32;
-        let current_line = (self.cursor_position.y + 1) as f32;
+    /// Shows the tab page that is `index` tabs from the left.
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.tab_index || index > self.tabs.len() {
+            return;
+        }
 
-        let percentage = if rows > 0.0 {
-            (current_line / rows * 100.0).trunc()
+        self.stash_tab();
+        self.unstash_tab(index);
+    }
+
+    /// Goes to the next or previous tab page, wrapping around at the ends.
+    fn cycle_tab(&mut self, forward: bool) {
+        let count = self.tabs.len() + 1;
+        let index = if forward {
+            (self.tab_index + 1) % count
         } else {
-            0 as f32
+            (self.tab_index + count - 1) % count
         };
 
-        let line_number = format!("{}/{}: {}%", current_line, rows, percentage);
-        let left_half = width.saturating_sub(status.len());
-        let right_half = left_half.saturating_sub(line_number.len());
-        let spaces = " ".repeat(right_half);
+        self.switch_tab(index);
+    }
+
+    /// Opens a tab page after this one, with one window showing the same buffer.
+    fn new_tab(&mut self) {
+        self.stash_tab();
+        self.tab_index += 1;
+
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.layout = Layout::Window(id);
+        self.window_id = id;
+    }
+
+    /// Closes the tab page and its windows, their buffers stay open.
+    fn close_tab(&mut self) {
+        if self.tabs.is_empty() {
+            self.status = StatusMessage::from("ERR: Cannot close the last tab page");
+            return;
+        }
+
+        // The tab page on the right takes its place, or the one on the left for the last one.
+        let index = self.tab_index.min(self.tabs.len() - 1);
+        self.document.commit(&self.cursor_position);
+        self.unstash_tab(index);
+    }
+
+    /// The labels of the tab pages, like ` 2 main.rs + `.
+    fn tab_labels(&self) -> Vec<String> {
+        let current = (&self.document, self.tab_index);
+        let hidden = self.tabs.iter().enumerate().map(|(index, tab)| {
+            let window = tab.windows.iter().find(|w| w.id == tab.window_id);
+            let document = window.map_or(&self.document, |w| self.document_of(w.buffer_id));
+            let index = if index < self.tab_index {
+                index
+            } else {
+                index + 1
+            };
+            (document, index)
+        });
+
+        let mut labels: Vec<(&Document, usize)> = iter::once(current).chain(hidden).collect();
+        labels.sort_by_key(|(_, index)| *index);
+        labels
+            .iter()
+            .map(|(document, index)| {
+                let modified = if document.is_modified() { "+ " } else { "" };
+                format!(" {} {} {}", index + 1, buffer::name(document), modified)
+            })
+            .collect()
+    }
 
-        self.terminal.set_bg_color(STATUS_BAR_BG_COLOUR);
+    /// Draws the tab line at the top of the screen, the tab page that is shown stands out.
+    fn draw_tab_line(&mut self) {
+        if self.tabs.is_empty() {
+            return;
+        }
+
+        let width = self.terminal.size().width as usize;
+        let mut used = 0;
+        self.terminal.set_cursor_position(&Position::new(0, 0));
         self.terminal.set_fg_color(STATUS_FG_COLOUR);
-        println!("{}{}{}\r", status, spaces, line_number);
+
+        for (index, label) in self.tab_labels().iter().enumerate() {
+            if index == self.tab_index {
+                self.terminal.set_bg_color(STATUS_BAR_BG_COLOUR);
+            } else {
+                self.terminal.set_bg_color(INACTIVE_STATUS_BAR_BG_COLOUR);
+            }
+
+            let label: String = label.chars().take(width.saturating_sub(used)).collect();
+            used += label.width();
+            self.terminal.print(&label);
+        }
+
+        self.terminal.set_bg_color(INACTIVE_STATUS_BAR_BG_COLOUR);
+        self.terminal.print(&" ".repeat(width.saturating_sub(used)));
         self.terminal.reset_fg_color();
         self.terminal.reset_bg_color();
     }
+}
 
-    fn draw_message_bar(&mut self) {
-        self.terminal.clear_current_line();
-        let message = &self.status;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_backend::MemoryBackend;
+
+    fn editor(width: u16, height: u16) -> (Editor, MemoryBackend) {
+        open(width, height, &[])
     }
 
-    fn draw_rows(&mut self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            self.terminal.set_cursor_position(&Position {
-                x: 0,
-                y: terminal_row as _,
-            });
-            self.terminal.clear_current_line();
+    /// An editor started with `args` on the command line.
+    fn open(width: u16, height: u16, args: &[&str]) -> (Editor, MemoryBackend) {
+        let backend = MemoryBackend::new(width, height);
+        let terminal = Terminal::new(Box::new(backend.clone())).unwrap();
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        (Editor::new(terminal, args), backend)
+    }
 
-            // NOTE: index = terminal_row + self.offset.y
-            if let Some(buffer) = self.document.buffer(terminal_row as usize + self.offset.y) {
-                self.draw_row(buffer);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
-            } else {
-                println!("~\r");
+    /// A directory for the files of one test, removed again when it's dropped.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join("hecto-test");
+            let path = root.join(format!("{}-{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Self(fs::canonicalize(path).unwrap())
+        }
+
+        fn path(&self, filename: &str) -> String {
+            self.0.join(filename).to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            // The undo and swap files of the files in here are named after their paths.
+            let prefix = self.0.to_string_lossy().replace('/', "%");
+            let state = crate::undo_file::state_directory().unwrap();
+            for kind in ["swap", "undo"] {
+                let entries = fs::read_dir(state.join(kind)).into_iter().flatten();
+                for entry in entries.flatten() {
+                    if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                        let _ = fs::remove_file(entry.path());
+                    }
+                }
             }
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Plays back keys the way `run` does, drawing after every one of them.
+    fn press(editor: &mut Editor, backend: &MemoryBackend, keys: &str) {
+        backend.push_keys(keys);
+        editor.render();
+        while backend.has_events() {
+            editor.step().unwrap();
+        }
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.document.text(0, editor.document.len_chars())
+    }
+
+    #[test]
+    fn insert_mode_types_text() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ihello<CR>world<Esc>");
+
+        assert_eq!(text(&editor), "hello\nworld");
+        assert_eq!(
+            backend.lines(),
+            [
+                "hello",
+                "world",
+                "~",
+                "~",
+                "~",
+                "MODE: NORMAL | [No Name] [+]  2/2: 100%",
+                "Press CTRL + Q to QUIT.",
+                "",
+            ]
+        );
+        assert_eq!(backend.cursor(), (5, 1));
+        assert!(backend.cursor_visible());
+    }
+
+    #[test]
+    fn normal_mode_moves_and_undoes() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ihello<CR>world<Esc>kA!<Esc>");
+
+        assert_eq!(text(&editor), "hello!\nworld");
+        assert_eq!(backend.lines()[..2], ["hello!", "world"]);
+        assert_eq!(
+            backend.lines()[5],
+            "MODE: NORMAL | [No Name] [+]   1/2: 50%"
+        );
+        assert_eq!(backend.cursor(), (6, 0));
+
+        press(&mut editor, &backend, "u");
+        assert_eq!(text(&editor), "hello\nworld");
+        assert_eq!(backend.lines()[..2], ["hello", "world"]);
+        assert_eq!(backend.cursor_shape(), Some(CursorShape::Block));
+    }
+
+    #[test]
+    fn command_popup_runs_commands() {
+        let (mut editor, backend) = editor(60, 20);
+        press(&mut editor, &backend, "ihello<Esc>:und");
+
+        let lines = backend.lines();
+        assert_eq!(lines[0], "hello");
+        assert_eq!(lines[3], "~              ┌───────── commands ─────────┐");
+        assert_eq!(lines[4], "~              │save                        │");
+        assert_eq!(lines[13], "~              ┌──────Type the command──────┐");
+        assert_eq!(lines[14], "~              │und                         │");
+        assert_eq!(backend.cursor(), (19, 14));
+
+        press(&mut editor, &backend, "o<CR>");
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(text(&editor), "");
+        assert!(backend.lines().iter().all(|line| !line.contains('│')));
+    }
+
+    #[test]
+    fn command_popup_rejects_unknown_commands() {
+        let (mut editor, backend) = editor(60, 20);
+        press(&mut editor, &backend, ":nope<CR>");
+
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(
+            backend.lines()[11..],
+            [
+                "~              ┌──────Invalid command───────┐",
+                "~              │                            │",
+                "~              └────────────────────────────┘",
+                "~              ┌────────────────────────────┐",
+                "~              │       Invalid Command      │",
+                "~              │  The command 'nope' is not │",
+                "MODE: COMMAND |│           valid.           │       1/0: 0%",
+                "Press CTRL + Q └────────────────────────────┘",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_popup_fits_small_screens() {
+        let (mut editor, backend) = editor(30, 8);
+        press(&mut editor, &backend, ":und");
+
+        let lines = backend.lines();
+        assert_eq!(lines[0], "~    ┌──── commands ────┐");
+        assert_eq!(lines[5], "[No N│und               │: 0%");
+        assert_eq!(backend.cursor(), (9, 5));
+
+        press(&mut editor, &backend, "<Esc>");
+        assert_eq!(backend.lines()[5], "[No Name]             1/0: 0%");
+    }
+
+    #[test]
+    fn split_windows_have_their_own_status_lines() {
+        let (mut editor, backend) = editor(40, 10);
+        press(&mut editor, &backend, "ione<CR>two<Esc><C-w>sgg");
+
+        assert_eq!(
+            backend.lines(),
+            [
+                "one",
+                "two",
+                "~",
+                "MODE: NORMAL | [No Name] [+]   1/2: 50%",
+                "one",
+                "two",
+                "~",
+                "[No Name] | utf-8 | unix [+]  2/2: 100%",
+                "Press CTRL + Q to QUIT.",
+                "",
+            ]
+        );
+        assert_eq!(backend.cursor(), (3, 0));
+        assert_eq!(backend.cell(0, 3).bg, STATUS_BAR_BG_COLOUR);
+        assert_eq!(backend.cell(0, 7).bg, INACTIVE_STATUS_BAR_BG_COLOUR);
+    }
+
+    #[test]
+    fn long_filenames_are_shortened_from_the_left() {
+        let (mut editor, backend) = open(40, 6, &["a/long/way/down/into/the/tree/file.txt"]);
+        press(&mut editor, &backend, "");
+        assert_eq!(
+            backend.lines()[3],
+            "<n/into/the/tree/file.txt [New] 1/0: 0%"
+        );
+    }
+
+    #[test]
+    fn quitting_leaves_the_alternate_screen() {
+        let (mut editor, backend) = editor(40, 6);
+        backend.push_keys(":q<CR>");
+        editor.run();
+        assert!(editor.should_quit);
+        assert!(!backend.alternate_screen());
+    }
+
+    #[test]
+    fn drawing_errors_go_to_the_message_bar() {
+        let (mut editor, backend) = editor(50, 6);
+        press(&mut editor, &backend, "ione<Esc>");
+        backend.fail_flushes(1);
+        // The failed frame is drawn again in full with the next key.
+        press(&mut editor, &backend, "atwo<Esc>");
+        assert_eq!(text(&editor), "onetwo");
+        assert_eq!(
+            backend.lines(),
+            [
+                "onetwo",
+                "~",
+                "~",
+                "MODE: NORMAL | [No Name] [+]            1/1: 100%",
+                "ERR: Could not draw the screen: broken pipe",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn new_lines_take_the_indentation_along() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "i<Tab>one<CR>two<Esc>");
+        assert_eq!(text(&editor), "    one\n    two");
+
+        // Looking for the indentation goes through any amount of lines.
+        let dir = TempDir::new("indent");
+        let path = dir.path("long.txt");
+        std::fs::write(&path, "xy\n".repeat(200_000) + "\tend\n").unwrap();
+        let (mut editor, backend) = open(40, 8, &[&path]);
+        press(&mut editor, &backend, "gg0li<CR><Esc>");
+        assert_eq!(editor.document.buffer(1).unwrap().line(), "\ty");
+    }
+
+    #[test]
+    fn tabs_line_up_with_tab_stops() {
+        let (mut editor, backend) = editor(40, 8);
+        // `expandtab` is on, Tab fills up to the next soft tab stop with spaces.
+        press(&mut editor, &backend, "ia<Tab>b<Esc>");
+        assert_eq!(text(&editor), "a   b");
+        // Backspace takes the spaces of a whole soft tab stop at once.
+        press(&mut editor, &backend, "ddi<Tab><Tab><BS>c<Esc>");
+        assert_eq!(text(&editor), "    c");
+
+        press(
+            &mut editor,
+            &backend,
+            ":set noexpandtab<CR>:set ts=4<CR>ddix<Tab>y<Esc>",
+        );
+        assert_eq!(text(&editor), "x\ty");
+        assert_eq!(backend.lines()[0], "x   y");
+        assert_eq!(backend.cursor(), (5, 0));
+        press(&mut editor, &backend, "h");
+        assert_eq!(backend.cursor(), (4, 0));
+
+        press(&mut editor, &backend, ">>");
+        assert_eq!(text(&editor), "\tx\ty");
+        assert_eq!(backend.lines()[0], "    x   y");
+        press(&mut editor, &backend, "$h");
+        assert_eq!(editor.cursor_position.x, 2);
+        assert_eq!(backend.cursor(), (5, 0));
+
+        press(&mut editor, &backend, ":set ts=8<CR>");
+        assert_eq!(backend.lines()[0], "        x       y");
+        assert_eq!(backend.cursor(), (9, 0));
+    }
+
+    #[test]
+    fn line_endings_are_kept_when_saving() {
+        let dir = TempDir::new("line-endings");
+        let path = dir.path("dos.txt");
+        fs::write(&path, "one\r\ntwo").unwrap();
+
+        let (mut editor, backend) = open(100, 8, &[&path]);
+        press(&mut editor, &backend, ":set ff? eol?<CR>");
+        assert_eq!(text(&editor), "one\ntwo");
+        assert_eq!(editor.status.text, "noendofline");
+        let status = format!("MODE: NORMAL | {} | utf-8 | dos [noeol]", path);
+        assert_eq!(backend.lines()[5], format!("{:<91}1/2: 50%", status));
+
+        press(&mut editor, &backend, "A!<Esc>:write<CR>");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one!\r\ntwo");
+
+        press(&mut editor, &backend, "<Esc>:set ff=unix eol ff?<CR>");
+        assert_eq!(editor.status.text, "fileformat=unix");
+        press(&mut editor, &backend, ":write<CR>");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one!\ntwo\n");
+
+        press(
+            &mut editor,
+            &backend,
+            "<Esc>:set ff=mac noeol<CR>:write<CR>",
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one!\rtwo");
+    }
+
+    #[test]
+    fn files_in_other_encodings_can_be_edited() {
+        let dir = TempDir::new("encodings");
+        let latin1 = dir.path("latin1.txt");
+        fs::write(&latin1, b"caf\xe9\n").unwrap();
+        let utf16 = dir.path("utf16.txt");
+        fs::write(&utf16, b"h\0i\0\n\0").unwrap();
+
+        let (mut editor, backend) = open(100, 8, &[&latin1]);
+        press(&mut editor, &backend, "A!<Esc>:write<CR>");
+        assert!(!editor.document.read_only);
+        assert_eq!(fs::read(&latin1).unwrap(), b"caf\xe9!\n");
+
+        press(
+            &mut editor,
+            &backend,
+            &format!(":edit {}<CR>A!<Esc>:write<CR>", utf16),
+        );
+        assert!(!editor.document.read_only);
+        assert_eq!(fs::read(&utf16).unwrap(), b"h\0i\0!\0\n\0");
+    }
+
+    #[test]
+    fn new_files_are_created_on_the_first_write() {
+        let dir = TempDir::new("new-file");
+        let path = dir.path("new.txt");
+        let (mut editor, backend) = open(60, 8, &[&path]);
+        press(&mut editor, &backend, "");
+        assert_eq!(backend.lines()[6], format!("\"{}\" [New]", path));
+        assert!(!Path::new(&path).exists());
+
+        press(&mut editor, &backend, "ihello<Esc><A-w>");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\n");
+        assert_eq!(backend.lines()[6], format!("\"{}\" written", path));
+        assert!(!editor.document.is_modified());
+    }
+
+    #[test]
+    fn messages_are_cut_off_between_characters() {
+        let (mut editor, backend) = editor(15, 8);
+        press(&mut editor, &backend, "ihello<Esc><A-w>日本語ファイル");
+        assert_eq!(backend.lines()[6], "Save as: 日本");
+        press(
+            &mut editor,
+            &backend,
+            "<Esc>ccéééééééééé<Esc>\"ayy:registers a<CR>",
+        );
+        assert_eq!(backend.lines()[6], "l \"a ééééééééé");
+    }
+
+    #[test]
+    fn unnamed_buffers_ask_for_a_filename() {
+        let dir = TempDir::new("save-as");
+        let (first, second) = (dir.path("first.txt"), dir.path("second.txt"));
+        let (mut editor, backend) = editor(80, 8);
+        press(&mut editor, &backend, "ihello<Esc><A-w>");
+        assert_eq!(backend.lines()[6], "Save as:");
+        assert_eq!(backend.cursor(), (9, 6));
+        press(&mut editor, &backend, "x<BS><Esc>");
+        assert_eq!(backend.lines()[6], "Not saved.");
+
+        press(&mut editor, &backend, &format!(":write<CR>{}<CR>", first));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "hello\n");
+        assert_eq!(editor.document.filename, first);
+
+        // `write` with a name writes a copy, the buffer stays with its own file.
+        press(
+            &mut editor,
+            &backend,
+            &format!("<Esc>A!<Esc>:write {}<CR>", second),
+        );
+        assert_eq!(fs::read_to_string(&second).unwrap(), "hello!\n");
+        assert_eq!(editor.document.filename, first);
+        assert!(editor.document.is_modified());
+
+        // `saveas` moves the buffer over, other files are only overwritten with `!`.
+        press(
+            &mut editor,
+            &backend,
+            &format!("<Esc>A?<Esc>:saveas {}<CR>", second),
+        );
+        assert!(editor.status.text.starts_with("ERR: "));
+        assert_eq!(fs::read_to_string(&second).unwrap(), "hello!\n");
+        press(
+            &mut editor,
+            &backend,
+            &format!("<Esc>:saveas! {}<CR>", second),
+        );
+        assert_eq!(fs::read_to_string(&second).unwrap(), "hello!?\n");
+        assert_eq!(editor.document.filename, second);
+        assert!(!editor.document.is_modified());
+        assert_eq!(fs::read_to_string(&first).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn undo_goes_back_to_unmodified() {
+        let (mut editor, backend) = editor(60, 8);
+        press(&mut editor, &backend, "ione<Esc>");
+        assert!(editor.document.is_modified());
+        assert!(backend.lines()[5].contains("unix [+]"));
+
+        press(&mut editor, &backend, "u");
+        assert!(!editor.document.is_modified());
+        assert!(!backend.lines()[5].contains("[+]"));
+        press(&mut editor, &backend, "<C-r>");
+        assert!(editor.document.is_modified());
+    }
+
+    #[test]
+    fn quitting_asks_about_unsaved_changes() {
+        let (mut editor, backend) = editor(60, 8);
+        press(&mut editor, &backend, "ione<Esc><C-q>");
+        assert!(!editor.should_quit);
+        assert_eq!(
+            backend.lines()[6],
+            "Save changes to \"[No Name]\"? [y]es, [n]o, [c]ancel"
+        );
+
+        press(&mut editor, &backend, "c");
+        assert!(!editor.should_quit);
+        assert!(editor.prompt.is_none());
+
+        press(&mut editor, &backend, ":quit!<CR>");
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn quitting_all_goes_through_every_modified_buffer() {
+        let dir = TempDir::new("quit-all");
+        let (a, b, c) = (dir.path("a"), dir.path("b"), dir.path("c"));
+        for path in [&a, &b, &c] {
+            fs::write(path, "text\n").unwrap();
         }
+        let (mut editor, backend) = open(120, 8, &[&a, &b, &c]);
+        let question = |path: &str| format!("Save changes to \"{}\"? [y]es, [n]o, [c]ancel", path);
+        press(&mut editor, &backend, "dl:bnext<CR>:bnext<CR>dl:bnext<CR>");
+        assert_eq!(editor.document.filename, a);
+
+        press(&mut editor, &backend, ":qall<CR>");
+        assert_eq!(backend.lines()[6], question(&a));
+        press(&mut editor, &backend, "y");
+        assert_eq!(fs::read_to_string(&a).unwrap(), "ext\n");
+        assert!(!editor.should_quit);
+
+        // `b` wasn't changed, `c` is next.
+        assert_eq!(backend.lines()[6], question(&c));
+        press(&mut editor, &backend, "n");
+        assert!(editor.should_quit);
+        assert_eq!(fs::read_to_string(&c).unwrap(), "text\n");
+    }
+
+    #[test]
+    fn files_changed_on_disk_are_reloaded_as_one_change() {
+        let dir = TempDir::new("reload");
+        let path = dir.path("file.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let (mut editor, backend) = open(60, 8, &[&path]);
+        press(&mut editor, &backend, ":set autoread<CR>");
+
+        // The same length and, on a file system that only keeps seconds, the same time.
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "one\nTWO\nthree\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(editor.check_file());
+        assert_eq!(text(&editor), "one\nTWO\nthree");
+        assert!(!editor.document.is_modified());
+        assert!(!editor.check_file());
+
+        // Only what changed goes into the history.
+        let history = &editor.document.history;
+        let changes = &history.revisions[history.current].changes;
+        let changes: Vec<String> = changes.iter().map(|change| change.serialize()).collect();
+        assert_eq!(changes, ["d 4 two", "i 4 TWO"]);
+
+        press(&mut editor, &backend, "u");
+        assert_eq!(text(&editor), "one\ntwo\nthree");
+        press(&mut editor, &backend, "<C-r>");
+        assert_eq!(text(&editor), "one\nTWO\nthree");
+    }
+
+    #[test]
+    fn buffers_keep_their_own_cursor() {
+        let dir = TempDir::new("buffers");
+        let (a, b) = (dir.path("a.txt"), dir.path("b.txt"));
+        fs::write(&a, "alpha\nsecond\n").unwrap();
+        fs::write(&b, "beta\n").unwrap();
+        let (mut editor, backend) = open(120, 8, &[&a, &b]);
+
+        press(&mut editor, &backend, "j:bn<CR>");
+        assert_eq!(text(&editor), "beta");
+        assert_eq!(editor.cursor_position.y, 0);
+        press(&mut editor, &backend, ":bp<CR>");
+        assert_eq!(text(&editor), "alpha\nsecond");
+        assert_eq!(editor.cursor_position.y, 1);
+
+        // `Ctrl-^` goes back and forth between the last two buffers.
+        press(&mut editor, &backend, "<C-^>");
+        assert_eq!(editor.document.filename, b);
+        press(&mut editor, &backend, "<C-^>");
+        assert_eq!(editor.document.filename, a);
+
+        press(&mut editor, &backend, "dl:ls<CR>");
+        assert_eq!(
+            editor.status.text,
+            format!("1 %+ \"{}\" line 2, 2 #  \"{}\" line 1", a, b)
+        );
+
+        press(&mut editor, &backend, "<Esc>:b b.txt<CR>");
+        assert_eq!(editor.document.filename, b);
+        press(&mut editor, &backend, "<Esc>:b .txt<CR>");
+        assert_eq!(editor.status.text, "ERR: More than one match for .txt");
+        press(&mut editor, &backend, "<Esc>:b 1<CR>");
+        assert_eq!(editor.document.filename, a);
+        assert_eq!(text(&editor), "alpha\necond");
+    }
+
+    #[test]
+    fn buffers_are_opened_and_closed() {
+        let dir = TempDir::new("edit");
+        let (a, new) = (dir.path("a.txt"), dir.path("new.txt"));
+        fs::write(&a, "alpha\n").unwrap();
+        let (mut editor, backend) = open(120, 8, &[&a]);
+
+        press(&mut editor, &backend, &format!(":e {}<CR>", new));
+        assert_eq!(editor.document.filename, new);
+        assert_eq!(editor.buffer_id, 2);
+        assert_eq!(text(&editor), "");
+
+        // Going back to a buffer that's already open doesn't open it twice.
+        press(&mut editor, &backend, &format!("<Esc>:e {}<CR>", a));
+        assert_eq!(editor.buffer_id, 1);
+        assert_eq!(editor.buffers.len(), 1);
+
+        // The alternate buffer takes the place of a deleted one.
+        press(&mut editor, &backend, "<Esc>:bd<CR>");
+        assert_eq!(editor.document.filename, new);
+        assert!(editor.buffers.is_empty());
+        press(&mut editor, &backend, ":bd<CR>");
+        assert_eq!(editor.document.filename, "");
+        assert_eq!(editor.buffer_id, 3);
+    }
+
+    #[test]
+    fn windows_move_resize_and_close() {
+        let (mut editor, backend) = editor(40, 10);
+        // Heights of the windows from top to bottom, with their status lines.
+        let heights = |editor: &Editor| -> Vec<(usize, u16)> {
+            let rects = editor.layout.rects(editor.area());
+            rects.iter().map(|(id, rect)| (*id, rect.height)).collect()
+        };
+
+        press(&mut editor, &backend, "ione<CR>two<Esc><C-w>vggAx<Esc>");
+        assert_eq!(
+            backend.lines()[..2],
+            ["onex               |onex", "two                |two"]
+        );
+        let left = editor.window_id;
+
+        press(&mut editor, &backend, "<C-w>l<C-w>s");
+        assert_ne!(editor.window_id, left);
+        let bottom = heights(&editor)[2].0;
+        assert_eq!(heights(&editor)[1..], [(editor.window_id, 4), (bottom, 4)]);
+        assert_eq!(backend.lines()[4], "~                  |onex");
+
+        press(&mut editor, &backend, "<C-w>+");
+        assert_eq!(heights(&editor)[1..], [(editor.window_id, 5), (bottom, 3)]);
+        // Every window keeps a line of text and its status line.
+        press(&mut editor, &backend, "9<C-w>+");
+        assert_eq!(heights(&editor)[1..], [(editor.window_id, 6), (bottom, 2)]);
+        press(&mut editor, &backend, "<C-w>=<C-w>r");
+        assert_eq!(heights(&editor)[1..], [(bottom, 4), (editor.window_id, 4)]);
+
+        // The windows keep their proportions when the terminal gets smaller.
+        backend.resize(30, 8);
+        press(&mut editor, &backend, "");
+        assert_eq!(
+            heights(&editor),
+            [(left, 6), (bottom, 3), (editor.window_id, 3)]
+        );
+        assert_eq!(backend.lines()[1][..15], *"two           |");
+
+        press(&mut editor, &backend, "<C-w>c");
+        assert_eq!(heights(&editor), [(left, 6), (bottom, 6)]);
+        press(&mut editor, &backend, "<C-w>h<C-w>o");
+        assert_eq!(heights(&editor), [(left, 6)]);
+        assert_eq!(backend.lines()[..2], ["onex", "two"]);
+    }
+
+    #[test]
+    fn tab_pages_have_their_own_layout() {
+        let dir = TempDir::new("tabs");
+        let (a, b) = (dir.path("a"), dir.path("b"));
+        fs::write(&a, "alpha\n").unwrap();
+        fs::write(&b, "beta\n").unwrap();
+        let (mut editor, backend) = open(100, 8, &["-p", &a, &b]);
+        let windows = |editor: &Editor| editor.layout.rects(editor.area()).len();
+
+        // The tab line takes the first row, the tab page that is shown stands out.
+        press(&mut editor, &backend, "");
+        let tab_line = format!(" 1 {}  2 {} ", a, b);
+        assert_eq!(backend.lines()[0], tab_line.trim_end());
+        assert_eq!(backend.lines()[1], "alpha");
+        assert_eq!(backend.cell(1, 0).bg, STATUS_BAR_BG_COLOUR);
+        assert_eq!(
+            backend.cell(tab_line.len() as u16 - 2, 0).bg,
+            INACTIVE_STATUS_BAR_BG_COLOUR
+        );
+
+        press(&mut editor, &backend, "gt<C-w>v");
+        assert_eq!(editor.tab_index, 1);
+        assert_eq!(windows(&editor), 2);
+        assert!(backend.lines()[1].starts_with("beta  "));
+
+        press(&mut editor, &backend, ":tabnew<CR>");
+        assert_eq!(editor.tab_index, 2);
+        assert_eq!(editor.tab_labels()[2], " 3 [No Name] ");
+        assert_eq!(windows(&editor), 1);
+
+        press(&mut editor, &backend, "gTgT");
+        assert_eq!((editor.tab_index, windows(&editor)), (0, 1));
+        assert_eq!(text(&editor), "alpha");
+        press(&mut editor, &backend, "gT");
+        assert_eq!(editor.tab_index, 2);
+
+        // The other tab pages keep their windows when one is closed.
+        press(&mut editor, &backend, "gt:tabclose<CR>");
+        assert_eq!(
+            editor.tab_labels(),
+            [format!(" 1 {} ", b), " 2 [No Name] ".to_string()]
+        );
+        assert_eq!((editor.tab_index, windows(&editor)), (0, 2));
+
+        // Without other tab pages the tab line goes away.
+        press(&mut editor, &backend, "gt:tabclose<CR>");
+        assert!(editor.tabs.is_empty());
+        assert!(backend.lines()[0].starts_with("beta  "));
+    }
+
+    #[test]
+    fn pending_keys_wait_for_the_rest_of_a_command() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ione<CR>two<Esc>g");
+        assert_eq!(editor.pending.len(), 1);
+        assert_eq!(editor.cursor_position.y, 1);
+
+        press(&mut editor, &backend, "g");
+        assert!(editor.pending.is_empty());
+        assert_eq!(editor.cursor_position.y, 0);
+
+        // Without the second key in time, the first one is dropped.
+        editor.options.timeoutlen = 0;
+        press(&mut editor, &backend, "G<lt>");
+        assert!(editor.pending.is_empty());
+
+        editor.options.timeoutlen = 1000;
+        press(&mut editor, &backend, "<lt>");
+        assert_eq!(editor.pending.len(), 1);
+    }
+
+    #[test]
+    fn resizing_draws_the_screen_at_the_new_size() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ihello<Esc>");
+
+        backend.resize(30, 6);
+        press(&mut editor, &backend, "");
+        assert_eq!(
+            backend.lines(),
+            [
+                "hello",
+                "~",
+                "~",
+                "[No Name] [+]       1/1: 100%",
+                "Press CTRL + Q to QUIT.",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_cells_that_changed_are_drawn() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ihello<Esc>");
+        backend.take_printed();
+
+        press(&mut editor, &backend, "h");
+        assert_eq!(backend.take_printed(), 0);
+        assert_eq!(backend.cursor(), (4, 0));
+
+        press(&mut editor, &backend, "a");
+        backend.take_printed();
+        press(&mut editor, &backend, "!");
+        assert_eq!(backend.lines()[0], "hello!");
+        assert_eq!(backend.take_printed(), 1);
+    }
+
+    #[test]
+    fn scrolling_moves_the_lines_already_on_the_screen() {
+        let (mut editor, backend) = editor(40, 8);
+        let lines: Vec<String> = (1..=20)
+            .map(|n| format!("line {:02} of twenty", n))
+            .collect();
+        press(
+            &mut editor,
+            &backend,
+            &format!("i{}<Esc>gg", lines.join("<CR>")),
+        );
+        press(&mut editor, &backend, "jjjj");
+        backend.take_printed();
+
+        press(&mut editor, &backend, "j");
+        assert_eq!(backend.lines()[..5], lines[1..6]);
+        // The new line and a few characters of the status line.
+        assert!(backend.take_printed() < 2 * lines[0].len());
+
+        press(&mut editor, &backend, "G");
+        assert_eq!(backend.lines()[..5], lines[15..]);
+        press(&mut editor, &backend, "kkkkk");
+        assert_eq!(backend.lines()[..5], lines[14..19]);
+    }
+
+    #[test]
+    fn operators_act_on_motions() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ione two three<Esc>0dw");
+        assert_eq!(text(&editor), "two three");
+
+        press(&mut editor, &backend, "wd$");
+        assert_eq!(text(&editor), "two ");
+
+        press(&mut editor, &backend, "0cwsix<Esc>");
+        assert_eq!(text(&editor), "six ");
+
+        // The change and the text typed for it are undone together.
+        press(&mut editor, &backend, "u");
+        assert_eq!(text(&editor), "two ");
+
+        press(&mut editor, &backend, "0gUe");
+        assert_eq!(text(&editor), "TWO ");
+    }
+
+    #[test]
+    fn doubled_operators_act_on_lines() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ione<CR>two<CR>three<Esc>ggdd");
+        assert_eq!(text(&editor), "two\nthree");
+
+        press(&mut editor, &backend, ">>jdd");
+        assert_eq!(text(&editor), "    two");
+
+        press(&mut editor, &backend, "cc2<Esc>");
+        assert_eq!(text(&editor), "    2");
+
+        press(&mut editor, &backend, "A<CR>x<Esc>kdj");
+        assert_eq!(text(&editor), "");
+    }
+
+    #[test]
+    fn operators_act_on_text_objects() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ifoo(bar, baz)<Esc>di(");
+        assert_eq!(text(&editor), "foo()");
+
+        press(&mut editor, &backend, "0ciwx<Esc>");
+        assert_eq!(text(&editor), "x()");
+
+        press(&mut editor, &backend, "A<CR><CR>a<CR>b<Esc>>ip");
+        assert_eq!(text(&editor), "x()\n\n    a\n    b");
+
+        press(&mut editor, &backend, "gg=G");
+        assert_eq!(text(&editor), "x()\n\na\nb");
+    }
+
+    #[test]
+    fn counts_repeat_motions_and_operators() {
+        let (mut editor, backend) = editor(40, 10);
+        press(
+            &mut editor,
+            &backend,
+            "ione<CR>two<CR>three<CR>four<CR>five<Esc>gg3j",
+        );
+        assert_eq!(editor.cursor_position.y, 3);
+
+        // `G` and `gg` take the count as a line number, `0` is part of the count.
+        press(&mut editor, &backend, "2G");
+        assert_eq!(editor.cursor_position.y, 1);
+        press(&mut editor, &backend, "10G");
+        assert_eq!(editor.cursor_position.y, 4);
+        press(&mut editor, &backend, "3gg");
+        assert_eq!(editor.cursor_position.y, 2);
+
+        press(&mut editor, &backend, "gg2dd");
+        assert_eq!(text(&editor), "three\nfour\nfive");
+
+        press(&mut editor, &backend, "Gd2k");
+        assert_eq!(text(&editor), "");
+
+        // The counts in front of an operator and its motion are multiplied.
+        press(&mut editor, &backend, "ia b c d e f g h<Esc>02d3w");
+        assert_eq!(text(&editor), "g h");
+
+        press(&mut editor, &backend, "$d0");
+        assert_eq!(text(&editor), "h");
+    }
+
+    #[test]
+    fn yanked_text_is_put_back() {
+        let (mut editor, backend) = editor(40, 8);
+        press(&mut editor, &backend, "ione<CR>two<Esc>ggyyp");
+        assert_eq!(text(&editor), "one\none\ntwo");
+        assert_eq!(editor.cursor_position.y, 1);
+
+        // The last line doesn't end with a newline, the lines still go below it.
+        press(&mut editor, &backend, "GyyP2p");
+        assert_eq!(text(&editor), "one\none\ntwo\ntwo\ntwo\ntwo");
+
+        press(&mut editor, &backend, "ggdGihello world<Esc>0yw$p");
+        assert_eq!(text(&editor), "hello worldhello ");
+
+        press(&mut editor, &backend, "0P");
+        assert_eq!(text(&editor), "hello hello worldhello ");
+        assert_eq!(editor.cursor_position.x, 5);
+    }
+
+    #[test]
+    fn deletes_and_named_registers() {
+        let (mut editor, backend) = editor(40, 8);
+        press(
+            &mut editor,
+            &backend,
+            "ione<CR>two<CR>three<Esc>gg\"ayyj\"Ayy",
+        );
+        assert_eq!(editor.registers.get('a').unwrap().text, "one\ntwo\n");
+
+        // Deleted lines go to `1` and push the older ones to `2`, smaller deletes go to `-`.
+        press(&mut editor, &backend, "ddddidone <Esc>0dw");
+        assert_eq!(text(&editor), "one");
+        assert_eq!(editor.registers.get('1').unwrap().text, "three\n");
+        assert_eq!(editor.registers.get('2').unwrap().text, "two\n");
+        assert_eq!(editor.registers.get('-').unwrap().text, "done ");
+
+        // The black hole register leaves the others alone.
+        press(&mut editor, &backend, "\"_dd\"ap");
+        assert_eq!(text(&editor), "\none\ntwo");
+        assert_eq!(editor.registers.get('"').unwrap().text, "done ");
+
+        press(&mut editor, &backend, "\".P:reg a.<CR>");
+        assert_eq!(text(&editor), "\ndone one\ntwo");
+        assert_eq!(editor.status.text, "l \"a one^Jtwo^J, c \". done ");
+    }
+
+    #[test]
+    fn visual_mode_selects_for_operators() {
+        let (mut editor, backend) = editor(40, 8);
+        press(
+            &mut editor,
+            &backend,
+            "ione two<CR>three four<CR>five<Esc>gg0vl",
+        );
+        assert!(backend.lines()[5].starts_with("MODE: VISUAL |"));
+        assert_eq!(backend.cell(1, 0).bg, SELECTION_BG_COLOUR);
+        assert_eq!(backend.cell(2, 0).bg, Color::Reset);
+
+        press(&mut editor, &backend, "ld");
+        assert_eq!(text(&editor), " two\nthree four\nfive");
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(backend.cell(0, 0).bg, Color::Reset);
+
+        press(&mut editor, &backend, "Vjy");
+        assert_eq!(
+            editor.registers.get('"').unwrap().text,
+            " two\nthree four\n"
+        );
+
+        // `gv` selects the lines again, `o` goes to the other end.
+        press(&mut editor, &backend, "gvo");
+        assert!(backend.lines()[5].starts_with("MODE: V-LINE |"));
+        assert_eq!(editor.cursor_position.y, 0);
+        assert_eq!(editor.anchor.y, 1);
+
+        press(&mut editor, &backend, "<Esc>ggwviwU");
+        assert_eq!(text(&editor), " TWO\nthree four\nfive");
+
+        // A block changes the same columns of every line.
+        press(&mut editor, &backend, "gg0<C-v>jjl");
+        assert!(backend.lines()[5].starts_with("MODE: V-BLOCK |"));
+        assert_eq!(backend.cell(2, 1).bg, Color::Reset);
+        press(&mut editor, &backend, "y");
+        assert_eq!(editor.registers.get('"').unwrap().text, " T\nth\nfi");
+        press(&mut editor, &backend, "gvcXY<Esc>");
+        assert_eq!(text(&editor), "XYWO\nXYree four\nXYve");
+
+        press(&mut editor, &backend, "u");
+        assert_eq!(text(&editor), " TWO\nthree four\nfive");
+    }
+
+    #[test]
+    fn blocks_are_put_as_columns() {
+        let (mut editor, backend) = editor(40, 8);
+        press(
+            &mut editor,
+            &backend,
+            "iabcd<CR>efgh<CR>ijkl<Esc>gg0l<C-v>jly",
+        );
+        let register = editor.registers.get('"').unwrap();
+        assert_eq!(register.text, "bc\nfg");
+        assert!(register.blockwise);
+
+        // Past the end of the document the block gets lines of its own.
+        press(&mut editor, &backend, "G$p");
+        assert_eq!(text(&editor), "abcd\nefgh\nijklbc\n    fg");
+        assert_eq!((editor.cursor_position.x, editor.cursor_position.y), (4, 2));
+        press(&mut editor, &backend, "u");
+        assert_eq!(text(&editor), "abcd\nefgh\nijkl");
+
+        press(&mut editor, &backend, "gg0P");
+        assert_eq!(text(&editor), "bcabcd\nfgefgh\nijkl");
+        assert_eq!((editor.cursor_position.x, editor.cursor_position.y), (0, 0));
+        press(&mut editor, &backend, "ugg02p");
+        assert_eq!(text(&editor), "abcbcbcd\nefgfgfgh\nijkl");
+
+        press(&mut editor, &backend, ":registers \"<CR>");
+        assert_eq!(editor.status.text, "b \"\" bc^Jfg");
+
+        // Short lines are filled up, so the text after the block stays lined up.
+        press(
+            &mut editor,
+            &backend,
+            "ggdGiabcde<CR>x<CR>abcde<Esc>G0l<C-v>kkly0P",
+        );
+        assert_eq!(editor.registers.get('"').unwrap().text, "bc\n\nbc");
+        assert_eq!(text(&editor), "bcabcde\n  x\nbcabcde");
+    }
+
+    #[test]
+    fn clipboard_registers_use_the_provider() {
+        let path = std::env::temp_dir().join(format!("hecto-clipboard-{}", std::process::id()));
+        let file = path.to_string_lossy().to_string();
+        let (mut editor, backend) = editor(40, 8);
+        editor.clipboard = Provider::Command {
+            copy: vec!["cp".to_string(), "/dev/stdin".to_string(), file.clone()],
+            paste: vec!["cat".to_string(), file],
+        };
+
+        press(&mut editor, &backend, "ione<CR>two<Esc>gg\"+yy");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        press(&mut editor, &backend, "G\"+p");
+        assert_eq!(text(&editor), "one\ntwo\none");
+
+        // Text another program copied goes in as it is.
+        fs::write(&path, "three").unwrap();
+        press(&mut editor, &backend, "gg0\"*P");
+        assert_eq!(text(&editor), "threeone\ntwo\none");
+        fs::remove_file(&path).unwrap();
+
+        // The terminal can't give the clipboard back, what was copied last is put instead.
+        press(&mut editor, &backend, ":set clipboard=osc52<CR>\"+dd\"+p");
+        assert_eq!(
+            backend.copied(),
+            Some((Selection::Clipboard, "threeone\n".to_string()))
+        );
+        assert_eq!(text(&editor), "two\nthreeone\none");
     }
 }
@@ -0,0 +1,181 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    // True when some bytes could not be decoded and were replaced.
+    pub lossy: bool,
+}
+
+/// UTF-16 without a BOM is recognised by the zero bytes in front of or behind every ascii character.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let pairs = bytes.len() / 2;
+    let even_zeros = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+
+    if odd_zeros * 10 >= pairs * 4 && even_zeros == 0 {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 >= pairs * 4 && odd_zeros == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Figures out the encoding of a file. A BOM is trusted first, then UTF-16 is sniffed
+/// (ascii in UTF-16 is valid UTF-8 too), then UTF-8 is tried, and anything else falls back
+/// to Windows-1252 (a superset of Latin-1), which decodes every byte.
+pub fn decode(bytes: &[u8]) -> Decoded {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return decode_with(bytes, encoding, true);
+    }
+
+    let encoding = match sniff_utf16(bytes) {
+        Some(encoding) => encoding,
+        None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        None => WINDOWS_1252,
+    };
+    decode_with(bytes, encoding, false)
+}
+
+fn decode_with(bytes: &[u8], encoding: &'static Encoding, bom: bool) -> Decoded {
+    let (text, lossy) = if bom {
+        let (text, lossy) = encoding.decode_with_bom_removal(bytes);
+        (text.into_owned(), lossy)
+    } else {
+        let (text, lossy) = encoding.decode_without_bom_handling(bytes);
+        (text.into_owned(), lossy)
+    };
+
+    Decoded {
+        text,
+        encoding,
+        bom,
+        lossy,
+    }
+}
+
+/// Turns text back into the bytes of a file, fails if a character doesn't exist in the encoding.
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, String> {
+    // encoding_rs only decodes UTF-16, so it's encoded by hand.
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little_endian = encoding == UTF_16LE;
+        let mut bytes = vec![];
+        let units = bom.then_some(0xfeff).into_iter().chain(text.encode_utf16());
+        for unit in units {
+            if little_endian {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+
+        return Ok(bytes);
+    }
+
+    let mut bytes = if bom && encoding == UTF_8 {
+        vec![0xef, 0xbb, 0xbf]
+    } else {
+        vec![]
+    };
+
+    let (encoded, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(format!(
+            "Some characters can't be written as {}",
+            encoding.name()
+        ));
+    }
+
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+/// Looks up an encoding by a name like `utf-8`, `latin1` or `utf-16le`.
+pub fn for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_tried_first() {
+        let decoded = decode("héllo".as_bytes());
+        assert_eq!(decoded.text, "héllo");
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(!decoded.bom && !decoded.lossy);
+
+        let decoded = decode(b"");
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(!decoded.lossy);
+    }
+
+    #[test]
+    fn a_bom_decides_the_encoding() {
+        let decoded = decode(b"\xef\xbb\xbfhi");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("hi", UTF_8));
+        assert!(decoded.bom && !decoded.lossy);
+
+        let decoded = decode(b"\xff\xfeh\0i\0");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("hi", UTF_16LE));
+        assert!(decoded.bom && !decoded.lossy);
+
+        let decoded = decode(b"\xfe\xff\0h\0i");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("hi", UTF_16BE));
+
+        // Bytes that don't fit the encoding of the BOM are replaced.
+        let decoded = decode(b"\xef\xbb\xbfh\xffi");
+        assert_eq!(decoded.text, "h\u{fffd}i");
+        assert!(decoded.lossy);
+    }
+
+    #[test]
+    fn utf16_without_a_bom_is_sniffed() {
+        let decoded = decode(b"h\0i\0\n\0");
+        assert_eq!(
+            (decoded.text.as_str(), decoded.encoding),
+            ("hi\n", UTF_16LE)
+        );
+        assert!(!decoded.bom && !decoded.lossy);
+
+        let decoded = decode(b"\0h\0i\0\n");
+        assert_eq!(
+            (decoded.text.as_str(), decoded.encoding),
+            ("hi\n", UTF_16BE)
+        );
+
+        // An odd amount of bytes can't be UTF-16, it's UTF-8 with zero bytes in it.
+        let decoded = decode(b"h\0i\0\n");
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(!decoded.lossy);
+    }
+
+    #[test]
+    fn anything_else_is_windows_1252() {
+        let decoded = decode(b"caf\xe9 \x80");
+        assert_eq!(decoded.text, "café €");
+        assert_eq!(decoded.encoding, WINDOWS_1252);
+        // Every byte means something in Windows-1252, the file can be edited.
+        assert!(!decoded.lossy);
+
+        // Saving it again gives back the same bytes.
+        let encoded = encode(&decoded.text, decoded.encoding, decoded.bom).unwrap();
+        assert_eq!(encoded, b"caf\xe9 \x80");
+    }
+
+    #[test]
+    fn encoding_writes_the_bom_back() {
+        assert_eq!(encode("hi", UTF_8, true).unwrap(), b"\xef\xbb\xbfhi");
+        assert_eq!(encode("hi", UTF_16LE, true).unwrap(), b"\xff\xfeh\0i\0");
+        assert_eq!(encode("hi", UTF_16BE, false).unwrap(), b"\0h\0i");
+        assert!(encode("日本", WINDOWS_1252, false).is_err());
+        assert_eq!(for_label("latin1"), Some(WINDOWS_1252));
+    }
+}
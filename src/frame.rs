@@ -0,0 +1,170 @@
+use crossterm::style::Color;
+use tui::style::Modifier;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// One character on the screen, along with how it looks. The column after a wide character
+/// holds an empty symbol.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub symbol: String,
+    pub fg: Color,
+    pub bg: Color,
+    pub modifier: Modifier,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            fg: Color::Reset,
+            bg: Color::Reset,
+            modifier: Modifier::empty(),
+        }
+    }
+}
+
+/// What the screen should look like. The editor draws into a frame like it would into the
+/// terminal, and only the cells that differ from the previous frame are sent to the terminal.
+#[derive(Clone)]
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<Cell>,
+    // Where the next text is printed, and where the cursor is shown once the frame is done.
+    pub cursor: (u16, u16),
+    pub cursor_visible: bool,
+    fg: Color,
+    bg: Color,
+}
+
+impl Frame {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); usize::from(width) * usize::from(height)],
+            cursor: (0, 0),
+            cursor_visible: true,
+            fg: Color::Reset,
+            bg: Color::Reset,
+        }
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        usize::from(y) * usize::from(self.width) + usize::from(x)
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+    }
+
+    pub fn clear_row(&mut self, y: u16) {
+        if y < self.height {
+            let start = self.index(0, y);
+            let end = start + usize::from(self.width);
+            self.cells[start..end].fill(Cell::default());
+        }
+    }
+
+    pub fn set_fg(&mut self, color: Color) {
+        self.fg = color;
+    }
+
+    pub fn set_bg(&mut self, color: Color) {
+        self.bg = color;
+    }
+
+    /// Puts a cell on the screen, nothing happens outside of it.
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if x < self.width && y < self.height {
+            let index = self.index(x, y);
+            self.cells[index] = cell;
+        }
+    }
+
+    /// Prints text at the cursor in the current colours, and moves the cursor past it.
+    /// Text that doesn't fit on the row is cut off.
+    pub fn print(&mut self, text: &str) {
+        for grapheme in text.graphemes(true) {
+            let (x, y) = self.cursor;
+            let width = grapheme.width() as u16;
+
+            // Like a terminal, characters without a width go with the one before them.
+            if width == 0 {
+                if x > 0 && x <= self.width && y < self.height {
+                    let index = self.index(x - 1, y);
+                    self.cells[index].symbol.push_str(grapheme);
+                }
+                continue;
+            }
+
+            let (fg, bg) = (self.fg, self.bg);
+            let cell = |symbol: &str| Cell {
+                symbol: symbol.to_string(),
+                fg,
+                bg,
+                modifier: Modifier::empty(),
+            };
+
+            // Half of a wide character can't be shown, a space takes its place.
+            if x.saturating_add(width) > self.width {
+                self.set(x, y, cell(" "));
+            } else {
+                self.set(x, y, cell(grapheme));
+                for column in 1..width {
+                    self.set(x + column, y, cell(""));
+                }
+            }
+            self.cursor = (x.saturating_add(width), y);
+        }
+    }
+
+    /// Moves the rows from `top` up to `bottom` up by `lines`, or down if it's negative, like
+    /// a terminal does when it scrolls a region. The rows that are left over are blank.
+    pub fn scroll(&mut self, top: u16, bottom: u16, lines: i32) {
+        let bottom = bottom.min(self.height);
+        if top >= bottom {
+            return;
+        }
+
+        let start = self.index(0, top);
+        let end = self.index(0, bottom);
+        let region = &mut self.cells[start..end];
+        let shift = (lines.unsigned_abs() as usize * usize::from(self.width)).min(region.len());
+
+        if lines > 0 {
+            region.rotate_left(shift);
+            let blank = region.len() - shift;
+            region[blank..].fill(Cell::default());
+        } else {
+            region.rotate_right(shift);
+            region[..shift].fill(Cell::default());
+        }
+    }
+
+    /// The cells that have to be drawn for the screen to go from `previous` to this frame,
+    /// along with where they go. Both frames need to have the same size.
+    pub fn diff<'a>(&'a self, previous: &Frame) -> Vec<(u16, u16, &'a Cell)> {
+        let mut updates = Vec::new();
+        // Cells covered by a wide character that was drawn, or by one that was replaced.
+        let mut to_skip = 0;
+        let mut invalidated = 0;
+
+        for (i, (current, previous)) in self.cells.iter().zip(&previous.cells).enumerate() {
+            if (current != previous || invalidated > 0) && to_skip == 0 {
+                let x = (i % usize::from(self.width)) as u16;
+                let y = (i / usize::from(self.width)) as u16;
+                updates.push((x, y, current));
+            }
+
+            let current_width = current.symbol.width();
+            to_skip = current_width.saturating_sub(1);
+            let affected = current_width.max(previous.symbol.width());
+            invalidated = affected.max(invalidated).saturating_sub(1);
+        }
+
+        updates
+    }
+}
@@ -1,13 +1,42 @@
+use std::cell::OnceCell;
 use std::cmp;
 use std::fmt;
 
+use super::width::grapheme_width;
+
 use unicode_segmentation::UnicodeSegmentation;
 
+// Smallest gap that gets opened up when the buffer runs out of room.
+const MIN_GAP: usize = 16;
+// Filler for the unused slots inside the gap, never shown to the user.
+const GAP_CHAR: char = '\0';
+
+/// A line of text stored as `[before gap][gap][after gap]`.
+///
+/// `cur_pos` is where the gap starts, and `len` is the amount of real characters.
+/// Everything in `chs[cur_pos..cur_pos + gap_len()]` is garbage.
+///
+/// The cursor moves over graphemes, not characters. As long as a line only holds ascii
+/// the two are the same, so `non_ascii` is tracked to skip segmenting those lines.
+/// `tabs` is tracked for the same reason, a tab is one grapheme but can be many columns.
+/// Other lines are segmented once, `graphemes` keeps the result until the next edit.
 #[derive(Default, Clone)]
 pub struct GapBuffer {
     pub cur_pos: usize,
     pub chs: Vec<char>,
     pub len: usize,
+    non_ascii: usize,
+    tabs: usize,
+    graphemes: OnceCell<Graphemes>,
+}
+
+/// Where the graphemes of a line start and how wide they are.
+#[derive(Clone)]
+struct Graphemes {
+    // The index of the first character of every grapheme, followed by the length of the line.
+    starts: Vec<usize>,
+    // `None` for tabs, their width depends on the column they start at.
+    widths: Vec<Option<usize>>,
 }
 
 impl fmt::Display for GapBuffer {
@@ -19,121 +48,463 @@ impl fmt::Display for GapBuffer {
 
 impl From<String> for GapBuffer {
     fn from(s: String) -> Self {
-        let mut buffer = vec![];
-        for ch in s.chars() {
-            buffer.push(ch);
-        }
+        Self::new(s.as_str())
+    }
+}
 
+impl GapBuffer {
+    pub fn new(chs: &str) -> Self {
+        let buffer: Vec<char> = chs.chars().collect();
+        Self::from_chars(buffer)
+    }
+
+    fn from_chars(buffer: Vec<char>) -> Self {
         let len = buffer.len();
+        let non_ascii = buffer.iter().filter(|c| !c.is_ascii()).count();
+        let tabs = buffer.iter().filter(|c| **c == '\t').count();
 
+        // The gap starts at the end of the line, it'll be moved to the cursor on the first edit.
         Self {
-            cur_pos: 0,
+            cur_pos: len,
             chs: buffer,
             len,
+            non_ascii,
+            tabs,
+            graphemes: OnceCell::new(),
         }
     }
-}
 
-impl GapBuffer {
-    pub fn update_len(&mut self) {
-        self.len = self.chs.len()
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.chs.is_empty()
+    fn gap_len(&self) -> usize {
+        self.chs.len() - self.len
     }
 
-    pub fn new(chs: &str) -> Self {
-        let mut buffer = vec![];
-        for ch in chs.chars() {
-            buffer.push(ch);
+    fn gap_end(&self) -> usize {
+        self.cur_pos + self.gap_len()
+    }
+
+    /// The characters in front of the gap.
+    pub fn before(&self) -> &[char] {
+        &self.chs[..self.cur_pos]
+    }
+
+    /// The characters behind the gap.
+    pub fn after(&self) -> &[char] {
+        &self.chs[self.gap_end()..]
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.before().iter().chain(self.after().iter()).copied()
+    }
+
+    pub fn get(&self, x: usize) -> Option<char> {
+        if x < self.cur_pos {
+            self.chs.get(x).copied()
+        } else if x < self.len {
+            self.chs.get(x + self.gap_len()).copied()
+        } else {
+            None
         }
+    }
 
-        let len = buffer.len();
+    /// Moves the start of the gap to `x`, only the characters between the old and new position are shifted.
+    fn move_gap(&mut self, x: usize) {
+        let x = cmp::min(x, self.len);
+        let gap_len = self.gap_len();
 
-        Self {
-            cur_pos: 0,
-            chs: buffer,
-            len,
+        if x < self.cur_pos {
+            // [ab|cd____ef] -> [ab____cdef]
+            self.chs.copy_within(x..self.cur_pos, x + gap_len);
+        } else if x > self.cur_pos {
+            // [ab____cdef] -> [abcd____ef]
+            let gap_end = self.gap_end();
+            self.chs
+                .copy_within(gap_end..gap_end + (x - self.cur_pos), self.cur_pos);
         }
+
+        self.cur_pos = x;
     }
 
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.line().len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
+    /// Makes sure there is room for at least `needed` characters inside of the gap.
+    fn grow_gap(&mut self, needed: usize) {
+        let gap_len = self.gap_len();
+        if gap_len >= needed {
+            return;
+        }
+
+        // Doubling keeps repeated inserts amortised O(1).
+        let extra = cmp::max(cmp::max(needed - gap_len, self.len), MIN_GAP);
+        let gap_end = self.gap_end();
+        self.chs
+            .splice(gap_end..gap_end, std::iter::repeat_n(GAP_CHAR, extra));
+    }
+
+    fn graphemes(&self) -> &Graphemes {
+        self.graphemes.get_or_init(|| {
+            let mut starts = Vec::new();
+            let mut widths = Vec::new();
+            let mut index = 0;
+            for grapheme in self.line().graphemes(true) {
+                starts.push(index);
+                widths.push(if grapheme == "\t" {
+                    None
+                } else {
+                    Some(grapheme_width(grapheme))
+                });
+                index += grapheme.chars().count();
+            }
+            starts.push(index);
+
+            Graphemes { starts, widths }
+        })
+    }
 
-        for grapheme in self.line()[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-        {
-            result.push_str(grapheme);
+    /// Amount of graphemes in the line, this is how far the cursor can move.
+    pub fn len_graphemes(&self) -> usize {
+        if self.non_ascii == 0 {
+            self.len
+        } else {
+            self.graphemes().widths.len()
         }
+    }
 
-        result
+    /// Converts the index of a grapheme into the index of its first character.
+    pub fn char_index(&self, x: usize) -> usize {
+        if self.non_ascii == 0 {
+            return cmp::min(x, self.len);
+        }
+
+        let starts = &self.graphemes().starts;
+        starts[cmp::min(x, starts.len() - 1)]
     }
 
-    pub fn line(&self) -> String {
-        let mut string = "".to_string();
-        self.chs.iter().for_each(|x| {
-            string = format!("{}{}", string, x);
-        });
+    /// Converts the index of a character into the index of the grapheme it is part of.
+    pub fn grapheme_index(&self, index: usize) -> usize {
+        if self.non_ascii == 0 {
+            return cmp::min(index, self.len);
+        }
 
-        string
+        let starts = &self.graphemes().starts;
+        let graphemes = starts.partition_point(|start| *start <= index) - 1;
+        cmp::min(graphemes, starts.len() - 1)
+    }
+
+    /// Lines without tabs or wide characters have one column per character.
+    fn is_simple(&self) -> bool {
+        self.non_ascii == 0 && self.tabs == 0
     }
 
-    pub fn display(&self) {
-        let mut indexes = "".to_string();
-        for i in 0..self.chs.len() {
-            let nums = format!("{}", i);
-            indexes.push_str(nums.as_str());
+    /// Calls `f` with the index of every grapheme, the column it starts at and how wide it is
+    /// drawn. Tabs stretch to the next tab stop. Stops early once `f` returns false.
+    fn each_grapheme(&self, tabstop: usize, mut f: impl FnMut(usize, usize, usize) -> bool) {
+        let tabstop = cmp::max(tabstop, 1);
+        let mut column = 0;
+        for (x, width) in self.graphemes().widths.iter().enumerate() {
+            let width = width.unwrap_or(tabstop - column % tabstop);
+            if !f(x, column, width) {
+                break;
+            }
+            column += width;
         }
+    }
 
-        let mut output = "".to_string();
-        for buffer in self.chs.clone() {
-            output.push_str(buffer.clone().to_string().as_str());
+    /// The terminal column grapheme `x` starts at.
+    pub fn column(&self, x: usize, tabstop: usize) -> usize {
+        if self.is_simple() {
+            return cmp::min(x, self.len);
         }
 
-        println!("{}\n{}\n", indexes, output);
+        // Grapheme `x` starts where the one in front of it ends.
+        let mut result = 0;
+        self.each_grapheme(tabstop, |index, column, width| {
+            if index == x {
+                return false;
+            }
+            result = column + width;
+            true
+        });
+
+        result
     }
 
-    pub fn insert(&mut self, ch: char, x: usize) {
-        if x >= self.chs.len() {
-            self.chs.push(ch);
-        } else if x == 0 {
-            self.chs.insert(0, ch);
-        } else {
-            self.chs.insert(x, ch);
+    /// The grapheme that is drawn at terminal column `column`.
+    pub fn grapheme_at_column(&self, column: usize, tabstop: usize) -> usize {
+        if self.is_simple() {
+            return cmp::min(column, self.len);
         }
 
-        self.update_len();
+        let mut graphemes = 0;
+        self.each_grapheme(tabstop, |_, start, width| {
+            if start + width > column {
+                return false;
+            }
+            graphemes += 1;
+            true
+        });
+
+        graphemes
     }
 
-    pub fn insert_newline(&mut self) {
-        self.chs = vec!['\n'];
+    /// Renders the part of the line that is visible between the columns `start` and `end`.
+    /// Tabs and wide graphemes cut off by the edges are replaced by spaces.
+    pub fn render(&self, start: usize, end: usize, tabstop: usize) -> String {
+        if self.is_simple() {
+            let end = cmp::min(end, self.len);
+            let start = cmp::min(start, end);
+            return self.chars().skip(start).take(end - start).collect();
+        }
+
+        let starts = &self.graphemes().starts;
+        let mut result = String::new();
+        self.each_grapheme(tabstop, |x, column, width| {
+            if column >= end {
+                return false;
+            }
+
+            let is_tab = self.get(starts[x]) == Some('\t');
+            if !is_tab && column >= start && column + width <= end {
+                result.extend((starts[x]..starts[x + 1]).filter_map(|index| self.get(index)));
+            } else if column + width > start {
+                let visible = cmp::min(column + width, end) - cmp::max(column, start);
+                result.push_str(&" ".repeat(visible));
+            }
+            true
+        });
+
+        result
     }
 
-    pub fn enter(&mut self) {}
+    pub fn line(&self) -> String {
+        let mut string = String::with_capacity(self.len);
+        string.extend(self.chars());
+
+        string
+    }
+
+    pub fn insert(&mut self, ch: char, x: usize) {
+        self.graphemes.take();
+        self.move_gap(x);
+        self.grow_gap(1);
+
+        self.chs[self.cur_pos] = ch;
+        self.cur_pos += 1;
+        self.len += 1;
+        if !ch.is_ascii() {
+            self.non_ascii += 1;
+        } else if ch == '\t' {
+            self.tabs += 1;
+        }
+    }
 
     pub fn delete(&mut self, x: usize) {
-        let len = self.chs.len();
-        if x >= len {
-            self.chs.pop();
-        } else {
-            self.chs.remove(x);
+        if self.is_empty() {
+            return;
+        }
+
+        let x = cmp::min(x, self.len - 1);
+        self.graphemes.take();
+        self.move_gap(x);
+
+        // The character right after the gap gets swallowed by it.
+        let gap_end = self.gap_end();
+        if !self.chs[gap_end].is_ascii() {
+            self.non_ascii -= 1;
+        } else if self.chs[gap_end] == '\t' {
+            self.tabs -= 1;
         }
+        self.chs[gap_end] = GAP_CHAR;
+        self.len -= 1;
+    }
 
-        self.update_len();
+    /// Moves every character of `other` to the end of this buffer.
+    pub fn append(&mut self, other: &GapBuffer) {
+        self.graphemes.take();
+        self.move_gap(self.len);
+        self.chs.truncate(self.cur_pos);
+        self.chs.extend(other.chars());
+        self.len = self.chs.len();
+        self.cur_pos = self.len;
+        self.non_ascii += other.non_ascii;
+        self.tabs += other.tabs;
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-        let start: Vec<char> = self.chs.iter().take(at).copied().collect();
-        let end: Vec<char> = self.chs.iter().skip(at).copied().collect();
-        let end: String = end.iter().collect();
+        self.graphemes.take();
+        self.move_gap(at);
+
+        let end = Self::from_chars(self.after().to_vec());
+        self.chs.truncate(self.cur_pos);
+        self.len = self.cur_pos;
+        self.non_ascii -= end.non_ascii;
+        self.tabs -= end.tabs;
+
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_and_deletes_at_the_gap() {
+        let mut buffer = GapBuffer::new("hllo");
+        buffer.insert('e', 1);
+        assert_eq!(buffer.line(), "hello");
+        assert_eq!(buffer.cur_pos, 2);
+
+        // Typing on goes into the gap without moving it.
+        buffer.insert('y', 2);
+        assert_eq!(buffer.line(), "heyllo");
+        assert_eq!(buffer.cur_pos, 3);
+
+        buffer.delete(2);
+        assert_eq!(buffer.line(), "hello");
+        assert_eq!(buffer.cur_pos, 2);
+        assert_eq!(buffer.len, 5);
+
+        // Past the end the last character goes.
+        buffer.delete(10);
+        assert_eq!(buffer.line(), "hell");
+
+        let mut empty = GapBuffer::new("");
+        empty.delete(0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn moving_the_gap_keeps_the_text() {
+        let mut buffer = GapBuffer::new("abcdef");
+        buffer.insert('X', 0);
+        buffer.insert('Y', 7);
+        buffer.insert('-', 3);
+        assert_eq!(buffer.line(), "Xab-cdefY");
+        assert_eq!(buffer.before(), ['X', 'a', 'b', '-']);
+        assert_eq!(buffer.after(), ['c', 'd', 'e', 'f', 'Y']);
+
+        let chars: Vec<Option<char>> = (0..10).map(|x| buffer.get(x)).collect();
+        let expected: Vec<Option<char>> = "Xab-cdefY".chars().map(Some).chain([None]).collect();
+        assert_eq!(chars, expected);
+
+        buffer.delete(0);
+        buffer.delete(7);
+        assert_eq!(buffer.line(), "ab-cdef");
+    }
+
+    #[test]
+    fn the_gap_grows_when_it_runs_out() {
+        let mut buffer = GapBuffer::new("");
+        buffer.insert('a', 0);
+        let capacity = buffer.chs.len();
+        assert_eq!(capacity, MIN_GAP);
+
+        // The gap isn't grown again until it's full.
+        for x in 1..MIN_GAP {
+            buffer.insert('a', x);
+        }
+        assert_eq!(buffer.chs.len(), capacity);
+
+        for x in MIN_GAP..100 {
+            buffer.insert('b', x);
+        }
+        assert_eq!(buffer.len, 100);
+        assert_eq!(
+            buffer.line(),
+            "a".repeat(MIN_GAP) + &"b".repeat(100 - MIN_GAP)
+        );
+        // It doubles, so the buffer is never much more than twice the text.
+        assert!(buffer.chs.len() <= 2 * 100 + MIN_GAP);
+    }
+
+    #[test]
+    fn splits_and_appends_around_the_gap() {
+        let mut buffer = GapBuffer::new("hello world");
+        buffer.insert('!', 5);
+        let mut end = buffer.split(7);
+        assert_eq!(buffer.line(), "hello! ");
+        assert_eq!(end.line(), "world");
+
+        end.insert('?', 5);
+        buffer.append(&end);
+        assert_eq!(buffer.line(), "hello! world?");
+        assert_eq!(buffer.len, 13);
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let buffer = GapBuffer::new("a日本b");
+        assert_eq!(buffer.len_graphemes(), 4);
+        let columns: Vec<usize> = (0..=4).map(|x| buffer.column(x, 4)).collect();
+        assert_eq!(columns, [0, 1, 3, 5, 6]);
+
+        // Both halves of a wide character belong to it.
+        let graphemes: Vec<usize> = (0..7).map(|c| buffer.grapheme_at_column(c, 4)).collect();
+        assert_eq!(graphemes, [0, 1, 1, 2, 2, 3, 4]);
+
+        assert_eq!(buffer.render(0, 6, 4), "a日本b");
+        assert_eq!(buffer.render(2, 6, 4), " 本b");
+        assert_eq!(buffer.render(0, 4, 4), "a日 ");
+    }
+
+    #[test]
+    fn combining_characters_belong_to_the_one_in_front() {
+        let buffer = GapBuffer::new("cafe\u{301}s");
+        assert_eq!(buffer.len, 6);
+        assert_eq!(buffer.len_graphemes(), 5);
+
+        let indices: Vec<usize> = (0..=5).map(|x| buffer.char_index(x)).collect();
+        assert_eq!(indices, [0, 1, 2, 3, 5, 6]);
+        let graphemes: Vec<usize> = (0..=6).map(|c| buffer.grapheme_index(c)).collect();
+        assert_eq!(graphemes, [0, 1, 2, 3, 3, 4, 5]);
+
+        let columns: Vec<usize> = (0..=5).map(|x| buffer.column(x, 4)).collect();
+        assert_eq!(columns, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(buffer.render(3, 5, 4), "e\u{301}s");
+    }
+
+    #[test]
+    fn tabs_stretch_to_the_next_tab_stop() {
+        let buffer = GapBuffer::new("a\tbc\td");
+        let columns: Vec<usize> = (0..=6).map(|x| buffer.column(x, 4)).collect();
+        assert_eq!(columns, [0, 1, 4, 5, 6, 8, 9]);
+        let columns: Vec<usize> = (0..=6).map(|x| buffer.column(x, 8)).collect();
+        assert_eq!(columns, [0, 1, 8, 9, 10, 16, 17]);
+
+        let graphemes: Vec<usize> = (0..10).map(|c| buffer.grapheme_at_column(c, 4)).collect();
+        assert_eq!(graphemes, [0, 1, 1, 1, 2, 3, 4, 4, 5, 6]);
+
+        assert_eq!(buffer.render(0, 9, 4), "a   bc  d");
+        assert_eq!(buffer.render(2, 5, 4), "  b");
+
+        // A tab after a wide character lines up with the same tab stops.
+        let buffer = GapBuffer::new("日\tx");
+        assert_eq!(buffer.column(2, 4), 4);
+    }
+
+    #[test]
+    fn edits_forget_the_old_graphemes() {
+        let mut buffer = GapBuffer::new("日本");
+        assert_eq!(buffer.column(2, 4), 4);
+
+        buffer.insert('\u{301}', 1);
+        buffer.insert('x', 0);
+        assert_eq!(buffer.len_graphemes(), 3);
+        assert_eq!(buffer.column(3, 4), 5);
+
+        buffer.delete(0);
+        buffer.delete(1);
+        assert_eq!(buffer.len_graphemes(), 2);
+
+        let end = buffer.split(1);
+        assert_eq!(buffer.len_graphemes(), 1);
+        assert_eq!(buffer.column(1, 4), 2);
 
-        self.chs = start;
-        self.update_len();
-        Self::from(end)
+        buffer.append(&GapBuffer::new("\tz"));
+        buffer.append(&end);
+        assert_eq!(buffer.line(), "日\tz本");
+        assert_eq!(buffer.column(4, 4), 7);
     }
 }
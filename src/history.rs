@@ -0,0 +1,498 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::editor::Position;
+
+/// A single reversible edit, `at` is a character offset into the document.
+#[derive(Clone)]
+pub enum Change {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+}
+
+impl Change {
+    pub fn inverse(&self) -> Self {
+        match self {
+            Self::Insert { at, text } => Self::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Self::Delete { at, text } => Self::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+
+    /// One line of text, `i <offset> <text>` or `d <offset> <text>` with newlines escaped.
+    pub fn serialize(&self) -> String {
+        match self {
+            Self::Insert { at, text } => format!("i {} {}", at, escape(text)),
+            Self::Delete { at, text } => format!("d {} {}", at, escape(text)),
+        }
+    }
+
+    pub fn deserialize(line: &str) -> Option<Self> {
+        let (kind, rest) = line.split_once(' ')?;
+        let (at, text) = rest.split_once(' ')?;
+        let at = at.parse().ok()?;
+        let text = unescape(text);
+
+        match kind {
+            "i" => Some(Self::Insert { at, text }),
+            "d" => Some(Self::Delete { at, text }),
+            _ => None,
+        }
+    }
+}
+
+/// A node in the undo tree, holding every change made by one insert session or normal mode command.
+pub struct Revision {
+    pub parent: usize,
+    pub children: Vec<usize>,
+    // The child that redo goes back to, it's the one that was most recently visited.
+    pub last_child: Option<usize>,
+    pub changes: Vec<Change>,
+    pub cursor_before: Position,
+    pub cursor_after: Position,
+    pub time: SystemTime,
+}
+
+impl Revision {
+    fn root() -> Self {
+        Self {
+            parent: 0,
+            children: vec![],
+            last_child: None,
+            changes: vec![],
+            cursor_before: Position::default(),
+            cursor_after: Position::default(),
+            time: SystemTime::now(),
+        }
+    }
+}
+
+/// One step to take when moving around the undo tree.
+pub enum Step {
+    Undo(usize),
+    Redo(usize),
+}
+
+/// An undo tree. Revisions are numbered in the order they were made, revision 0 is the original file.
+pub struct History {
+    pub revisions: Vec<Revision>,
+    pub current: usize,
+    pending: Vec<Change>,
+    pending_cursor: Option<Position>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            revisions: vec![Revision::root()],
+            current: 0,
+            pending: vec![],
+            pending_cursor: None,
+        }
+    }
+}
+
+impl History {
+    /// Adds a change to the group that is currently being built.
+    pub fn record(&mut self, change: Change, cursor: &Position) {
+        if self.pending_cursor.is_none() {
+            self.pending_cursor = Some(*cursor);
+        }
+
+        // Typing or backspacing over a word creates one change instead of one per character.
+        match (self.pending.last_mut(), &change) {
+            (
+                Some(Change::Insert { at, text }),
+                Change::Insert {
+                    at: new_at,
+                    text: new_text,
+                },
+            ) if *at + text.chars().count() == *new_at => {
+                text.push_str(new_text);
+                return;
+            }
+            (
+                Some(Change::Delete { at, text }),
+                Change::Delete {
+                    at: new_at,
+                    text: new_text,
+                },
+            ) if *new_at + new_text.chars().count() == *at => {
+                text.insert_str(0, new_text);
+                *at = *new_at;
+                return;
+            }
+            _ => (),
+        }
+
+        self.pending.push(change);
+    }
+
+    /// Closes the group being built and turns it into a new revision.
+    pub fn commit(&mut self, cursor: &Position) {
+        let cursor_before = match self.pending_cursor.take() {
+            Some(cursor) => cursor,
+            None => return,
+        };
+
+        let id = self.revisions.len();
+        self.revisions.push(Revision {
+            parent: self.current,
+            children: vec![],
+            last_child: None,
+            changes: std::mem::take(&mut self.pending),
+            cursor_before,
+            cursor_after: *cursor,
+            time: SystemTime::now(),
+        });
+
+        let parent = &mut self.revisions[self.current];
+        parent.children.push(id);
+        parent.last_child = Some(id);
+        self.current = id;
+    }
+
+    /// Whether there are changes that haven't been committed yet.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The revision `u` would undo.
+    pub fn undo_step(&self) -> Option<Step> {
+        if self.current == 0 {
+            None
+        } else {
+            Some(Step::Undo(self.current))
+        }
+    }
+
+    /// The revision `Ctrl-R` would redo.
+    pub fn redo_step(&self) -> Option<Step> {
+        self.revisions[self.current].last_child.map(Step::Redo)
+    }
+
+    /// The steps needed to get from the current revision to `target`, going through their common ancestor.
+    pub fn path_to(&self, target: usize) -> Vec<Step> {
+        let ancestors = |mut id: usize| {
+            let mut path = vec![id];
+            while id != 0 {
+                id = self.revisions[id].parent;
+                path.push(id);
+            }
+            path
+        };
+
+        let from = ancestors(self.current);
+        let to = ancestors(target);
+        let common = *from.iter().find(|id| to.contains(id)).unwrap_or(&0);
+
+        let mut steps: Vec<Step> = from
+            .into_iter()
+            .take_while(|id| *id != common)
+            .map(Step::Undo)
+            .collect();
+
+        let redos: Vec<usize> = to.into_iter().take_while(|id| *id != common).collect();
+        steps.extend(redos.into_iter().rev().map(Step::Redo));
+        steps
+    }
+
+    /// Marks a step as taken.
+    pub fn apply(&mut self, step: &Step) {
+        match *step {
+            Step::Undo(id) => {
+                let parent = self.revisions[id].parent;
+                self.revisions[parent].last_child = Some(id);
+                self.current = parent;
+            }
+            Step::Redo(id) => {
+                let parent = self.revisions[id].parent;
+                self.revisions[parent].last_child = Some(id);
+                self.current = id;
+            }
+        }
+    }
+
+    /// Revisions without children, these are the ends of each branch.
+    pub fn leaves(&self) -> Vec<usize> {
+        (1..self.revisions.len())
+            .filter(|id| self.revisions[*id].children.is_empty())
+            .collect()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some(c) => result.push(c),
+                None => (),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+// Writing and reading the undo tree, so that it survives closing the editor.
+//
+// The format is line based:
+//   current <revision>
+//   revision <parent> <last child or -> <seconds since epoch> <cursor before x y> <cursor after x y>
+//   i <offset> <text>   (or `d` for deletions, belonging to the revision above it)
+impl History {
+    pub fn serialize(&self) -> String {
+        let mut output = format!("current {}\n", self.current);
+        for revision in &self.revisions {
+            let last_child = match revision.last_child {
+                Some(child) => child.to_string(),
+                None => "-".to_string(),
+            };
+            let time = revision
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            output.push_str(&format!(
+                "revision {} {} {} {} {} {} {}\n",
+                revision.parent,
+                last_child,
+                time,
+                revision.cursor_before.x,
+                revision.cursor_before.y,
+                revision.cursor_after.x,
+                revision.cursor_after.y,
+            ));
+
+            for change in &revision.changes {
+                output.push_str(&change.serialize());
+                output.push('\n');
+            }
+        }
+
+        output
+    }
+
+    /// Returns None if the input is not a valid undo tree.
+    pub fn deserialize(input: &str) -> Option<Self> {
+        let mut lines = input.lines();
+        let current = lines.next()?.strip_prefix("current ")?.parse().ok()?;
+
+        let mut revisions: Vec<Revision> = vec![];
+        for line in lines {
+            let (kind, rest) = line.split_once(' ')?;
+            match kind {
+                "revision" => {
+                    let fields: Vec<&str> = rest.split(' ').collect();
+                    if fields.len() != 7 {
+                        return None;
+                    }
+
+                    let number = |i: usize| fields[i].parse::<usize>().ok();
+                    let id = revisions.len();
+                    let parent = number(0)?;
+                    if id != 0 && parent >= id {
+                        return None;
+                    }
+
+                    revisions.push(Revision {
+                        parent,
+                        children: vec![],
+                        last_child: number(1),
+                        changes: vec![],
+                        cursor_before: Position::new(number(3)?, number(4)?),
+                        cursor_after: Position::new(number(5)?, number(6)?),
+                        time: UNIX_EPOCH + Duration::from_secs(number(2)? as u64),
+                    });
+
+                    if id != 0 {
+                        revisions[parent].children.push(id);
+                    }
+                }
+                "i" | "d" => {
+                    let change = Change::deserialize(line)?;
+                    revisions.last_mut()?.changes.push(change);
+                }
+                _ => return None,
+            }
+        }
+
+        // The child redo goes to has to be one of the revision's own children.
+        let valid = |(id, revision): (usize, &Revision)| {
+            revision.last_child.is_none_or(|child| {
+                child != 0 && revisions.get(child).is_some_and(|child| child.parent == id)
+            })
+        };
+        if current >= revisions.len() || !revisions.iter().enumerate().all(valid) {
+            return None;
+        }
+
+        Some(Self {
+            revisions,
+            current,
+            ..Self::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(history: &mut History, at: usize, text: &str) {
+        let change = Change::Insert {
+            at,
+            text: text.to_string(),
+        };
+        history.record(change, &Position::new(at, 0));
+        history.commit(&Position::new(at + text.len(), 0));
+    }
+
+    fn steps(steps: &[Step]) -> Vec<(char, usize)> {
+        steps
+            .iter()
+            .map(|step| match step {
+                Step::Undo(id) => ('u', *id),
+                Step::Redo(id) => ('r', *id),
+            })
+            .collect()
+    }
+
+    // Revisions 1 and 2 on one branch, 3 and 4 on another that starts at 1:
+    //   0 - 1 - 2
+    //        \- 3 - 4
+    fn branched() -> History {
+        let mut history = History::default();
+        insert(&mut history, 0, "a");
+        insert(&mut history, 1, "b");
+        history.apply(&Step::Undo(2));
+        insert(&mut history, 1, "c");
+        insert(&mut history, 2, "d");
+        history
+    }
+
+    #[test]
+    fn changes_are_grouped_until_committed() {
+        let mut history = History::default();
+        history.commit(&Position::default());
+        assert_eq!(history.revisions.len(), 1);
+
+        for (at, c) in "abc".chars().enumerate() {
+            let change = Change::Insert {
+                at,
+                text: c.to_string(),
+            };
+            history.record(change, &Position::new(at, 0));
+        }
+        // Backspacing over the last two is one delete, from the left.
+        for at in [2, 1] {
+            let text = "abc"[at..=at].to_string();
+            history.record(Change::Delete { at, text }, &Position::new(at, 0));
+        }
+        assert!(history.has_pending());
+        history.commit(&Position::new(1, 0));
+
+        let revision = &history.revisions[1];
+        assert_eq!(revision.changes.len(), 2);
+        assert_eq!(revision.changes[0].serialize(), "i 0 abc");
+        assert_eq!(revision.changes[1].serialize(), "d 1 bc");
+        assert_eq!(revision.cursor_before.x, 0);
+        assert_eq!(revision.cursor_after.x, 1);
+        assert!(!history.has_pending());
+    }
+
+    #[test]
+    fn new_changes_after_undo_start_a_branch() {
+        let history = branched();
+        assert_eq!(history.current, 4);
+        assert_eq!(history.revisions[1].children, [2, 3]);
+        assert_eq!(history.revisions[3].parent, 1);
+        assert_eq!(history.leaves(), [2, 4]);
+        // Redo from 1 goes down the branch that was made last.
+        assert_eq!(history.revisions[1].last_child, Some(3));
+    }
+
+    #[test]
+    fn undo_and_redo_follow_the_last_visited_child() {
+        let mut history = branched();
+        while let Some(step) = history.undo_step() {
+            history.apply(&step);
+        }
+        assert_eq!(history.current, 0);
+        assert!(history.undo_step().is_none());
+
+        let mut redone = vec![];
+        while let Some(step) = history.redo_step() {
+            redone.extend(steps(std::slice::from_ref(&step)));
+            history.apply(&step);
+        }
+        assert_eq!(redone, [('r', 1), ('r', 3), ('r', 4)]);
+
+        // Going to the other branch makes redo follow that one instead.
+        for step in history.path_to(2) {
+            history.apply(&step);
+        }
+        history.apply(&Step::Undo(2));
+        history.apply(&Step::Undo(1));
+        let mut redone = vec![];
+        while let Some(step) = history.redo_step() {
+            redone.extend(steps(std::slice::from_ref(&step)));
+            history.apply(&step);
+        }
+        assert_eq!(redone, [('r', 1), ('r', 2)]);
+    }
+
+    #[test]
+    fn paths_go_through_the_common_ancestor() {
+        let mut history = branched();
+        assert_eq!(steps(&history.path_to(2)), [('u', 4), ('u', 3), ('r', 2)]);
+        assert_eq!(steps(&history.path_to(4)), []);
+        assert_eq!(steps(&history.path_to(0)), [('u', 4), ('u', 3), ('u', 1)]);
+
+        // Walking the path there and back ends up where it started, in both directions of
+        // time, like `g-` and `g+`.
+        for target in [2, 0, 3, 4, 1, 4] {
+            for step in history.path_to(target) {
+                history.apply(&step);
+            }
+            assert_eq!(history.current, target);
+        }
+
+        history.apply(&Step::Undo(4));
+        assert_eq!(steps(&history.path_to(2)), [('u', 3), ('r', 2)]);
+        assert_eq!(steps(&history.path_to(4)), [('r', 4)]);
+    }
+
+    #[test]
+    fn changes_invert_and_escape() {
+        let change = Change::Delete {
+            at: 3,
+            text: "a\\b\nc\r".to_string(),
+        };
+        let line = change.serialize();
+        assert_eq!(line, "d 3 a\\\\b\\nc\\r");
+        assert!(!line.contains('\n'));
+
+        let inverse = Change::deserialize(&line).unwrap().inverse();
+        assert_eq!(inverse.serialize(), "i 3 a\\\\b\\nc\\r");
+        assert!(Change::deserialize("x 3 text").is_none());
+        assert!(Change::deserialize("i three text").is_none());
+    }
+}
@@ -1,17 +1,40 @@
 mod editor;
 use editor::Editor;
 
+mod buffer;
+mod clipboard;
+mod diff;
 mod document;
+mod encoding;
+mod frame;
 mod gap_buffer;
+mod history;
+#[cfg(test)]
+mod memory_backend;
 mod modes;
+mod motion;
+mod operator;
+mod options;
+mod register;
+mod rope;
+mod save;
 mod status_message;
+mod swap_file;
 mod terminal;
+mod timer;
 mod ui;
+mod undo_file;
+mod width;
+mod window;
 
+use std::env;
 use std::io::stdout;
 
+use terminal::{CrosstermBackend, Terminal};
+
 fn main() {
-    let stdout = stdout();
-    let mut editor = Editor::new(stdout);
+    let backend = CrosstermBackend::new(stdout());
+    let terminal = Terminal::new(Box::new(backend)).expect("Failed to initialize terminal.");
+    let mut editor = Editor::new(terminal, env::args().skip(1).collect());
     editor.run();
 }
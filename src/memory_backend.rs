@@ -0,0 +1,366 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::clipboard::Selection;
+use super::terminal::Backend;
+
+use crossterm::cursor::CursorShape;
+use crossterm::event::{Event, KeyCode as Key, KeyEvent, KeyModifiers as Mod};
+use crossterm::style::{Attribute, Color};
+use crossterm::terminal::ClearType;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub symbol: String,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            fg: Color::Reset,
+            bg: Color::Reset,
+        }
+    }
+}
+
+struct Screen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    cursor: (u16, u16),
+    fg: Color,
+    bg: Color,
+    cursor_visible: bool,
+    alternate_screen: bool,
+    cursor_shape: Option<CursorShape>,
+    events: VecDeque<Event>,
+    // How many cells were printed, to see how much of the screen a frame draws.
+    printed: usize,
+    // What was last sent to the clipboard with OSC 52.
+    copied: Option<(Selection, String)>,
+    // How many of the next flushes fail, like a terminal that went away.
+    failing_flushes: usize,
+}
+
+impl Screen {
+    fn clear_row(&mut self, y: u16) {
+        let start = usize::from(y) * usize::from(self.width);
+        let end = start + usize::from(self.width);
+        if let Some(row) = self.cells.get_mut(start..end) {
+            row.fill(Cell::default());
+        }
+    }
+
+    fn put(&mut self, x: u16, y: u16, symbol: &str) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let index = usize::from(y) * usize::from(self.width) + usize::from(x);
+        self.cells[index] = Cell {
+            symbol: symbol.to_string(),
+            fg: self.fg,
+            bg: self.bg,
+        };
+    }
+}
+
+/// A terminal that only exists in memory, for tests. It keeps a grid of cells instead of
+/// drawing them, and plays back events that were queued beforehand. Clones share the screen,
+/// so a test can keep one while the editor draws on the other.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        let screen = Screen {
+            width,
+            height,
+            cells: vec![Cell::default(); usize::from(width) * usize::from(height)],
+            cursor: (0, 0),
+            fg: Color::Reset,
+            bg: Color::Reset,
+            cursor_visible: true,
+            alternate_screen: false,
+            cursor_shape: None,
+            events: VecDeque::new(),
+            printed: 0,
+            copied: None,
+            failing_flushes: 0,
+        };
+
+        Self {
+            screen: Rc::new(RefCell::new(screen)),
+        }
+    }
+
+    pub fn push_event(&self, event: Event) {
+        self.screen.borrow_mut().events.push_back(event);
+    }
+
+    /// Queues keys written like vim's key notation: `ihello<Esc>`, `<C-w>v`, `:wq<CR>`.
+    pub fn push_keys(&self, keys: &str) {
+        let mut rest = keys;
+        while let Some(c) = rest.chars().next() {
+            let special = rest
+                .strip_prefix('<')
+                .and_then(|inner| inner.split_once('>'))
+                .and_then(|(name, after)| Some((parse_key(name)?, after)));
+
+            let (key, modifiers) = match special {
+                Some((key, after)) => {
+                    rest = after;
+                    key
+                }
+                None => {
+                    rest = &rest[c.len_utf8()..];
+                    (Key::Char(c), Mod::NONE)
+                }
+            };
+
+            self.push_event(Event::Key(KeyEvent::new(key, modifiers)));
+        }
+    }
+
+    /// Resizes the screen, which loses what was on it, and queues the event a terminal sends.
+    pub fn resize(&self, width: u16, height: u16) {
+        let mut screen = self.screen.borrow_mut();
+        screen.width = width;
+        screen.height = height;
+        screen.cells = vec![Cell::default(); usize::from(width) * usize::from(height)];
+        screen.events.push_back(Event::Resize(width, height));
+    }
+
+    pub fn has_events(&self) -> bool {
+        !self.screen.borrow().events.is_empty()
+    }
+
+    /// The text on the screen, one string per row, without trailing spaces.
+    pub fn lines(&self) -> Vec<String> {
+        let screen = self.screen.borrow();
+        screen
+            .cells
+            .chunks(usize::from(screen.width))
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.symbol.as_str()).collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    pub fn cell(&self, x: u16, y: u16) -> Cell {
+        let screen = self.screen.borrow();
+        screen.cells[usize::from(y) * usize::from(screen.width) + usize::from(x)].clone()
+    }
+
+    /// The number of cells printed since the last time this was asked.
+    pub fn take_printed(&self) -> usize {
+        mem::take(&mut self.screen.borrow_mut().printed)
+    }
+
+    pub fn cursor(&self) -> (u16, u16) {
+        self.screen.borrow().cursor
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.screen.borrow().cursor_visible
+    }
+
+    pub fn alternate_screen(&self) -> bool {
+        self.screen.borrow().alternate_screen
+    }
+
+    pub fn cursor_shape(&self) -> Option<CursorShape> {
+        self.screen.borrow().cursor_shape
+    }
+
+    pub fn copied(&self) -> Option<(Selection, String)> {
+        self.screen.borrow().copied.clone()
+    }
+
+    /// Makes the next `count` flushes fail.
+    pub fn fail_flushes(&self, count: usize) {
+        self.screen.borrow_mut().failing_flushes = count;
+    }
+}
+
+/// `Esc`, `CR`, `C-w` and the like, without the angle brackets.
+fn parse_key(name: &str) -> Option<(Key, Mod)> {
+    if let Some(key) = name.strip_prefix("C-") {
+        let mut chars = key.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Some((Key::Char(c), Mod::CONTROL)),
+            _ => None,
+        };
+    }
+
+    if let Some(key) = name.strip_prefix("A-") {
+        let mut chars = key.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Some((Key::Char(c), Mod::ALT)),
+            _ => None,
+        };
+    }
+
+    let key = match name {
+        "Esc" => Key::Esc,
+        "CR" | "Enter" => Key::Enter,
+        "BS" => Key::Backspace,
+        "Tab" => Key::Tab,
+        "Del" => Key::Delete,
+        "lt" => Key::Char('<'),
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        _ => return None,
+    };
+
+    Some((key, Mod::NONE))
+}
+
+impl Backend for MemoryBackend {
+    fn size(&self) -> io::Result<(u16, u16)> {
+        let screen = self.screen.borrow();
+        Ok((screen.width, screen.height))
+    }
+
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn enter_alternate_screen(&mut self) -> io::Result<()> {
+        self.screen.borrow_mut().alternate_screen = true;
+        Ok(())
+    }
+
+    fn leave_alternate_screen(&mut self) -> io::Result<()> {
+        self.screen.borrow_mut().alternate_screen = false;
+        Ok(())
+    }
+
+    fn clear(&mut self, clear_type: ClearType) -> io::Result<()> {
+        let mut screen = self.screen.borrow_mut();
+        match clear_type {
+            ClearType::CurrentLine => {
+                let y = screen.cursor.1;
+                screen.clear_row(y);
+            }
+            _ => {
+                for y in 0..screen.height {
+                    screen.clear_row(y);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) -> io::Result<()> {
+        self.screen.borrow_mut().cursor = (x, y);
+        Ok(())
+    }
+
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        let mut screen = self.screen.borrow_mut();
+        for grapheme in text.graphemes(true) {
+            let (x, y) = screen.cursor;
+            screen.put(x, y, grapheme);
+            screen.printed += 1;
+
+            // The column after a wide character is covered by it.
+            let width = grapheme.width() as u16;
+            for column in 1..width {
+                screen.put(x + column, y, "");
+            }
+            screen.cursor = (x.saturating_add(width), y);
+        }
+        Ok(())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.screen.borrow_mut().fg = color;
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.screen.borrow_mut().bg = color;
+        Ok(())
+    }
+
+    fn set_attribute(&mut self, _attribute: Attribute) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_cursor_shape(&mut self, shape: CursorShape) -> io::Result<()> {
+        self.screen.borrow_mut().cursor_shape = Some(shape);
+        Ok(())
+    }
+
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> io::Result<()> {
+        let mut screen = self.screen.borrow_mut();
+        let width = usize::from(screen.width);
+        let rows = usize::from(top) * width..usize::from(bottom.min(screen.height)) * width;
+        let region = &mut screen.cells[rows];
+        let shift = (lines.unsigned_abs() as usize * width).min(region.len());
+
+        if lines > 0 {
+            region.rotate_left(shift);
+            let blank = region.len() - shift;
+            region[blank..].fill(Cell::default());
+        } else {
+            region.rotate_right(shift);
+            region[..shift].fill(Cell::default());
+        }
+        Ok(())
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.screen.borrow_mut().cursor_visible = false;
+        Ok(())
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.screen.borrow_mut().cursor_visible = true;
+        Ok(())
+    }
+
+    fn copy(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        self.screen.borrow_mut().copied = Some((selection, text.to_string()));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut screen = self.screen.borrow_mut();
+        if screen.failing_flushes > 0 {
+            screen.failing_flushes -= 1;
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        }
+        Ok(())
+    }
+
+    // Events are all queued up front, there's nothing to wait for.
+    fn poll(&mut self, _timeout: Duration) -> io::Result<bool> {
+        Ok(self.has_events())
+    }
+
+    fn read(&mut self) -> io::Result<Event> {
+        let event = self.screen.borrow_mut().events.pop_front();
+        event.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No more events"))
+    }
+}
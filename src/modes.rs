@@ -1,11 +1,20 @@
 use std::fmt;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mode {
     Insert,
     Normal,
     Command,
-    Save,
+    // Selecting characters, whole lines or a block of columns, `v`, `V` and `Ctrl-V`.
+    Visual,
+    VisualLine,
+    VisualBlock,
+}
+
+impl Mode {
+    pub fn is_visual(self) -> bool {
+        matches!(self, Self::Visual | Self::VisualLine | Self::VisualBlock)
+    }
 }
 
 impl fmt::Display for Mode {
@@ -14,7 +23,9 @@ impl fmt::Display for Mode {
             Self::Insert => "INSERT",
             Self::Normal => "NORMAL",
             Self::Command => "COMMAND",
-            Self::Save => "SAVE",
+            Self::Visual => "VISUAL",
+            Self::VisualLine => "V-LINE",
+            Self::VisualBlock => "V-BLOCK",
         };
 
         write!(f, "MODE: {}", mode)
@@ -0,0 +1,660 @@
+use super::document::Document;
+use super::editor::Position;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Moves the cursor somewhere. An operator in front of it acts on the text the cursor moves over.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    // Past the next space, `w`.
+    WordForward,
+    // Back to the space before, `b`.
+    WordBackward,
+    // To the last character before a space, `e`.
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    PageUp,
+    PageDown,
+}
+
+/// How an operator treats the text between the cursor and where a motion goes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    // Up to the character the motion lands on.
+    Exclusive,
+    // Up to and including the character the motion lands on.
+    Inclusive,
+    // Every line from the cursor's to the motion's.
+    Linewise,
+}
+
+impl Motion {
+    /// The motions that are a single key.
+    pub fn from_key(c: char) -> Option<Self> {
+        let motion = match c {
+            'h' => Motion::Left,
+            'l' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            'w' => Motion::WordForward,
+            'b' => Motion::WordBackward,
+            'e' => Motion::WordEnd,
+            '0' => Motion::LineStart,
+            'S' | '^' => Motion::FirstNonBlank,
+            's' | '$' => Motion::LineEnd,
+            'G' => Motion::LastLine,
+            'K' => Motion::PageUp,
+            'J' => Motion::PageDown,
+            _ => return None,
+        };
+
+        Some(motion)
+    }
+
+    pub fn kind(self) -> Kind {
+        match self {
+            Motion::Up
+            | Motion::Down
+            | Motion::FirstLine
+            | Motion::LastLine
+            | Motion::PageUp
+            | Motion::PageDown => Kind::Linewise,
+            Motion::WordEnd | Motion::LineEnd => Kind::Inclusive,
+            _ => Kind::Exclusive,
+        }
+    }
+}
+
+/// The terminal column a position is drawn at, before scrolling.
+pub fn column_of(document: &Document, position: Position, tabstop: usize) -> usize {
+    let Position { x, y } = position;
+    document
+        .buffer(y)
+        .map_or(x, |buffer| buffer.column(x, tabstop))
+}
+
+fn graphemes(document: &Document, y: usize) -> Vec<String> {
+    let line = document.buffer(y).map(|buffer| buffer.line());
+    let line = line.unwrap_or_default();
+    line.graphemes(true).map(str::to_string).collect()
+}
+
+/// The last grapheme of a line, where `s` goes.
+fn last(document: &Document, y: usize) -> usize {
+    document
+        .buffer(y)
+        .map_or(0, |buffer| buffer.len_graphemes().saturating_sub(1))
+}
+
+/// Where the text of a line starts, after its indentation.
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    graphemes(document, y)
+        .iter()
+        .take_while(|grapheme| grapheme.trim().is_empty())
+        .count()
+}
+
+/// Where `motion` takes the cursor from `from`, going `count` times. A page is `height` lines.
+pub fn target(
+    document: &Document,
+    motion: Motion,
+    count: Option<usize>,
+    from: Position,
+    tabstop: usize,
+    height: usize,
+) -> Position {
+    let step = |from| step(document, motion, from, tabstop, height);
+    match (motion, count) {
+        // With a count, `G` and `gg` go to that line.
+        (Motion::FirstLine | Motion::LastLine, Some(line)) => {
+            let y = line.saturating_sub(1).min(document.len().saturating_sub(1));
+            let width = document
+                .buffer(y)
+                .map_or(0, |buffer| buffer.len_graphemes());
+            Position::new(from.x.min(width), y)
+        }
+        // `3$` goes to the end of the line two lines down.
+        (Motion::LineEnd, Some(count)) => {
+            let y = from.y.saturating_add(count - 1);
+            step(Position::new(
+                from.x,
+                y.min(document.len().saturating_sub(1)),
+            ))
+        }
+        _ => {
+            let mut position = from;
+            for _ in 0..count.unwrap_or(1) {
+                let next = step(position);
+                // A big count stops once the cursor can't go any further.
+                if (next.x, next.y) == (position.x, position.y) {
+                    break;
+                }
+                position = next;
+            }
+            position
+        }
+    }
+}
+
+fn step(
+    document: &Document,
+    motion: Motion,
+    from: Position,
+    tabstop: usize,
+    height: usize,
+) -> Position {
+    let Position { mut x, mut y } = from;
+    let doc_height = document.len();
+    // the width changes depending on the length of the row
+    let width = last(document, y);
+
+    match motion {
+        Motion::Up if y > 0 => {
+            let column = column_of(document, from, tabstop);
+            y -= 1;
+            if x == width {
+                x = last(document, y);
+            } else if let Some(top) = document.buffer(y) {
+                // Stays in the same column, even if the lines have wide characters.
+                x = top.grapheme_at_column(column, tabstop);
+            }
+        }
+
+        Motion::Down if y < doc_height.saturating_sub(1) => {
+            let column = column_of(document, from, tabstop);
+            y = y.saturating_add(1);
+            if x >= width.saturating_sub(1) {
+                // NOTE: In the case of an empty line, it will move the cursor to the
+                // very end of it because the if condition is still true.
+                x = last(document, y);
+            } else if let Some(bottom) = document.buffer(y) {
+                x = bottom.grapheme_at_column(column, tabstop);
+            }
+        }
+
+        Motion::Up | Motion::Down => (),
+
+        Motion::Left => {
+            // lets the user move to the end of the previous line,
+            // if cursor at the start of a line.
+            if x > 0 {
+                x -= 1;
+            } else if y > 0 {
+                y -= 1;
+                x = last(document, y);
+            }
+        }
+
+        Motion::Right => {
+            if x < width {
+                x += 1;
+            } else if y < doc_height.saturating_sub(1) && x >= width {
+                y += 1;
+                x = 0;
+            }
+        }
+
+        Motion::WordBackward => {
+            if document.buffer(y).is_some() {
+                let graphemes = graphemes(document, y);
+                if let Some(contents) = graphemes.get(..x.saturating_add(1)) {
+                    let mut index = 0;
+
+                    // count starts at 0
+                    for (count, grapheme) in contents.iter().rev().enumerate() {
+                        // NOTE: Still buggy, run once in nvim then in hecto to see problem.
+                        if grapheme == " " {
+                            index = count + 1;
+                            break;
+                        }
+                    }
+
+                    if y > 0 && x == 0 {
+                        y -= 1;
+                        x = last(document, y);
+                    } else if index == 0 {
+                        x = 0;
+                    } else {
+                        x = x.saturating_sub(index);
+                    }
+                } else {
+                    // NOTE: If there is white space at the front, it goes through one character by one character.
+                    // It doesn't skip straight to the non white-space character
+                    y = y.saturating_sub(1);
+                    x = last(document, y);
+                }
+            }
+        }
+
+        Motion::WordForward => {
+            if document.buffer(y).is_some() {
+                // NOTE: If there is white space at the front, it goes through one character by one character.
+                // It doesn't skip straight to the non white-space character
+                let graphemes = graphemes(document, y);
+                if let Some(contents) = graphemes.get(x..) {
+                    let mut index = 0;
+
+                    for (count, grapheme) in contents.iter().enumerate() {
+                        if grapheme == " " {
+                            index = count + 1;
+                            break;
+                        }
+                    }
+
+                    if y < doc_height.saturating_sub(1) && x >= width.saturating_sub(1) {
+                        y += 1;
+                        x = 0;
+                    } else if index == 0 {
+                        x = width.saturating_sub(1);
+                    } else {
+                        x = x.saturating_add(index);
+                    }
+                }
+            }
+        }
+
+        Motion::WordEnd => Position { x, y } = word_end(document, Position { x: x + 1, y }),
+
+        Motion::PageUp => {
+            // first if only happens on the 1st screen.
+            y = y.saturating_sub(height)
+        }
+        Motion::PageDown => {
+            // height is the number of visible rows on the screen.
+            // doc_height is the number of rows in the entire file
+            y = if y.saturating_add(height) < doc_height.saturating_sub(1) {
+                y + height
+            } else {
+                // This is only true when it's at the last page
+                doc_height.saturating_sub(1)
+            }
+        }
+
+        Motion::FirstLine => y = 0,
+        Motion::LastLine => y = doc_height.saturating_sub(1),
+        Motion::LineStart => x = 0,
+        // Moves to start of the line, minus the whitespace.
+        Motion::FirstNonBlank => x = first_non_blank(document, y),
+        Motion::LineEnd => x = width,
+    }
+
+    // if the cursor is further than the width
+    // the x pos of the cursor will be set to the width
+    // snapping it to the end of the line.
+    let width = document
+        .buffer(y)
+        .map_or(0, |buffer| buffer.len_graphemes());
+    Position { x: x.min(width), y }
+}
+
+/// The last grapheme before a space, starting the search at `from`. Spaces and empty lines in
+/// front of the word are skipped.
+pub fn word_end(document: &Document, from: Position) -> Position {
+    let Position { mut x, mut y } = from;
+    let mut line = graphemes(document, y);
+
+    // Skips to the next word, which may be on a line further down.
+    while line
+        .get(x)
+        .is_none_or(|grapheme| grapheme.trim().is_empty())
+    {
+        if x < line.len() {
+            x += 1;
+        } else if y + 1 < document.len() {
+            y += 1;
+            x = 0;
+            line = graphemes(document, y);
+        } else {
+            return Position::new(last(document, y), y);
+        }
+    }
+
+    while line
+        .get(x + 1)
+        .is_some_and(|grapheme| !grapheme.trim().is_empty())
+    {
+        x += 1;
+    }
+    Position { x, y }
+}
+
+/// Where the `count`th word after `from` starts, this is where `dw` stops. Like in vim, it
+/// goes on to the lines below for a count, but it never goes past the end of the line the last
+/// word is on.
+pub fn next_word(document: &Document, from: Position, count: usize) -> Position {
+    let mut position = from;
+    for _ in 0..count {
+        let line = graphemes(document, position.y);
+        let blank = |x: usize| line[x].trim().is_empty();
+        let mut x = position.x.min(line.len());
+        if x == line.len() {
+            if position.y + 1 >= document.len() {
+                break;
+            }
+            let y = position.y + 1;
+            position = Position::new(first_non_blank(document, y), y);
+            continue;
+        }
+
+        while x < line.len() && !blank(x) {
+            x += 1;
+        }
+        while x < line.len() && blank(x) {
+            x += 1;
+        }
+        position.x = x;
+    }
+
+    if position.y > from.y && position.x == first_non_blank(document, position.y) {
+        let y = position.y - 1;
+        return Position::new(document.buffer(y).map_or(0, |b| b.len_graphemes()), y);
+    }
+    position
+}
+
+/// A piece of text around the cursor an operator can act on, like the word in `diw`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextObject {
+    Word {
+        around: bool,
+    },
+    Paragraph {
+        around: bool,
+    },
+    // Between a pair of quotes on the line, like `i"`.
+    Quote {
+        quote: char,
+        around: bool,
+    },
+    // Between a pair of brackets, like `i(` and `a{`.
+    Bracket {
+        open: char,
+        close: char,
+        around: bool,
+    },
+}
+
+impl TextObject {
+    /// The text object for the key after `i` (inner) or `a` (around).
+    pub fn from_key(c: char, around: bool) -> Option<Self> {
+        let object = match c {
+            'w' => TextObject::Word { around },
+            'p' => TextObject::Paragraph { around },
+            '"' | '\'' | '`' => TextObject::Quote { quote: c, around },
+            '(' | ')' | 'b' => TextObject::Bracket {
+                open: '(',
+                close: ')',
+                around,
+            },
+            '{' | '}' | 'B' => TextObject::Bracket {
+                open: '{',
+                close: '}',
+                around,
+            },
+            '[' | ']' => TextObject::Bracket {
+                open: '[',
+                close: ']',
+                around,
+            },
+            '<' | '>' => TextObject::Bracket {
+                open: '<',
+                close: '>',
+                around,
+            },
+            _ => return None,
+        };
+
+        Some(object)
+    }
+}
+
+/// Text an operator acts on. `end` isn't part of it, unless it's `linewise`: then it's every
+/// line from `start.y` to `end.y`.
+#[derive(Clone, Copy, Debug)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+    pub linewise: bool,
+}
+
+impl Range {
+    pub fn lines(first: usize, last: usize) -> Self {
+        Self {
+            start: Position::new(0, first),
+            end: Position::new(0, last),
+            linewise: true,
+        }
+    }
+}
+
+// Words are told apart like `iw` in vim: runs of letters, digits and underscores, runs of other
+// characters and runs of whitespace.
+#[derive(PartialEq)]
+enum Class {
+    Blank,
+    Keyword,
+    Other,
+}
+
+fn class(grapheme: &str) -> Class {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => Class::Blank,
+        Some(c) if c.is_alphanumeric() || c == '_' => Class::Keyword,
+        _ => Class::Other,
+    }
+}
+
+fn is_blank_line(document: &Document, y: usize) -> bool {
+    document
+        .buffer(y)
+        .is_none_or(|buffer| buffer.line().trim().is_empty())
+}
+
+/// Where a text object is around `at`, if there's one. A `count` takes in more words or
+/// paragraphs after it, or brackets further out.
+pub fn object_range(
+    document: &Document,
+    object: TextObject,
+    at: Position,
+    count: usize,
+) -> Option<Range> {
+    match object {
+        TextObject::Word { around } => {
+            let mut range = word_object(document, at, around)?;
+            for _ in 1..count {
+                match word_object(document, range.end, around) {
+                    Some(next) if next.end.x > range.end.x => range.end = next.end,
+                    _ => break,
+                }
+            }
+            Some(range)
+        }
+        TextObject::Paragraph { around } => {
+            let mut range = paragraph_object(document, at.y, around);
+            for _ in 1..count {
+                if range.end.y + 1 >= document.len() {
+                    break;
+                }
+                range.end = paragraph_object(document, range.end.y + 1, around).end;
+            }
+            Some(range)
+        }
+        TextObject::Quote { quote, around } => quote_object(document, at, quote, around),
+        TextObject::Bracket {
+            open,
+            close,
+            around,
+        } => bracket_object(document, at, (open, close), around, count),
+    }
+}
+
+fn word_object(document: &Document, at: Position, around: bool) -> Option<Range> {
+    let line = graphemes(document, at.y);
+    let x = at.x.min(line.len().checked_sub(1)?);
+
+    let same = |a: &String, b: &String| class(a) == class(b);
+    let mut start = x;
+    while start > 0 && same(&line[start - 1], &line[x]) {
+        start -= 1;
+    }
+    let mut end = x + 1;
+    while end < line.len() && same(&line[end], &line[x]) {
+        end += 1;
+    }
+
+    // `aw` takes the whitespace after the word along, or the whitespace before it if there's
+    // none after it.
+    if around && class(&line[x]) != Class::Blank {
+        let after = line[end..].iter().take_while(|g| class(g) == Class::Blank);
+        let after = after.count();
+        if after > 0 {
+            end += after;
+        } else {
+            let before = line[..start].iter().rev();
+            start -= before.take_while(|g| class(g) == Class::Blank).count();
+        }
+    }
+
+    Some(Range {
+        start: Position::new(start, at.y),
+        end: Position::new(end, at.y),
+        linewise: false,
+    })
+}
+
+fn paragraph_object(document: &Document, y: usize, around: bool) -> Range {
+    let blank = is_blank_line(document, y);
+    let last_line = document.len().saturating_sub(1);
+    let same = |y: usize| is_blank_line(document, y) == blank;
+
+    let mut first = y;
+    while first > 0 && same(first - 1) {
+        first -= 1;
+    }
+    let mut last = y;
+    while last < last_line && same(last + 1) {
+        last += 1;
+    }
+
+    // `ap` takes the blank lines after the paragraph along, or the ones before it if there are
+    // none after it.
+    if around {
+        let other = |y: usize| is_blank_line(document, y) != blank;
+        if last < last_line && other(last + 1) {
+            last += 1;
+            while last < last_line && other(last + 1) {
+                last += 1;
+            }
+        } else {
+            while first > 0 && other(first - 1) {
+                first -= 1;
+            }
+        }
+    }
+
+    Range::lines(first, last)
+}
+
+fn quote_object(document: &Document, at: Position, quote: char, around: bool) -> Option<Range> {
+    let line = graphemes(document, at.y);
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for (x, grapheme) in line.iter().enumerate() {
+        if !escaped && grapheme.starts_with(quote) {
+            quotes.push(x);
+        }
+        escaped = !escaped && grapheme == "\\";
+    }
+
+    // Quotes pair up from the start of the line. The cursor can be inside a pair or in front
+    // of one.
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| at.x <= *close)?;
+
+    let (start, end) = if around {
+        (open, close + 1)
+    } else {
+        (open + 1, close)
+    };
+    Some(Range {
+        start: Position::new(start, at.y),
+        end: Position::new(end, at.y),
+        linewise: false,
+    })
+}
+
+fn bracket_object(
+    document: &Document,
+    at: Position,
+    brackets: (char, char),
+    around: bool,
+    count: usize,
+) -> Option<Range> {
+    let text: Vec<char> = document.text(0, document.len_chars()).chars().collect();
+    let cursor = document.offset(&at).min(text.len().checked_sub(1)?);
+
+    let (mut start, mut end) = enclosing(&text, cursor, brackets, true)?;
+    for _ in 1..count {
+        (start, end) = enclosing(&text, start.checked_sub(1)?, brackets, false)?;
+    }
+
+    let (start, end) = if around {
+        (start, end + 1)
+    } else {
+        (start + 1, end)
+    };
+    Some(Range {
+        start: document.position(start),
+        end: document.position(end),
+        linewise: false,
+    })
+}
+
+// The offsets of the brackets around `cursor`. With `on`, the cursor can be on either of them.
+fn enclosing(
+    text: &[char],
+    cursor: usize,
+    (open, close): (char, char),
+    on: bool,
+) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut start = None;
+    for index in (0..=cursor).rev() {
+        if text[index] == close && !(on && index == cursor) {
+            depth += 1;
+        } else if text[index] == open {
+            if depth == 0 {
+                start = Some(index);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let start = start?;
+
+    let mut depth = 0;
+    let mut end = None;
+    for (index, c) in text.iter().enumerate().skip(start + 1) {
+        if *c == open {
+            depth += 1;
+        } else if *c == close {
+            if depth == 0 {
+                end = Some(index);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+
+    Some((start, end?))
+}
@@ -0,0 +1,295 @@
+use super::motion::{Motion, TextObject};
+use super::register;
+
+use crossterm::event::{KeyCode as Key, KeyEvent, KeyModifiers as Mod};
+
+/// Does something to the text a motion or text object covers, like `d` in `dw`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Unindent,
+    // Indents lines like the line above them, `=`.
+    Reindent,
+    Lowercase,
+    Uppercase,
+}
+
+impl Operator {
+    /// The operators that are a single key, `gu` and `gU` come after a `g`.
+    fn from_key(c: char) -> Option<Self> {
+        let operator = match c {
+            'd' => Operator::Delete,
+            'c' => Operator::Change,
+            'y' => Operator::Yank,
+            '>' => Operator::Indent,
+            '<' => Operator::Unindent,
+            '=' => Operator::Reindent,
+            _ => return None,
+        };
+
+        Some(operator)
+    }
+
+    // `gu` and `gU` start with a `g`, which can be typed again when they're doubled.
+    fn after_g(self) -> bool {
+        matches!(self, Operator::Lowercase | Operator::Uppercase)
+    }
+
+    // The key that makes the operator act on whole lines when it's typed twice, like `dd`.
+    fn key(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+            Operator::Indent => '>',
+            Operator::Unindent => '<',
+            Operator::Reindent => '=',
+            Operator::Lowercase => 'u',
+            Operator::Uppercase => 'U',
+        }
+    }
+}
+
+/// What an operator acts on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    // The line the cursor is on and the ones below it, from typing the operator twice.
+    Lines,
+    // What was selected in visual mode.
+    Selection,
+}
+
+/// A normal mode command, once all of its keys are in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    // Selecting a text object in visual mode, like `iw`.
+    Select(TextObject),
+    // Every other command, with the key after it for the ones that take two keys, like `gt`.
+    Other(KeyEvent, Option<KeyEvent>),
+}
+
+/// What's typed in front of a command: a count and the register it uses, like `2"a` in `2"ayy`.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Prefix {
+    pub count: Option<usize>,
+    pub register: Option<char>,
+}
+
+pub enum Parse {
+    Done(Action, Prefix),
+    // The keys so far start a command, it needs more of them.
+    Pending,
+    // The keys don't make a command, they are dropped.
+    Invalid,
+}
+
+// Keys typed without Ctrl or Alt. Shift doesn't matter, it's already in the character.
+fn plain(event: &KeyEvent) -> Option<char> {
+    match event.code {
+        Key::Char(c) if !event.modifiers.intersects(Mod::CONTROL | Mod::ALT) => Some(c),
+        _ => None,
+    }
+}
+
+// Reads a count like the `3` in `3j`, returns it along with the keys after it. A count can't
+// start with `0`, that's a motion of its own.
+fn read_count(keys: &[KeyEvent]) -> (Option<usize>, &[KeyEvent]) {
+    let digits = keys
+        .iter()
+        .enumerate()
+        .take_while(|(i, key)| match plain(key) {
+            Some('1'..='9') => true,
+            Some('0') => *i > 0,
+            _ => false,
+        })
+        .count();
+    if digits == 0 {
+        return (None, keys);
+    }
+
+    let count = keys[..digits]
+        .iter()
+        .filter_map(plain)
+        .filter_map(|c| c.to_digit(10))
+        .fold(0usize, |count, digit| {
+            count.saturating_mul(10).saturating_add(digit as usize)
+        });
+    (Some(count), &keys[digits..])
+}
+
+// Counts typed in more than one place are multiplied, like in `2d3w`.
+fn multiply(count: Option<usize>, more: Option<usize>) -> Option<usize> {
+    match (count, more) {
+        (Some(count), Some(more)) => Some(count.saturating_mul(more)),
+        (count, more) => count.or(more),
+    }
+}
+
+// Reads the counts and the register name in front of a command, returns them along with the
+// keys after them.
+fn read_prefix(mut keys: &[KeyEvent]) -> Result<(Prefix, &[KeyEvent]), Parse> {
+    let mut prefix = Prefix::default();
+    loop {
+        let (count, rest) = read_count(keys);
+        prefix.count = multiply(prefix.count, count);
+        keys = rest;
+
+        if keys.first().and_then(plain) != Some('"') {
+            return Ok((prefix, keys));
+        }
+        match keys.get(1).map(plain) {
+            Some(Some(name)) if register::is_register(name) => prefix.register = Some(name),
+            Some(_) => return Err(Parse::Invalid),
+            None => return Err(Parse::Pending),
+        }
+        keys = &keys[2..];
+    }
+}
+
+/// Reads the keys typed in normal mode so far as a command, like `d`, `i`, `w`.
+pub fn parse(keys: &[KeyEvent]) -> Parse {
+    let (prefix, keys) = match read_prefix(keys) {
+        Ok(read) => read,
+        Err(parse) => return parse,
+    };
+    let (first, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Parse::Pending,
+    };
+
+    if first.code == Key::Char('w') && first.modifiers.contains(Mod::CONTROL) {
+        return match rest.first() {
+            Some(next) => Parse::Done(Action::Other(*first, Some(*next)), prefix),
+            None => Parse::Pending,
+        };
+    }
+
+    let c = match plain(first) {
+        Some(c) => c,
+        None => return Parse::Done(Action::Other(*first, None), prefix),
+    };
+
+    if c == 'g' {
+        let next = match rest.first() {
+            Some(next) => next,
+            None => return Parse::Pending,
+        };
+        return match plain(next) {
+            Some('g') => Parse::Done(Action::Move(Motion::FirstLine), prefix),
+            Some('u') => operator(Operator::Lowercase, prefix, &rest[1..]),
+            Some('U') => operator(Operator::Uppercase, prefix, &rest[1..]),
+            _ => Parse::Done(Action::Other(*first, Some(*next)), prefix),
+        };
+    }
+
+    if let Some(op) = Operator::from_key(c) {
+        return operator(op, prefix, rest);
+    }
+
+    match Motion::from_key(c) {
+        Some(motion) => Parse::Done(Action::Move(motion), prefix),
+        None => Parse::Done(Action::Other(*first, None), prefix),
+    }
+}
+
+/// Reads the keys typed in visual mode so far as a command. Operators act on the selection
+/// right away, and `i` and `a` select a text object instead of inserting.
+pub fn parse_visual(keys: &[KeyEvent]) -> Parse {
+    let (prefix, keys) = match read_prefix(keys) {
+        Ok(read) => read,
+        Err(parse) => return parse,
+    };
+    let (first, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Parse::Pending,
+    };
+    let c = match plain(first) {
+        Some(c) => c,
+        None => return Parse::Done(Action::Other(*first, None), prefix),
+    };
+    let done = |action| Parse::Done(action, prefix);
+
+    let operator = match c {
+        'x' => Some(Operator::Delete),
+        'u' => Some(Operator::Lowercase),
+        'U' => Some(Operator::Uppercase),
+        'g' => {
+            let next = match rest.first() {
+                Some(next) => next,
+                None => return Parse::Pending,
+            };
+            match plain(next) {
+                Some('g') => return done(Action::Move(Motion::FirstLine)),
+                Some('u') => Some(Operator::Lowercase),
+                Some('U') => Some(Operator::Uppercase),
+                _ => return done(Action::Other(*first, Some(*next))),
+            }
+        }
+        'i' | 'a' => {
+            return match rest.first() {
+                Some(next) => match plain(next).and_then(|key| TextObject::from_key(key, c == 'a'))
+                {
+                    Some(object) => done(Action::Select(object)),
+                    None => Parse::Invalid,
+                },
+                None => Parse::Pending,
+            }
+        }
+        _ => Operator::from_key(c),
+    };
+
+    match (operator, Motion::from_key(c)) {
+        (Some(op), _) => done(Action::Operate(op, Target::Selection)),
+        (None, Some(motion)) => done(Action::Move(motion)),
+        (None, None) => done(Action::Other(*first, None)),
+    }
+}
+
+// The keys after an operator: a motion, a text object or the operator again. The motion can
+// have a count of its own, the two counts are multiplied like in `2d3w`.
+fn operator(op: Operator, mut prefix: Prefix, keys: &[KeyEvent]) -> Parse {
+    let (more, keys) = read_count(keys);
+    prefix.count = multiply(prefix.count, more);
+
+    let (first, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Parse::Pending,
+    };
+    let c = match plain(first) {
+        Some(c) => c,
+        None => return Parse::Invalid,
+    };
+    let done = |target| Parse::Done(Action::Operate(op, target), prefix);
+
+    if c == op.key() {
+        return done(Target::Lines);
+    }
+
+    match c {
+        'i' | 'a' => match rest.first() {
+            Some(next) => match plain(next).and_then(|key| TextObject::from_key(key, c == 'a')) {
+                Some(object) => done(Target::Object(object)),
+                None => Parse::Invalid,
+            },
+            None => Parse::Pending,
+        },
+        'g' => match rest.first().map(plain) {
+            Some(Some('g')) => done(Target::Motion(Motion::FirstLine)),
+            // `gugu` and `gUgU` are the same as `guu` and `gUU`.
+            Some(Some(key)) if key == op.key() && op.after_g() => done(Target::Lines),
+            Some(_) => Parse::Invalid,
+            None => Parse::Pending,
+        },
+        _ => match Motion::from_key(c) {
+            Some(motion) => done(Target::Motion(motion)),
+            None => Parse::Invalid,
+        },
+    }
+}
@@ -0,0 +1,126 @@
+/// Settings that can be changed with the `set` command, e.g. `set tabstop=4` or `set noexpandtab`.
+pub struct Options {
+    pub tabstop: usize,
+    pub softtabstop: usize,
+    pub shiftwidth: usize,
+    pub expandtab: bool,
+    // Keep the previous version of a file as `filename~` when saving.
+    pub backup: bool,
+    // Reload files that changed on disk by themselves, as long as they have no unsaved changes.
+    pub autoread: bool,
+    // How long to wait for the rest of a command like `gg`, in milliseconds.
+    pub timeoutlen: usize,
+    // Save modified files after this many seconds without a key, 0 turns it off.
+    pub autosave: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tabstop: 8,
+            softtabstop: 4,
+            shiftwidth: 4,
+            expandtab: true,
+            backup: false,
+            autoread: false,
+            timeoutlen: 1000,
+            autosave: 0,
+        }
+    }
+}
+
+impl Options {
+    /// The width of one level of indentation, a `shiftwidth` of 0 falls back to `tabstop`.
+    pub fn shiftwidth(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+
+    /// How far Tab and Backspace move in insert mode, a `softtabstop` of 0 falls back to `tabstop`.
+    pub fn softtabstop(&self) -> usize {
+        if self.softtabstop == 0 {
+            self.tabstop
+        } else {
+            self.softtabstop
+        }
+    }
+
+    /// The whitespace needed to get from column `from` to column `to`.
+    /// Tabs are used wherever they fit, unless `expandtab` is on.
+    pub fn whitespace(&self, from: usize, to: usize) -> String {
+        let mut result = String::new();
+        let mut column = from;
+        while column < to {
+            let next_stop = column + self.tabstop - column % self.tabstop;
+            if !self.expandtab && next_stop <= to {
+                result.push('\t');
+                column = next_stop;
+            } else {
+                result.push(' ');
+                column += 1;
+            }
+        }
+
+        result
+    }
+
+    /// Handles a single argument of the `set` command.
+    /// Returns a message to show when the option was queried with `name?`.
+    pub fn set(&mut self, argument: &str) -> Result<Option<String>, String> {
+        if let Some(name) = argument.strip_suffix('?') {
+            let value = match name {
+                "tabstop" | "ts" => self.tabstop.to_string(),
+                "softtabstop" | "sts" => self.softtabstop.to_string(),
+                "shiftwidth" | "sw" => self.shiftwidth.to_string(),
+                "timeoutlen" | "tm" => self.timeoutlen.to_string(),
+                "autosave" => self.autosave.to_string(),
+                "expandtab" | "et" => {
+                    let prefix = if self.expandtab { "" } else { "no" };
+                    return Ok(Some(format!("{}expandtab", prefix)));
+                }
+                "backup" | "bk" => {
+                    let prefix = if self.backup { "" } else { "no" };
+                    return Ok(Some(format!("{}backup", prefix)));
+                }
+                "autoread" | "ar" => {
+                    let prefix = if self.autoread { "" } else { "no" };
+                    return Ok(Some(format!("{}autoread", prefix)));
+                }
+                _ => return Err(format!("Unknown option: {}", name)),
+            };
+
+            return Ok(Some(format!("{}={}", name, value)));
+        }
+
+        if let Some((name, value)) = argument.split_once('=') {
+            let value: usize = value
+                .parse()
+                .map_err(|_| format!("Invalid number: {}", value))?;
+
+            match name {
+                "tabstop" | "ts" if value > 0 => self.tabstop = value,
+                "tabstop" | "ts" => return Err("tabstop has to be at least 1".to_string()),
+                "softtabstop" | "sts" => self.softtabstop = value,
+                "shiftwidth" | "sw" => self.shiftwidth = value,
+                "timeoutlen" | "tm" => self.timeoutlen = value,
+                "autosave" => self.autosave = value,
+                _ => return Err(format!("Unknown option: {}", name)),
+            }
+        } else {
+            match argument {
+                "expandtab" | "et" => self.expandtab = true,
+                "noexpandtab" | "noet" => self.expandtab = false,
+                "backup" | "bk" => self.backup = true,
+                "nobackup" | "nobk" => self.backup = false,
+                "autoread" | "ar" => self.autoread = true,
+                "noautoread" | "noar" => self.autoread = false,
+                _ => return Err(format!("Unknown option: {}", argument)),
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,160 @@
+use std::iter;
+
+/// Text that was deleted or yanked, so it can be put back.
+#[derive(Clone, Default, Debug)]
+pub struct Register {
+    pub text: String,
+    // Whether it's whole lines, which go in as lines of their own.
+    pub linewise: bool,
+    // Whether it's a block, one line of the text for every line it covered. These go in as
+    // columns, each line below the one before.
+    pub blockwise: bool,
+}
+
+impl Register {
+    pub fn new(text: String, linewise: bool) -> Self {
+        Self {
+            text,
+            linewise,
+            blockwise: false,
+        }
+    }
+
+    pub fn block(lines: &[String]) -> Self {
+        Self {
+            text: lines.join("\n"),
+            linewise: false,
+            blockwise: true,
+        }
+    }
+}
+
+/// Every register, like vim has them. The ones the editor keeps track of itself, like `%` for
+/// the filename, are filled in by the editor.
+#[derive(Default)]
+pub struct Registers {
+    // What `p` puts without a register, the text that was deleted or yanked last.
+    unnamed: Register,
+    // `a` to `z`.
+    named: [Register; 26],
+    // `0` is the last yank, `1` to `9` are the last deletes with the newest one first.
+    numbered: [Register; 10],
+    // Deletes within a line, `-`.
+    small: Register,
+    // The text typed the last time insert mode was left, `.`.
+    pub inserted: String,
+    // The last command line, `:`.
+    pub command: String,
+    // What was last copied to the system clipboard with `+` and `*`, for when the clipboard
+    // can't be read back.
+    selections: [Register; 2],
+}
+
+/// Whether a register can be named with `"` in front of a command. `%`, `:` and `.` can only be
+/// read from.
+pub fn is_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_' | '%' | ':' | '.' | '+' | '*')
+}
+
+pub fn is_read_only(name: char) -> bool {
+    matches!(name, '%' | ':' | '.')
+}
+
+impl Registers {
+    /// Reads a register. The black hole register `_` is always empty.
+    pub fn get(&self, name: char) -> Option<Register> {
+        let register = match name {
+            '"' => self.unnamed.clone(),
+            'a'..='z' | 'A'..='Z' => {
+                self.named[(name.to_ascii_lowercase() as u8 - b'a') as usize].clone()
+            }
+            '0'..='9' => self.numbered[(name as u8 - b'0') as usize].clone(),
+            '-' => self.small.clone(),
+            '+' => self.selections[0].clone(),
+            '*' => self.selections[1].clone(),
+            '.' => Register::new(self.inserted.clone(), false),
+            ':' => Register::new(self.command.clone(), false),
+            _ => return None,
+        };
+
+        Some(register)
+    }
+
+    /// Stores yanked text in the register named for it, or in `0` without one.
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.write(name, register),
+            _ => {
+                self.numbered[0] = register.clone();
+                self.unnamed = register;
+            }
+        }
+    }
+
+    /// Stores deleted text in the register named for it. Without one, deletes of whole lines
+    /// or across lines go to `1`, moving the older ones to `2` and on, and smaller deletes go
+    /// to `-`.
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.write(name, register),
+            _ => {
+                if register.linewise || register.text.contains('\n') {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = register.clone();
+                } else {
+                    self.small = register.clone();
+                }
+                self.unnamed = register;
+            }
+        }
+    }
+
+    // Uppercase letters add to the register instead of replacing it.
+    fn write(&mut self, name: char, register: Register) {
+        let slot = match name {
+            'a'..='z' | 'A'..='Z' => {
+                &mut self.named[(name.to_ascii_lowercase() as u8 - b'a') as usize]
+            }
+            '0'..='9' => &mut self.numbered[(name as u8 - b'0') as usize],
+            '-' => &mut self.small,
+            '+' => &mut self.selections[0],
+            '*' => &mut self.selections[1],
+            // The black hole register throws the text away, and leaves the others alone.
+            _ => return,
+        };
+
+        if name.is_ascii_uppercase() && !slot.text.is_empty() {
+            // Lines added to text in a line start on a line of their own, and the other way
+            // around.
+            if (register.linewise || slot.linewise) && !slot.text.ends_with('\n') {
+                slot.text.push('\n');
+            }
+            // Blocks get more lines, any other text makes them plain text again.
+            if register.blockwise && slot.blockwise {
+                slot.text.push('\n');
+            }
+            slot.text.push_str(&register.text);
+            slot.linewise |= register.linewise;
+            slot.blockwise &= register.blockwise;
+            if slot.linewise && !slot.text.ends_with('\n') {
+                slot.text.push('\n');
+            }
+        } else {
+            *slot = register;
+        }
+
+        self.unnamed = slot.clone();
+    }
+
+    /// The registers that aren't empty, for `registers`.
+    pub fn list(&self) -> Vec<(char, Register)> {
+        let names = iter::once('"')
+            .chain('0'..='9')
+            .chain('a'..='z')
+            .chain(['-', '.', ':', '+', '*']);
+        names
+            .filter_map(|name| Some((name, self.get(name)?)))
+            .filter(|(_, register)| !register.text.is_empty())
+            .collect()
+    }
+}
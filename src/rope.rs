@@ -0,0 +1,587 @@
+use std::cmp;
+use std::mem;
+
+use super::gap_buffer::GapBuffer;
+
+// Amount of lines a leaf holds before it gets split in two.
+const MAX_LEAF: usize = 256;
+
+/// A balanced tree of lines.
+///
+/// Every leaf holds a small run of lines, every branch caches how many lines and characters
+/// are beneath it, so finding a line or mapping a character offset to a line is O(log n).
+pub struct Rope {
+    root: Node,
+}
+
+enum Node {
+    Leaf(Vec<GapBuffer>),
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        lines: usize,
+        chars: usize,
+        depth: usize,
+        leaves: usize,
+    },
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self {
+            root: Node::Leaf(vec![]),
+        }
+    }
+}
+
+impl Node {
+    fn lines(&self) -> usize {
+        match self {
+            Self::Leaf(lines) => lines.len(),
+            Self::Branch { lines, .. } => *lines,
+        }
+    }
+
+    /// Characters in the node, the newlines between lines are not counted.
+    fn chars(&self) -> usize {
+        match self {
+            Self::Leaf(lines) => lines.iter().map(|line| line.len).sum(),
+            Self::Branch { chars, .. } => *chars,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Self::Leaf(_) => 0,
+            Self::Branch { depth, .. } => *depth,
+        }
+    }
+
+    fn leaves(&self) -> usize {
+        match self {
+            Self::Leaf(_) => 1,
+            Self::Branch { leaves, .. } => *leaves,
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Self {
+        Self::Branch {
+            lines: left.lines() + right.lines(),
+            chars: left.chars() + right.chars(),
+            depth: cmp::max(left.depth(), right.depth()) + 1,
+            leaves: left.leaves() + right.leaves(),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Builds a balanced tree out of a list of leaves.
+    fn build(mut leaves: Vec<Node>) -> Self {
+        if leaves.is_empty() {
+            return Self::Leaf(vec![]);
+        }
+
+        while leaves.len() > 1 {
+            let mut paired = Vec::with_capacity(leaves.len() / 2 + 1);
+            let mut iter = leaves.into_iter();
+            while let Some(left) = iter.next() {
+                match iter.next() {
+                    Some(right) => paired.push(Self::branch(left, right)),
+                    None => paired.push(left),
+                }
+            }
+            leaves = paired;
+        }
+
+        leaves.pop().unwrap()
+    }
+
+    /// Recomputes the cached values of a branch after one of its children changed.
+    fn refresh(&mut self) {
+        if let Self::Branch {
+            left,
+            right,
+            lines,
+            chars,
+            depth,
+            leaves,
+        } = self
+        {
+            *lines = left.lines() + right.lines();
+            *chars = left.chars() + right.chars();
+            *depth = cmp::max(left.depth(), right.depth()) + 1;
+            *leaves = left.leaves() + right.leaves();
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<&GapBuffer> {
+        match self {
+            Self::Leaf(lines) => lines.get(index),
+            Self::Branch { left, right, .. } => {
+                let left_lines = left.lines();
+                if index < left_lines {
+                    left.get(index)
+                } else {
+                    right.get(index - left_lines)
+                }
+            }
+        }
+    }
+
+    fn edit<R>(&mut self, index: usize, f: impl FnOnce(&mut GapBuffer) -> R) -> Option<R> {
+        let result = match self {
+            Self::Leaf(lines) => return lines.get_mut(index).map(f),
+            Self::Branch { left, right, .. } => {
+                let left_lines = left.lines();
+                if index < left_lines {
+                    left.edit(index, f)
+                } else {
+                    right.edit(index - left_lines, f)
+                }
+            }
+        };
+
+        self.refresh();
+        result
+    }
+
+    fn insert(&mut self, index: usize, line: GapBuffer) {
+        match self {
+            Self::Leaf(lines) => {
+                let index = cmp::min(index, lines.len());
+                lines.insert(index, line);
+
+                if lines.len() > MAX_LEAF {
+                    let right = lines.split_off(lines.len() / 2);
+                    let left = mem::take(lines);
+                    *self = Self::branch(Self::Leaf(left), Self::Leaf(right));
+                }
+            }
+            Self::Branch { left, right, .. } => {
+                let left_lines = left.lines();
+                if index <= left_lines {
+                    left.insert(index, line);
+                } else {
+                    right.insert(index - left_lines, line);
+                }
+                self.refresh();
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<GapBuffer> {
+        let removed = match self {
+            Self::Leaf(lines) => {
+                return if index < lines.len() {
+                    Some(lines.remove(index))
+                } else {
+                    None
+                };
+            }
+            Self::Branch { left, right, .. } => {
+                let left_lines = left.lines();
+                if index < left_lines {
+                    left.remove(index)
+                } else {
+                    right.remove(index - left_lines)
+                }
+            }
+        };
+
+        // A branch with an empty side is replaced by its other side, and two leaves that fit
+        // in one are merged, so deleting doesn't leave lots of tiny leaves behind.
+        if let Self::Branch { left, right, .. } = self {
+            if left.lines() == 0 {
+                *self = mem::replace(right.as_mut(), Self::Leaf(vec![]));
+            } else if right.lines() == 0 {
+                *self = mem::replace(left.as_mut(), Self::Leaf(vec![]));
+            } else if let (Self::Leaf(left), Self::Leaf(right)) = (left.as_mut(), right.as_mut()) {
+                if left.len() + right.len() <= MAX_LEAF / 2 {
+                    left.append(right);
+                    *self = Self::Leaf(mem::take(left));
+                }
+            }
+        }
+
+        self.refresh();
+        removed
+    }
+
+    /// Amount of characters in front of the line at `index`, newlines included.
+    fn line_to_offset(&self, index: usize) -> usize {
+        match self {
+            Self::Leaf(lines) => lines.iter().take(index).map(|line| line.len + 1).sum(),
+            Self::Branch { left, right, .. } => {
+                let left_lines = left.lines();
+                if index < left_lines {
+                    left.line_to_offset(index)
+                } else {
+                    left.chars() + left_lines + right.line_to_offset(index - left_lines)
+                }
+            }
+        }
+    }
+
+    /// Returns the line the character at `offset` is on, and how far into the line it is.
+    fn offset_to_line(&self, offset: usize) -> (usize, usize) {
+        match self {
+            Self::Leaf(lines) => {
+                let mut offset = offset;
+                for (index, line) in lines.iter().enumerate() {
+                    if offset <= line.len {
+                        return (index, offset);
+                    }
+                    offset -= line.len + 1;
+                }
+
+                (lines.len(), offset)
+            }
+            Self::Branch { left, right, .. } => {
+                let left_size = left.chars() + left.lines();
+                if offset < left_size {
+                    left.offset_to_line(offset)
+                } else {
+                    let (line, x) = right.offset_to_line(offset - left_size);
+                    (line + left.lines(), x)
+                }
+            }
+        }
+    }
+
+    fn into_lines(self, lines: &mut Vec<GapBuffer>) {
+        match self {
+            Self::Leaf(mut leaf) => lines.append(&mut leaf),
+            Self::Branch { left, right, .. } => {
+                left.into_lines(lines);
+                right.into_lines(lines);
+            }
+        }
+    }
+}
+
+impl Rope {
+    pub fn from_lines(lines: impl IntoIterator<Item = GapBuffer>) -> Self {
+        let mut leaves = vec![];
+        let mut leaf = Vec::with_capacity(MAX_LEAF / 2);
+        for line in lines {
+            leaf.push(line);
+            if leaf.len() == MAX_LEAF / 2 {
+                leaves.push(Node::Leaf(mem::replace(
+                    &mut leaf,
+                    Vec::with_capacity(MAX_LEAF / 2),
+                )));
+            }
+        }
+
+        if !leaf.is_empty() {
+            leaves.push(Node::Leaf(leaf));
+        }
+
+        Self {
+            root: Node::build(leaves),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.lines()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total amount of characters, with a newline counted between each line.
+    pub fn len_chars(&self) -> usize {
+        self.root.chars() + self.len().saturating_sub(1)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&GapBuffer> {
+        self.root.get(index)
+    }
+
+    /// Gives mutable access to a single line, the cached sizes are updated afterwards.
+    pub fn edit<R>(&mut self, index: usize, f: impl FnOnce(&mut GapBuffer) -> R) -> Option<R> {
+        self.root.edit(index, f)
+    }
+
+    pub fn insert(&mut self, index: usize, line: GapBuffer) {
+        self.root.insert(index, line);
+        self.rebalance();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<GapBuffer> {
+        let removed = self.root.remove(index);
+        self.rebalance();
+        removed
+    }
+
+    /// Splits line `y` at `x`, everything after `x` ends up on a new line below it.
+    pub fn split_line(&mut self, y: usize, x: usize) {
+        if let Some(new) = self.edit(y, |line| line.split(x)) {
+            self.insert(y + 1, new);
+        }
+    }
+
+    /// Appends line `y` to the end of the line above it.
+    pub fn join_line(&mut self, y: usize) {
+        if y == 0 || y >= self.len() {
+            return;
+        }
+
+        if let Some(current) = self.remove(y) {
+            self.edit(y - 1, |above| above.append(&current));
+        }
+    }
+
+    pub fn line_to_offset(&self, y: usize) -> usize {
+        self.root.line_to_offset(y)
+    }
+
+    pub fn offset_to_line(&self, offset: usize) -> (usize, usize) {
+        self.root.offset_to_line(offset)
+    }
+
+    pub fn iter(&self) -> Lines<'_> {
+        self.iter_from(0)
+    }
+
+    /// Iterates over the lines from `index` on. Finding the first one is O(log n), every line
+    /// after it comes right out of its leaf.
+    pub fn iter_from(&self, index: usize) -> Lines<'_> {
+        let mut stack = vec![];
+        let mut node = &self.root;
+        let mut index = index;
+        loop {
+            match node {
+                Node::Leaf(lines) => {
+                    let leaf = lines.get(index..).unwrap_or_default().iter();
+                    return Lines { stack, leaf };
+                }
+                Node::Branch { left, right, .. } => {
+                    let left_lines = left.lines();
+                    if index < left_lines {
+                        stack.push(right);
+                        node = left;
+                    } else {
+                        index -= left_lines;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rebuilds the tree once it gets lopsided, the depth is kept within twice the ideal for
+    /// the leaves there are. The rebuilt tree has half full leaves again.
+    fn rebalance(&mut self) {
+        let leaves = self.root.leaves();
+        let ideal = (usize::BITS - leaves.leading_zeros()) as usize;
+        if self.root.depth() <= ideal * 2 + 2 {
+            return;
+        }
+
+        let root = mem::replace(&mut self.root, Node::Leaf(vec![]));
+        let mut lines = Vec::with_capacity(root.lines());
+        root.into_lines(&mut lines);
+        *self = Self::from_lines(lines);
+    }
+}
+
+pub struct Lines<'a> {
+    stack: Vec<&'a Node>,
+    leaf: std::slice::Iter<'a, GapBuffer>,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a GapBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.leaf.next() {
+                return Some(line);
+            }
+
+            match self.stack.pop()? {
+                Node::Leaf(lines) => self.leaf = lines.iter(),
+                Node::Branch { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: usize) -> Rope {
+        Rope::from_lines((0..lines).map(|i| GapBuffer::new(&format!("line {}", i))))
+    }
+
+    fn text(rope: &Rope) -> Vec<String> {
+        rope.iter().map(|line| line.line()).collect()
+    }
+
+    // The sizes cached in every branch match what's below it.
+    fn check(node: &Node) -> (usize, usize, usize) {
+        match node {
+            Node::Leaf(lines) => (lines.len(), node.chars(), 0),
+            Node::Branch {
+                left,
+                right,
+                lines,
+                chars,
+                depth,
+                leaves,
+            } => {
+                assert_eq!(*leaves, left.leaves() + right.leaves());
+                let (left_lines, left_chars, left_depth) = check(left);
+                let (right_lines, right_chars, right_depth) = check(right);
+                assert_eq!(*lines, left_lines + right_lines);
+                assert_eq!(*chars, left_chars + right_chars);
+                assert_eq!(*depth, cmp::max(left_depth, right_depth) + 1);
+                (*lines, *chars, *depth)
+            }
+        }
+    }
+
+    #[test]
+    fn inserts_and_removes_across_leaves() {
+        let mut rope = numbered(1000);
+        let mut expected = text(&rope);
+        // Lines are loaded into leaves of half the maximum, so these are on leaf edges.
+        let edge = MAX_LEAF / 2;
+
+        for index in [0, edge - 1, edge, edge + 1, 3 * edge, 1000] {
+            rope.insert(index, GapBuffer::new("new"));
+            expected.insert(index, "new".to_string());
+        }
+        assert_eq!(text(&rope), expected);
+        check(&rope.root);
+
+        for index in [edge, edge - 1, 0, 500, 999] {
+            let removed = rope.remove(index).map(|line| line.line());
+            assert_eq!(removed, Some(expected.remove(index)));
+        }
+        assert_eq!(text(&rope), expected);
+        assert_eq!(rope.remove(5000).map(|line| line.line()), None);
+        check(&rope.root);
+
+        // Removing everything leaves an empty rope behind.
+        while rope.remove(0).is_some() {}
+        assert!(rope.is_empty());
+        assert_eq!(rope.len_chars(), 0);
+    }
+
+    #[test]
+    fn joins_and_splits_lines_at_leaf_edges() {
+        let mut rope = numbered(MAX_LEAF);
+        let edge = MAX_LEAF / 2;
+
+        // The first line of the second leaf goes to the end of the last line of the first.
+        rope.join_line(edge);
+        assert_eq!(rope.len(), MAX_LEAF - 1);
+        let joined = format!("line {}line {}", edge - 1, edge);
+        assert_eq!(rope.get(edge - 1).unwrap().line(), joined);
+        check(&rope.root);
+
+        rope.split_line(edge - 1, format!("line {}", edge - 1).len());
+        assert_eq!(text(&rope), text(&numbered(MAX_LEAF)));
+        check(&rope.root);
+
+        // Splitting lines over and over splits the leaf too.
+        for _ in 0..MAX_LEAF {
+            rope.split_line(edge, 0);
+        }
+        assert_eq!(rope.len(), 2 * MAX_LEAF);
+        assert_eq!(rope.get(edge + MAX_LEAF).unwrap().line(), "line 128");
+        check(&rope.root);
+
+        // The first line and lines that don't exist can't be joined.
+        rope.join_line(0);
+        rope.join_line(rope.len());
+        assert_eq!(rope.len(), 2 * MAX_LEAF);
+    }
+
+    #[test]
+    fn iterating_starts_at_any_line() {
+        let rope = numbered(1000);
+        let all = text(&rope);
+        for index in [0, 1, 127, 128, 129, 500, 999, 1000, 2000] {
+            let lines: Vec<String> = rope.iter_from(index).map(|line| line.line()).collect();
+            assert_eq!(lines, all.get(index..).unwrap_or_default());
+        }
+    }
+
+    #[test]
+    fn offsets_and_lines_map_back_and_forth() {
+        let mut rope = numbered(700);
+        rope.edit(300, |line| line.insert('é', 2));
+        rope.insert(400, GapBuffer::default());
+
+        let mut offset = 0;
+        for y in 0..rope.len() {
+            assert_eq!(rope.line_to_offset(y), offset);
+            let len = rope.get(y).unwrap().len;
+            for x in 0..=len {
+                assert_eq!(rope.offset_to_line(offset + x), (y, x));
+            }
+            offset += len + 1;
+        }
+        // Counting the newline after every line but the last one.
+        assert_eq!(rope.len_chars(), offset - 1);
+    }
+
+    #[test]
+    fn deep_trees_get_rebalanced() {
+        let mut rope = Rope::default();
+        // Always inserting at the front keeps splitting the leftmost leaf, which would make
+        // the tree as deep as it has leaves.
+        for i in 0..20_000 {
+            rope.insert(0, GapBuffer::new(&i.to_string()));
+        }
+
+        let (lines, _, depth) = check(&rope.root);
+        assert_eq!(lines, 20_000);
+        let leaves = 20_000 / (MAX_LEAF / 2);
+        let ideal = (usize::BITS - leaves.leading_zeros()) as usize;
+        assert!(
+            depth <= ideal * 2 + 2,
+            "depth {} for {} leaves",
+            depth,
+            leaves
+        );
+        assert_eq!(rope.get(0).unwrap().line(), "19999");
+        assert_eq!(rope.get(19_999).unwrap().line(), "0");
+    }
+
+    #[test]
+    fn edits_after_deleting_most_lines_stay_cheap() {
+        let mut rope = numbered(100_000);
+        // Deleting all over the file leaves leaves with only a few lines in them.
+        for i in 0..99_000 {
+            rope.remove((i * 7919) % rope.len());
+        }
+        assert_eq!(rope.len(), 1_000);
+        let (_, _, depth) = check(&rope.root);
+        let ideal = (usize::BITS - rope.root.leaves().leading_zeros()) as usize;
+        assert!(depth <= ideal * 2 + 2);
+        assert!(
+            rope.root.leaves() <= 1_000 / 8,
+            "{} leaves",
+            rope.root.leaves()
+        );
+
+        // Edits in the middle don't rebuild the tree, which would move the first line.
+        let first = rope.get(0).unwrap() as *const GapBuffer;
+        for _ in 0..10_000 {
+            rope.insert(500, GapBuffer::new("new"));
+            rope.remove(500);
+        }
+        assert_eq!(rope.get(0).unwrap() as *const GapBuffer, first);
+        assert_eq!(rope.len(), 1_000);
+        check(&rope.root);
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A temporary file next to `path`, so that renaming it over `path` stays on the same file system.
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.hecto-{}.tmp", name, process::id()))
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+/// Gives the new file the same owner as the file it replaces. Only root can give a file away,
+/// so for everyone else keeping the group is the best that can be done.
+#[cfg(unix)]
+fn copy_owner(from: &fs::Metadata, to: &Path) {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    if chown(to, Some(from.uid()), Some(from.gid())).is_err() {
+        let _ = chown(to, None, Some(from.gid()));
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_from: &fs::Metadata, _to: &Path) {}
+
+/// Creates the temporary file with the permissions and owner of the file it replaces, before
+/// anything is written to it. A private file would be readable by everyone until the rename
+/// otherwise.
+fn create_temporary(path: &Path, original: Option<&fs::Metadata>) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    // Never write through whatever else is already there, like a symlink.
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(metadata) = original {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode() & 0o777);
+    }
+    let file = options.open(path)?;
+
+    if let Some(metadata) = original {
+        // Changing the owner drops the setuid and setgid bits, so the permissions come after.
+        copy_owner(metadata, path);
+        file.set_permissions(metadata.permissions())?;
+    }
+
+    Ok(file)
+}
+
+/// Writes `bytes` to `path` without ever leaving a half written file behind.
+///
+/// Everything goes to a temporary file in the same directory first, which is synced to disk and
+/// then renamed over the original. A crash or a full disk leaves the original untouched.
+pub fn write_atomically(path: &Path, bytes: &[u8], backup: bool) -> Result<(), std::io::Error> {
+    // Writing through a symlink should replace the file it points to, not the link itself.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+    let temporary = temporary_path(&path);
+
+    // Left behind by a crash, `create_new` would fail on it.
+    let _ = fs::remove_file(&temporary);
+
+    let result = (|| {
+        let mut file = create_temporary(&temporary, original.as_ref())?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+
+        if original.is_some() && backup {
+            fs::copy(&path, backup_path(&path))?;
+        }
+
+        fs::rename(&temporary, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+        return result;
+    }
+
+    // The rename only survives a crash once the directory itself is synced.
+    if let Some(directory) = path.parent() {
+        if let Ok(directory) = fs::File::open(directory) {
+            let _ = directory.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    fn directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hecto-save-{}-{}", name, process::id()));
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn replaced_files_keep_their_permissions() {
+        let dir = directory("permissions");
+        let path = dir.join("secret");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomically(&path, b"new", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "old");
+        assert_eq!(mode(&backup_path(&path)), 0o600);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomically(&path, b"newer", false).unwrap();
+        assert_eq!(mode(&path), 0o640);
+
+        // Nothing is left behind next to it.
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["secret", "secret~"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinks_are_written_through() {
+        let dir = directory("symlink");
+        let target = dir.join("target");
+        let link = dir.join("link");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomically(&link, b"new", false).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn new_files_and_leftover_temporary_files() {
+        let dir = directory("new");
+        let path = dir.join("file");
+
+        // New files get created.
+        write_atomically(&path, b"text", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "text");
+
+        // A temporary file left by a crash doesn't get in the way.
+        fs::write(temporary_path(&path), "junk").unwrap();
+        write_atomically(&path, b"new", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temporary_path(&path).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use super::history::Change;
+use super::save;
+use super::undo_file;
+
+const MAGIC: &str = "hecto-swap 2";
+
+/// What a swap file holds: the changes made since the file was last saved,
+/// and the process that made them, which is the one editing the file.
+pub struct Swap {
+    pub pid: u32,
+    // When the process started, pids get reused. Empty if that couldn't be found out.
+    pub started: String,
+    // Hash of the file the changes were made to, they only fit on top of that version.
+    pub hash: u64,
+    pub changes: Vec<Change>,
+}
+
+/// What `find` turns up for a file that is being opened.
+pub enum Found {
+    Nothing,
+    // Another hecto that is still running has the file open.
+    Running(u32),
+    // Left behind by a hecto that didn't exit cleanly.
+    Stale(Swap),
+}
+
+/// Swap files are kept in `$XDG_STATE_HOME/hecto/swap`, named like undo files.
+fn path(filename: &str) -> Option<PathBuf> {
+    let mut path = undo_file::path_in("swap", filename)?.into_os_string();
+    path.push(".swp");
+    Some(PathBuf::from(path))
+}
+
+/// When a process started, None if there is no such process. On Linux that's the clock tick
+/// since boot together with the id of the boot, since the ticks start over with every boot.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The name in parentheses can have spaces in it, the fields after it can't.
+    // Field 22 of the file is the start time, the 20th after the name.
+    let (_, fields) = stat.rsplit_once(')')?;
+    let ticks = fields.split_whitespace().nth(19)?;
+    let boot = fs::read_to_string("/proc/sys/kernel/random/boot_id").unwrap_or_default();
+
+    Some(format!("{} {}", boot.trim(), ticks))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn start_time(pid: u32) -> Option<String> {
+    let output = process::Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    (output.status.success() && !started.is_empty()).then_some(started)
+}
+
+#[cfg(not(unix))]
+fn start_time(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Whether the process that wrote a swap file is still running. A process that got the same pid
+/// later on started at a different time.
+fn is_running(pid: u32, started: &str) -> bool {
+    start_time(pid).is_some_and(|start| started.is_empty() || start == started)
+}
+
+pub fn write(filename: &str, hash: u64, changes: &[Change]) -> Result<(), std::io::Error> {
+    let path = path(filename)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No swap directory"))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    save::write_atomically(&path, format(hash, changes).as_bytes(), false)
+}
+
+fn format(hash: u64, changes: &[Change]) -> String {
+    let pid = process::id();
+    let started = start_time(pid).unwrap_or_default();
+    let mut contents = format!("{}\n{}\n{}\n{:016x}\n", MAGIC, pid, started, hash);
+    for change in changes {
+        contents.push_str(&change.serialize());
+        contents.push('\n');
+    }
+
+    contents
+}
+
+fn read(filename: &str) -> Option<Swap> {
+    parse(&fs::read_to_string(path(filename)?).ok()?)
+}
+
+fn parse(contents: &str) -> Option<Swap> {
+    let mut lines = contents.lines();
+
+    if lines.next()? != MAGIC {
+        return None;
+    }
+
+    let pid = lines.next()?.parse().ok()?;
+    let started = lines.next()?.to_string();
+    let hash = u64::from_str_radix(lines.next()?, 16).ok()?;
+    let changes = lines.map(Change::deserialize).collect::<Option<_>>()?;
+
+    Some(Swap {
+        pid,
+        started,
+        hash,
+        changes,
+    })
+}
+
+/// Looks for the swap file of a file that is about to be opened.
+pub fn find(filename: &str) -> Found {
+    let own = |swap: &Swap| swap.pid == process::id() && is_running(swap.pid, &swap.started);
+
+    match read(filename) {
+        Some(swap) if own(&swap) => Found::Nothing,
+        Some(swap) if is_running(swap.pid, &swap.started) => Found::Running(swap.pid),
+        Some(swap) if swap.changes.is_empty() => Found::Nothing,
+        Some(swap) => Found::Stale(swap),
+        None => Found::Nothing,
+    }
+}
+
+pub fn remove(filename: &str) {
+    if let Some(path) = path(filename) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes() -> Vec<Change> {
+        ["i 0 hello\\nworld", "d 3 lo"]
+            .iter()
+            .map(|line| Change::deserialize(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn swap_files_survive_a_round_trip() {
+        let swap = parse(&format(0xabc, &changes())).unwrap();
+        assert_eq!(swap.pid, process::id());
+        assert_eq!(swap.hash, 0xabc);
+        let changes: Vec<String> = swap.changes.iter().map(Change::serialize).collect();
+        assert_eq!(changes, ["i 0 hello\\nworld", "d 3 lo"]);
+
+        assert!(parse(&format(0, &[])).unwrap().changes.is_empty());
+        assert!(parse("hecto-swap 1\n1\n0000000000000000\n").is_none());
+        assert!(parse(&format(0, &[]).replace("\n0000", "\nzzzz")).is_none());
+    }
+
+    #[test]
+    fn reused_pids_are_not_running() {
+        let swap = parse(&format(0, &[])).unwrap();
+        assert!(!swap.started.is_empty());
+        assert!(is_running(swap.pid, &swap.started));
+        // Without a start time only the pid can be checked.
+        assert!(is_running(swap.pid, ""));
+        assert!(!is_running(swap.pid, "some other process"));
+
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!is_running(pid, ""));
+    }
+}
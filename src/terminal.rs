@@ -1,11 +1,25 @@
-use std::io::{Stdout, Write};
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
 
+use super::clipboard::{self, Selection};
 use super::editor::Position;
+use super::frame::{Cell, Frame};
 
 use crossterm::cursor;
-use crossterm::execute;
-use crossterm::style::{Color, SetBackgroundColor, SetForegroundColor};
-use crossterm::terminal::{enable_raw_mode, size, Clear, ClearType};
+use crossterm::event::{self, Event};
+use crossterm::queue;
+use crossterm::style::{
+    Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::terminal::{
+    self as term, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+};
+
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color as TuiColor, Modifier};
+
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Clone)]
 pub struct Size {
@@ -38,46 +52,215 @@ impl PartialEq<(u16, u16)> for Size {
     }
 }
 
+/// Everything the editor needs from a terminal: somewhere to draw, and events to react to.
+pub trait Backend {
+    /// The number of columns and rows.
+    fn size(&self) -> io::Result<(u16, u16)>;
+    fn enable_raw_mode(&mut self) -> io::Result<()>;
+    fn disable_raw_mode(&mut self) -> io::Result<()>;
+    fn enter_alternate_screen(&mut self) -> io::Result<()>;
+    /// Goes back to the screen the shell was on, right away.
+    fn leave_alternate_screen(&mut self) -> io::Result<()>;
+
+    fn clear(&mut self, clear_type: ClearType) -> io::Result<()>;
+    fn move_to(&mut self, x: u16, y: u16) -> io::Result<()>;
+    /// Prints text at the cursor, and moves the cursor past it.
+    fn print(&mut self, text: &str) -> io::Result<()>;
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()>;
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()>;
+    fn set_attribute(&mut self, attribute: Attribute) -> io::Result<()>;
+    fn set_cursor_shape(&mut self, shape: cursor::CursorShape) -> io::Result<()>;
+    /// Moves the rows from `top` up to `bottom` up by `lines`, or down if it's negative.
+    /// The rows that are left over are blank.
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> io::Result<()>;
+    fn hide_cursor(&mut self) -> io::Result<()>;
+    fn show_cursor(&mut self) -> io::Result<()>;
+    /// Asks the terminal to put text on the system clipboard, with OSC 52.
+    fn copy(&mut self, selection: Selection, text: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Waits up to `timeout` for an event, returns whether one came.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool>;
+    fn read(&mut self) -> io::Result<Event>;
+}
+
+/// The terminal hecto runs in.
+pub struct CrosstermBackend {
+    stdout: Stdout,
+}
+
+impl CrosstermBackend {
+    pub fn new(stdout: Stdout) -> Self {
+        Self { stdout }
+    }
+}
+
+impl Backend for CrosstermBackend {
+    fn size(&self) -> io::Result<(u16, u16)> {
+        term::size().map_err(io::Error::other)
+    }
+
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        term::enable_raw_mode().map_err(io::Error::other)
+    }
+
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        term::disable_raw_mode().map_err(io::Error::other)
+    }
+
+    fn enter_alternate_screen(&mut self) -> io::Result<()> {
+        queue!(self.stdout, EnterAlternateScreen).map_err(io::Error::other)
+    }
+
+    fn leave_alternate_screen(&mut self) -> io::Result<()> {
+        queue!(self.stdout, LeaveAlternateScreen).map_err(io::Error::other)?;
+        self.stdout.flush()
+    }
+
+    // Everything is queued up until `flush`, so that a whole frame goes out at once.
+    fn clear(&mut self, clear_type: ClearType) -> io::Result<()> {
+        queue!(self.stdout, Clear(clear_type)).map_err(io::Error::other)
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) -> io::Result<()> {
+        queue!(self.stdout, cursor::MoveTo(x, y)).map_err(io::Error::other)
+    }
+
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        queue!(self.stdout, Print(text)).map_err(io::Error::other)
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        queue!(self.stdout, SetForegroundColor(color)).map_err(io::Error::other)
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        queue!(self.stdout, SetBackgroundColor(color)).map_err(io::Error::other)
+    }
+
+    fn set_attribute(&mut self, attribute: Attribute) -> io::Result<()> {
+        queue!(self.stdout, SetAttribute(attribute)).map_err(io::Error::other)
+    }
+
+    fn set_cursor_shape(&mut self, shape: cursor::CursorShape) -> io::Result<()> {
+        queue!(self.stdout, cursor::SetCursorShape(shape)).map_err(io::Error::other)
+    }
+
+    // NOTE: The scroll region has to be set with a raw escape sequence, crossterm has no command
+    // for it. Setting it also moves the cursor, which is moved again before anything is drawn.
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> io::Result<()> {
+        write!(self.stdout, "\x1b[{};{}r", top + 1, bottom)?;
+        if lines > 0 {
+            queue!(self.stdout, term::ScrollUp(lines as u16)).map_err(io::Error::other)?;
+        } else {
+            queue!(self.stdout, term::ScrollDown(-lines as u16)).map_err(io::Error::other)?;
+        }
+        write!(self.stdout, "\x1b[r")
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        queue!(self.stdout, cursor::Hide).map_err(io::Error::other)
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        queue!(self.stdout, cursor::Show).map_err(io::Error::other)
+    }
+
+    // NOTE: Like the scroll region, OSC 52 has no crossterm command. Terminals that don't
+    // know it ignore it.
+    fn copy(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        let target = match selection {
+            Selection::Clipboard => 'c',
+            Selection::Primary => 'p',
+        };
+        write!(
+            self.stdout,
+            "\x1b]52;{};{}\x07",
+            target,
+            clipboard::base64(text)
+        )?;
+        self.stdout.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        event::poll(timeout).map_err(io::Error::other)
+    }
+
+    fn read(&mut self) -> io::Result<Event> {
+        event::read().map_err(io::Error::other)
+    }
+}
+
+/// The terminal, as the editor sees it. Drawing goes into `frame`, and `flush` sends whatever
+/// changed since the last frame to the backend.
 pub struct Terminal {
     size: Size,
-    pub stdout: Stdout,
+    backend: Box<dyn Backend>,
+    frame: Frame,
+    // What the backend shows right now.
+    previous: Frame,
+    // Whether the backend shows something `previous` doesn't know about, like before the first
+    // frame or after a resize.
+    stale: bool,
+    cursor_shape: Option<cursor::CursorShape>,
 }
 
 impl Terminal {
-    pub fn new(stdout: Stdout) -> Result<Self, std::io::Error> {
-        let size = size().unwrap();
+    pub fn new(mut backend: Box<dyn Backend>) -> Result<Self, std::io::Error> {
+        let size = backend.size()?;
 
-        let term = Self {
+        backend.enable_raw_mode()?;
+        Ok(Self {
             size: Size {
                 width: size.0.saturating_sub(1),
                 height: size.1.saturating_sub(3),
             },
-            stdout,
+            backend,
+            frame: Frame::new(size.0, size.1),
+            previous: Frame::new(size.0, size.1),
+            stale: true,
+            cursor_shape: None,
+        })
+    }
+
+    /// Takes on the new size after the terminal was resized. The next frame is drawn from scratch.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.size = Size {
+            width: width.saturating_sub(1),
+            height: height.saturating_sub(3),
         };
+        self.frame = Frame::new(width, height);
+        self.previous = Frame::new(width, height);
+        self.stale = true;
+    }
 
-        enable_raw_mode().unwrap();
-        Ok(term)
+    pub fn enable_raw_mode(&mut self) {
+        self.backend.enable_raw_mode().unwrap();
     }
 
-    pub fn update_dimensions(&mut self, prev_dim: Size) {
-        let cur_dim = size().unwrap();
-        if prev_dim != cur_dim {
-            self.clear_screen();
-            self.size = Size {
-                width: cur_dim.0.saturating_sub(1),
-                height: cur_dim.1.saturating_sub(3),
-            };
-        }
+    pub fn disable_raw_mode(&mut self) {
+        self.backend.disable_raw_mode().unwrap();
+    }
+
+    pub fn enter_alternate_screen(&mut self) {
+        self.backend.enter_alternate_screen().unwrap();
+    }
+
+    pub fn leave_alternate_screen(&mut self) {
+        // Quitting goes on either way, there's nothing left to draw on.
+        let _ = self.backend.leave_alternate_screen();
     }
 
     pub fn clear_screen(&mut self) {
-        write!(self.stdout, "{}", Clear(ClearType::All)).unwrap();
-        self.stdout.flush().unwrap();
+        self.frame.clear();
     }
 
     pub fn clear_current_line(&mut self) {
-        write!(self.stdout, "{}", Clear(ClearType::CurrentLine)).unwrap();
-        self.stdout.flush().unwrap();
+        self.frame.clear_row(self.frame.cursor.1);
     }
 
     pub fn set_cursor_position(&mut self, pos: &Position) {
@@ -85,48 +268,205 @@ impl Terminal {
         let x = *x as u16;
         let y = *y as u16;
 
-        write!(self.stdout, "{}", cursor::MoveTo(x, y)).unwrap();
-        self.stdout.flush().unwrap();
+        self.frame.cursor = (x, y);
     }
 
-    pub fn size(&self) -> &Size {
-        &self.size
+    pub fn print(&mut self, text: &str) {
+        self.frame.print(text);
     }
 
-    pub fn dim(&self) -> (u16, u16) {
-        (self.size.width, self.size.height)
+    /// The number of columns and rows, all of them.
+    pub fn screen_size(&self) -> (u16, u16) {
+        (self.frame.width, self.frame.height)
+    }
+
+    /// Draws tui widgets, like the command window, over what's on the screen. Only the cells
+    /// in `areas` are taken from the buffer.
+    pub fn overlay(&mut self, buffer: &Buffer, areas: &[Rect]) {
+        for area in areas {
+            for y in area.top()..area.bottom() {
+                for x in area.left()..area.right() {
+                    let cell = buffer.get(x, y);
+                    let cell = Cell {
+                        symbol: cell.symbol.clone(),
+                        fg: color(cell.fg),
+                        bg: color(cell.bg),
+                        modifier: cell.modifier,
+                    };
+                    self.frame.set(x, y, cell);
+                }
+            }
+        }
+    }
+
+    /// Tells the terminal that the rows from `top` up to `bottom` show the same lines as before,
+    /// moved up by `lines` (or down if it's negative). The terminal scrolls them into place,
+    /// so only the lines that weren't on the screen yet have to be drawn.
+    pub fn scroll(&mut self, top: u16, bottom: u16, lines: i32) {
+        let rows = i32::from(bottom.saturating_sub(top));
+        if self.stale || lines == 0 || lines.abs() >= rows {
+            return;
+        }
+
+        self.backend.scroll(top, bottom, lines).unwrap();
+        self.previous.scroll(top, bottom, lines);
+    }
+
+    pub fn size(&self) -> &Size {
+        &self.size
     }
 
     pub fn set_bg_color(&mut self, color: Color) {
-        execute!(self.stdout, SetBackgroundColor(color)).unwrap();
+        self.frame.set_bg(color);
     }
 
     pub fn set_fg_color(&mut self, color: Color) {
-        execute!(self.stdout, SetForegroundColor(color)).unwrap();
+        self.frame.set_fg(color);
     }
 
     pub fn reset_fg_color(&mut self) {
-        execute!(self.stdout, SetForegroundColor(Color::Reset)).unwrap();
+        self.frame.set_fg(Color::Reset);
     }
 
     pub fn reset_bg_color(&mut self) {
-        execute!(self.stdout, SetBackgroundColor(Color::Reset)).unwrap();
+        self.frame.set_bg(Color::Reset);
     }
 
     pub fn change_cursor_shape(&mut self, cursor_shape: cursor::CursorShape) {
-        let cursor_shape = cursor::SetCursorShape(cursor_shape);
-        execute!(self.stdout, cursor_shape).unwrap();
+        if self.cursor_shape != Some(cursor_shape) {
+            self.backend.set_cursor_shape(cursor_shape).unwrap();
+            self.cursor_shape = Some(cursor_shape);
+        }
     }
 
     pub fn hide_cursor(&mut self) {
-        execute!(self.stdout, cursor::Hide).unwrap();
+        self.frame.cursor_visible = false;
     }
 
     pub fn show_cursor(&mut self) {
-        execute!(self.stdout, cursor::Show).unwrap();
+        self.frame.cursor_visible = true;
+    }
+
+    pub fn copy(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        self.backend.copy(selection, text)
     }
 
-    pub fn flush(&mut self) {
-        self.stdout.flush().unwrap();
+    /// Sends the frame to the backend, only the cells that changed since the last one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let result = self.draw_frame();
+        if result.is_err() {
+            // There's no telling what made it to the screen, the next frame is drawn in full.
+            self.previous = Frame::new(self.frame.width, self.frame.height);
+            self.stale = true;
+        }
+
+        result
+    }
+
+    fn draw_frame(&mut self) -> io::Result<()> {
+        let backend = &mut self.backend;
+        if self.stale {
+            backend.clear(ClearType::All)?;
+            self.stale = false;
+        }
+
+        let updates = self.frame.diff(&self.previous);
+        // The cursor would jump around while the cells are drawn.
+        if self.previous.cursor_visible && !updates.is_empty() {
+            backend.hide_cursor()?;
+            self.previous.cursor_visible = false;
+        }
+
+        let mut next = None;
+        let mut style = (Color::Reset, Color::Reset, Modifier::empty());
+        for (x, y, cell) in updates {
+            if next != Some((x, y)) {
+                backend.move_to(x, y)?;
+            }
+
+            if cell.modifier != style.2 {
+                backend.set_attribute(Attribute::Reset)?;
+                for (flag, attribute) in ATTRIBUTES.iter() {
+                    if cell.modifier.contains(*flag) {
+                        backend.set_attribute(*attribute)?;
+                    }
+                }
+                // Resetting the attributes resets the colours too.
+                style = (Color::Reset, Color::Reset, cell.modifier);
+                backend.set_fg_color(Color::Reset)?;
+                backend.set_bg_color(Color::Reset)?;
+            }
+            if cell.fg != style.0 {
+                backend.set_fg_color(cell.fg)?;
+                style.0 = cell.fg;
+            }
+            if cell.bg != style.1 {
+                backend.set_bg_color(cell.bg)?;
+                style.1 = cell.bg;
+            }
+
+            backend.print(&cell.symbol)?;
+            next = Some((x.saturating_add(cell.symbol.width() as u16), y));
+        }
+
+        if style != (Color::Reset, Color::Reset, Modifier::empty()) {
+            backend.set_attribute(Attribute::Reset)?;
+            backend.set_fg_color(Color::Reset)?;
+            backend.set_bg_color(Color::Reset)?;
+        }
+
+        let (x, y) = self.frame.cursor;
+        if self.frame.cursor_visible {
+            backend.move_to(x, y)?;
+            if !self.previous.cursor_visible {
+                backend.show_cursor()?;
+            }
+        } else if self.previous.cursor_visible {
+            backend.hide_cursor()?;
+        }
+
+        self.previous.clone_from(&self.frame);
+        backend.flush()
+    }
+
+    pub fn poll(&mut self, timeout: Duration) -> bool {
+        self.backend.poll(timeout).unwrap_or(false)
+    }
+
+    pub fn read(&mut self) -> io::Result<Event> {
+        self.backend.read()
+    }
+}
+
+fn color(color: TuiColor) -> Color {
+    match color {
+        TuiColor::Reset => Color::Reset,
+        TuiColor::Black => Color::Black,
+        TuiColor::Red => Color::DarkRed,
+        TuiColor::Green => Color::DarkGreen,
+        TuiColor::Yellow => Color::DarkYellow,
+        TuiColor::Blue => Color::DarkBlue,
+        TuiColor::Magenta => Color::DarkMagenta,
+        TuiColor::Cyan => Color::DarkCyan,
+        TuiColor::Gray => Color::Grey,
+        TuiColor::DarkGray => Color::DarkGrey,
+        TuiColor::LightRed => Color::Red,
+        TuiColor::LightGreen => Color::Green,
+        TuiColor::LightBlue => Color::Blue,
+        TuiColor::LightYellow => Color::Yellow,
+        TuiColor::LightMagenta => Color::Magenta,
+        TuiColor::LightCyan => Color::Cyan,
+        TuiColor::White => Color::White,
+        TuiColor::Indexed(i) => Color::AnsiValue(i),
+        TuiColor::Rgb(r, g, b) => Color::Rgb { r, g, b },
     }
 }
+
+const ATTRIBUTES: [(Modifier, Attribute); 6] = [
+    (Modifier::BOLD, Attribute::Bold),
+    (Modifier::DIM, Attribute::Dim),
+    (Modifier::ITALIC, Attribute::Italic),
+    (Modifier::UNDERLINED, Attribute::Underlined),
+    (Modifier::REVERSED, Attribute::Reverse),
+    (Modifier::CROSSED_OUT, Attribute::CrossedOut),
+];
@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+/// Things the editor does at a certain time, rather than because of a key.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Timer {
+    // The start of a command like `gg` is dropped when the rest doesn't come in time.
+    PendingKeys,
+    // The status message runs out, the message bar has to be drawn again.
+    Status,
+    // The user stopped typing long enough for unsaved changes to go to the swap file.
+    Swap,
+    // Time to check the files for changes made by other programs.
+    Watch,
+    // The user stopped typing long enough for `autosave`.
+    AutoSave,
+}
+
+/// The timers that are waiting to go off. A timer is never waiting more than once.
+#[derive(Default)]
+pub struct Timers {
+    deadlines: Vec<(Instant, Timer)>,
+}
+
+impl Timers {
+    /// Sets a timer to go off at `at`, instead of whenever it was going to go off before.
+    pub fn schedule(&mut self, timer: Timer, at: Instant) {
+        self.cancel(timer);
+        self.deadlines.push((at, timer));
+    }
+
+    pub fn cancel(&mut self, timer: Timer) {
+        self.deadlines.retain(|(_, waiting)| *waiting != timer);
+    }
+
+    /// When the first timer goes off.
+    pub fn next(&self) -> Option<Instant> {
+        self.deadlines.iter().map(|(at, _)| *at).min()
+    }
+
+    /// Takes out the timers that should have gone off by `now`, in the order they went off.
+    pub fn due(&mut self, now: Instant) -> Vec<Timer> {
+        let mut due: Vec<(Instant, Timer)> = Vec::new();
+        self.deadlines.retain(|deadline| {
+            let waiting = deadline.0 > now;
+            if !waiting {
+                due.push(*deadline);
+            }
+            waiting
+        });
+
+        due.sort_by_key(|(at, _)| *at);
+        due.into_iter().map(|(_, timer)| timer).collect()
+    }
+}
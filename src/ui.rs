@@ -1,18 +1,20 @@
 use tui::{
-    backend::Backend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    buffer::Buffer,
+    layout::{Alignment, Rect},
     style::{Color as ColorT, Modifier, Style},
-    terminal::Frame,
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
-    Terminal,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget, Wrap},
 };
 
+use unicode_width::UnicodeWidthStr;
+
 use super::modes::Mode;
 use crossterm::event::{Event, KeyCode as Key, KeyModifiers as Mod};
 
 pub enum Command {
     Instruction(Mode, (Key, Mod)),
+    // A command the editor runs itself, along with its arguments.
+    Ex(String),
     None,
 }
 
@@ -30,10 +32,36 @@ pub struct StatefulList {
 
 impl Default for StatefulList {
     fn default() -> Self {
-        let mut items = vec![];
-        items.push("save".to_string());
-        items.push("quit".to_string());
-        items.push("save & quit".to_string());
+        let items = vec![
+            "save".to_string(),
+            "quit".to_string(),
+            "save & quit".to_string(),
+            "undo".to_string(),
+            "undolist".to_string(),
+            "set".to_string(),
+            "write".to_string(),
+            "saveas".to_string(),
+            "wq".to_string(),
+            "qall".to_string(),
+            "wqall".to_string(),
+            "edit".to_string(),
+            "bnext".to_string(),
+            "bprevious".to_string(),
+            "buffer".to_string(),
+            "buffers".to_string(),
+            "registers".to_string(),
+            "bdelete".to_string(),
+            "split".to_string(),
+            "vsplit".to_string(),
+            "new".to_string(),
+            "vnew".to_string(),
+            "close".to_string(),
+            "only".to_string(),
+            "tabnew".to_string(),
+            "tabclose".to_string(),
+            "tabnext".to_string(),
+            "tabprevious".to_string(),
+        ];
         Self {
             state: ListState::default(),
             items,
@@ -100,112 +128,59 @@ impl Default for App {
     }
 }
 
-fn state_returns<'a>(
-    commands: Vec<ListItem<'a>>,
-    app: &'a mut App,
-) -> (List<'a>, Paragraph<'a>, Option<Vec<Spans<'a>>>) {
-    let command_block = Block::default()
-        .borders(Borders::ALL)
-        .title(Span::styled(
-            " commands ".to_string(),
-            Style::default().add_modifier(Modifier::BOLD),
-        ))
-        .title_alignment(Alignment::Center);
-
-    let commands = List::new(commands)
-        .style(Style::default())
-        .block(command_block.clone().title_alignment(Alignment::Center))
-        .highlight_style(
-            Style::default()
-                .bg(ColorT::Rgb(252, 170, 7))
-                .add_modifier(Modifier::BOLD),
-        )
-        .highlight_symbol(">> ");
-
-    let input_block = Block::default()
-        .borders(Borders::ALL)
-        .title("Type the command")
-        .title_alignment(Alignment::Center);
-
-    let input = Paragraph::new(app.input.as_ref())
-        .style(Style::default())
-        .block(input_block.clone());
-
-    let cmd = format!("'{}'", app.current_command);
-    let text = vec![
-        Spans::from(vec![Span::styled(
-            "Invalid Command",
-            Style::default()
-                .add_modifier(Modifier::BOLD)
-                .fg(ColorT::Red),
-        )]),
-        Spans::from(vec![
-            Span::from("The command "),
-            Span::styled(
-                cmd.clone(),
-                Style::default()
-                    .add_modifier(Modifier::BOLD)
-                    .fg(ColorT::Blue),
-            ),
-            Span::from(" is not valid."),
-        ]),
-    ];
-
-    if app.state == State::Success {
-        let commands = commands
-            .style(Style::default())
-            .block(command_block.border_style(Style::default().fg(ColorT::Green)));
-
-        let input = input
-            .style(Style::default().fg(ColorT::Green))
-            .block(input_block.title("Success"));
-
-        (commands, input, None)
-    } else if app.state == State::Fine {
-        let commands = commands
-            .style(Style::default())
-            .block(command_block.border_style(Style::default().fg(ColorT::White)));
-
-        let input = Paragraph::new(app.input.as_ref())
-            .style(Style::default().fg(ColorT::White))
-            .block(input_block.title("Type the command"));
-
-        (commands, input, None)
-    } else {
-        let commands = commands
-            .style(Style::default())
-            .block(command_block.border_style(Style::default().fg(ColorT::Red)));
-
-        let input = Paragraph::new(app.input.as_ref())
-            .style(Style::default().fg(ColorT::Red))
-            .block(input_block.title("Invalid command"));
+// The command window is never narrower than this, unless the screen is.
+const MIN_WIDTH: u16 = 20;
+// Nor is it wider than this, so that it doesn't stretch across big screens.
+const MAX_WIDTH: u16 = 60;
+const INPUT_HEIGHT: u16 = 3;
+const ERROR_HEIGHT: u16 = 5;
+
+/// The command window, drawn on a buffer of its own so that it can go over the editor.
+pub struct Popup {
+    pub buffer: Buffer,
+    // The parts of the buffer the window covers, the rest of the screen is left alone.
+    pub areas: Vec<Rect>,
+    pub cursor: (u16, u16),
+}
 
-        (commands, input, Some(text))
+/// Where the list of commands, the input box and the error message go, in the middle of the
+/// screen. They shrink along with the screen, the list first.
+fn layout(area: Rect, items: usize, error: bool) -> [Rect; 3] {
+    let width = (area.width / 2).clamp(MIN_WIDTH.min(area.width), MAX_WIDTH.min(area.width));
+    let input_height = INPUT_HEIGHT.min(area.height);
+    let error_height = if error { ERROR_HEIGHT } else { 0 };
+    let error_height = error_height.min(area.height - input_height);
+
+    // The list takes up to half of the screen, and scrolls if that isn't enough.
+    let room = area.height - input_height - error_height;
+    let mut list_height = (items as u16)
+        .saturating_add(2)
+        .min(room)
+        .min(area.height / 2);
+    // A list without a single line inside its borders isn't worth drawing.
+    if list_height < 3 {
+        list_height = 0;
     }
+
+    let x = area.x + (area.width - width) / 2;
+    let y = area.y + (room - list_height) / 2;
+    let list = Rect::new(x, y, width, list_height);
+    let input = Rect::new(x, list.bottom(), width, input_height);
+    let error = Rect::new(x, input.bottom(), width, error_height);
+    [list, input, error]
 }
 
-pub fn command_window<B: Backend>(f: &mut Frame<B>, app: &mut App) {
-    let size = f.size();
-
-    // FIXME: The reason the window isn't resized properly is because the horizontal margin is set.
-    // It needs to be dynamic instead of static.
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .horizontal_margin(50)
-        .constraints(
-            [
-                Constraint::Percentage(25),
-                Constraint::Percentage(50),
-                Constraint::Percentage(7),
-                Constraint::Percentage(100),
-            ]
-            .as_ref(),
-        )
-        .split(size);
+pub fn command_window(width: u16, height: u16, app: &mut App) -> Popup {
+    let size = Rect::new(0, 0, width, height);
+    let mut buffer = Buffer::empty(size);
+    let buf = &mut buffer;
+
+    let error = app.state == State::InvalidCommand;
+    let [list_area, input_area, error_area] = layout(size, app.commands.items.len(), error);
 
     // Clear the area from text to make space for list of commands.
     let block = Block::default().style(Style::default().fg(ColorT::White));
-    f.render_widget(block, chunks[1]);
+    block.render(list_area, buf);
 
     let items: Vec<ListItem> = app
         .commands
@@ -298,66 +273,106 @@ pub fn command_window<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         (commands, input, Some(text))
     };
 
-    f.render_stateful_widget(windows.0, chunks[1], &mut app.commands.state);
-    // f.render_stateful_widget(items, windows.0, chunks[1]);
+    StatefulWidget::render(windows.0, list_area, buf, &mut app.commands.state);
 
     // Clear the area from text to make space for input box.
     let block = Block::default().style(Style::default().fg(ColorT::White));
-    f.render_widget(block, chunks[2]);
-    f.render_widget(windows.1, chunks[2]);
-
-    if app.state == State::InvalidCommand {
-        let dyn_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .horizontal_margin(60)
-            .vertical_margin(19)
-            .constraints(
-                [
-                    Constraint::Percentage(45),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(25),
-                ]
-                .as_ref(),
-            )
-            .split(size);
+    block.render(input_area, buf);
+    windows.1.render(input_area, buf);
 
+    if let Some(text) = windows.2 {
         let block = Block::default()
             .style(Style::default().fg(ColorT::White))
             .borders(Borders::ALL);
 
-        let chunk = 1;
-        f.render_widget(block, dyn_chunks[chunk]);
+        let msg = Paragraph::new(text)
+            .block(block)
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true });
+        msg.render(error_area, buf);
+    }
+
+    // The cursor stays inside the input box, even when the input doesn't fit.
+    let inner = input_area.right().saturating_sub(2);
+    let column = input_area.x + 1 + app.input.width() as u16;
+    let cursor = (column.min(inner), input_area.y + 1);
 
-        let msg = Paragraph::new(windows.2.unwrap()).alignment(Alignment::Center);
-        f.render_widget(msg, dyn_chunks[chunk]);
+    Popup {
+        buffer,
+        areas: vec![list_area, input_area, error_area],
+        cursor,
     }
+}
 
-    f.set_cursor(chunks[2].x + app.input.len() as u16 + 1, chunks[2].y + 1);
+// Short names of commands, like `w` for `write`.
+const ABBREVIATIONS: [(&str, &str); 19] = [
+    ("w", "write"),
+    ("e", "edit"),
+    ("bn", "bnext"),
+    ("bp", "bprevious"),
+    ("b", "buffer"),
+    ("ls", "buffers"),
+    ("reg", "registers"),
+    ("di", "registers"),
+    ("bd", "bdelete"),
+    ("sp", "split"),
+    ("vs", "vsplit"),
+    ("clo", "close"),
+    ("on", "only"),
+    ("tabc", "tabclose"),
+    ("tabn", "tabnext"),
+    ("tabp", "tabprevious"),
+    ("q", "quit"),
+    ("qa", "qall"),
+    ("wqa", "wqall"),
+];
+
+/// Lowercases the name of a command and expands abbreviations, arguments like paths are left alone.
+fn normalize(input: &str) -> String {
+    let input = input.trim();
+    let (name, arguments) = input.split_once(' ').unwrap_or((input, ""));
+    let name = name.to_lowercase();
+
+    // A `!` at the end of a name forces the command, e.g. `w! file` overwrites an existing file.
+    let (base, bang) = match name.strip_suffix('!') {
+        Some(base) => (base, "!"),
+        None => (name.as_str(), ""),
+    };
+    let base = ABBREVIATIONS
+        .iter()
+        .find(|(short, _)| *short == base)
+        .map_or(base, |(_, full)| full);
+
+    format!("{}{} {}", base, bang, arguments.trim())
+        .trim_end()
+        .to_string()
 }
 
 fn process_command(command: String, app: &mut App) -> Command {
-    let command = app.input.to_lowercase();
     let mut iter = app.commands.items.iter();
     app.current_command = app.input.clone();
 
+    // Commands like `undo 3` take arguments after their name.
+    let name = command.split_whitespace().next().unwrap_or_default();
+    let name = name.strip_suffix('!').unwrap_or(name);
+
     app.input.clear();
-    if let Some(_) = iter.find(|&e| e == &command) {
+    if command == "save" {
         app.state = State::Fine;
         Command::Instruction(Mode::Normal, (Key::Char('w'), Mod::ALT))
+    } else if command == "save & quit" {
+        app.state = State::Fine;
+        Command::Ex("wq".to_string())
+    } else if iter.any(|e| e == name) {
+        app.state = State::Fine;
+        Command::Ex(command)
     } else {
         app.state = State::InvalidCommand;
         Command::None
     }
 }
 
-pub fn run_command_mode<B: Backend>(
-    terminal: &mut Terminal<B>,
-    app: &mut App,
-    key: Event,
-) -> Command {
-    terminal.hide_cursor().unwrap();
-    terminal.draw(|f| command_window(f, app)).unwrap();
-
+pub fn run_command_mode(app: &mut App, key: Event) -> Command {
     if let Event::Key(event) = key {
         match event.code {
             Key::Char(c) => {
@@ -385,17 +400,16 @@ pub fn run_command_mode<B: Backend>(
             }
             Key::Esc => {
                 app.input.clear();
-                terminal.show_cursor().unwrap();
                 Command::None
             }
             Key::Enter => {
                 if app.selected {
-                    let command = process_command(app.input.to_lowercase(), app);
+                    let command = process_command(normalize(&app.input), app);
                     app.commands.unselect();
                     app.selected = false;
                     command
                 } else {
-                    process_command(app.input.to_lowercase(), app)
+                    process_command(normalize(&app.input), app)
                 }
                 // let command = app.input.to_lowercase();
                 // let mut iter = app.commands.items.iter();
@@ -0,0 +1,158 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::history::History;
+use super::save;
+
+const MAGIC: &str = "hecto-undo 1";
+
+/// The directory undo and swap files are kept in, `$XDG_STATE_HOME/hecto` or `~/.local/state/hecto`.
+#[cfg(not(test))]
+pub fn state_directory() -> Option<PathBuf> {
+    let state = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(state.join("hecto"))
+}
+
+/// Tests keep their files out of the user's state directory, without changing the environment
+/// that other tests are reading at the same time.
+#[cfg(test)]
+pub fn state_directory() -> Option<PathBuf> {
+    Some(env::temp_dir().join("hecto-test/state/hecto"))
+}
+
+/// Every file gets its own file in `directory`, named after its absolute path with `/` replaced by `%`.
+pub fn path_in(directory: &str, filename: &str) -> Option<PathBuf> {
+    let path = Path::new(filename);
+    let absolute = match fs::canonicalize(path) {
+        Ok(absolute) => absolute,
+        // A file that doesn't exist yet, its directory has to exist though.
+        Err(_) => {
+            let parent = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty());
+            fs::canonicalize(parent.unwrap_or_else(|| Path::new(".")))
+                .ok()?
+                .join(path.file_name()?)
+        }
+    };
+    let name = absolute.to_string_lossy().replace('/', "%");
+
+    Some(state_directory()?.join(directory).join(name))
+}
+
+fn path(filename: &str) -> Option<PathBuf> {
+    path_in("undo", filename)
+}
+
+/// FNV-1a, it only has to notice that the file changed outside of hecto.
+pub fn hash(contents: &str) -> u64 {
+    contents.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn write(filename: &str, contents_hash: u64, history: &History) -> Result<(), std::io::Error> {
+    let path = path(filename)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No undo directory"))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // A crash halfway through doesn't leave a cut off undo file behind.
+    save::write_atomically(&path, format(contents_hash, history).as_bytes(), false)
+}
+
+fn format(contents_hash: u64, history: &History) -> String {
+    format!("{}\n{:016x}\n{}", MAGIC, contents_hash, history.serialize())
+}
+
+/// Reads the undo tree of a file, as long as the file is still the same as when it was written.
+pub fn read(filename: &str, contents_hash: u64) -> Option<History> {
+    parse(&fs::read_to_string(path(filename)?).ok()?, contents_hash)
+}
+
+fn parse(contents: &str, contents_hash: u64) -> Option<History> {
+    let mut parts = contents.splitn(3, '\n');
+
+    if parts.next()? != MAGIC {
+        return None;
+    }
+
+    if u64::from_str_radix(parts.next()?, 16).ok()? != contents_hash {
+        return None;
+    }
+
+    History::deserialize(parts.next()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Position;
+    use crate::history::{Change, Step};
+
+    // Two branches off the first revision, with text that needs escaping.
+    fn history() -> History {
+        let mut history = History::default();
+        for (at, text) in [(0, "one\n"), (4, "two\\\r\n"), (4, "three")] {
+            let change = Change::Insert {
+                at,
+                text: text.to_string(),
+            };
+            history.record(change, &Position::new(at, 0));
+            history.commit(&Position::new(0, 1));
+            if at == 4 {
+                history.apply(&Step::Undo(history.current));
+            }
+        }
+        history
+    }
+
+    #[test]
+    fn undo_trees_survive_a_round_trip() {
+        let history = history();
+        let contents = format(hash("one\n"), &history);
+        let read = parse(&contents, hash("one\n")).unwrap();
+
+        assert_eq!(read.serialize(), history.serialize());
+        assert_eq!(read.current, 1);
+        assert_eq!(read.revisions[1].children, [2, 3]);
+        assert_eq!(read.revisions[1].last_child, Some(3));
+        assert_eq!(
+            read.revisions[2].changes[0].serialize(),
+            "i 4 two\\\\\\r\\n"
+        );
+    }
+
+    #[test]
+    fn files_that_changed_or_are_not_undo_files_are_rejected() {
+        let contents = format(hash("one\n"), &history());
+        assert!(parse(&contents, hash("one\n")).is_some());
+        assert!(parse(&contents, hash("one\ntwo\n")).is_none());
+        assert!(parse(&contents.replacen(MAGIC, "hecto-undo 2", 1), hash("one\n")).is_none());
+        assert!(parse("hello world\n", hash("one\n")).is_none());
+
+        let broken = format!("{}\n{:016x}\ncurrent 5\nrevision 0 - 0 0 0 0 0\n", MAGIC, 1);
+        assert!(parse(&broken, 1).is_none());
+
+        // Redo from revision 0 would go to 2, which is a child of 1.
+        let revisions = "revision 0 2 0 0 0 0 0\nrevision 0 2 0 0 0 0 0\nrevision 1 - 0 0 0 0 0\n";
+        let broken = format!("{}\n{:016x}\ncurrent 0\n{}", MAGIC, 1, revisions);
+        assert!(parse(&broken, 1).is_none());
+        let fixed = broken.replacen("revision 0 2", "revision 0 1", 1);
+        assert!(parse(&fixed, 1).is_some());
+    }
+
+    #[test]
+    fn the_hash_notices_any_change() {
+        assert_ne!(hash("one\n"), hash("one\r\n"));
+        assert_ne!(hash("ab"), hash("ba"));
+        assert_eq!(hash(""), 0xcbf2_9ce4_8422_2325);
+    }
+}
@@ -0,0 +1,37 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Amount of terminal columns a grapheme takes up.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let first = match grapheme.chars().next() {
+        Some(c) => c,
+        None => return 0,
+    };
+
+    // Flags are made out of two regional indicators, and `U+FE0F` asks for the emoji version
+    // of a character. Terminals draw both of them two columns wide.
+    if ('\u{1f1e6}'..='\u{1f1ff}').contains(&first) || grapheme.contains('\u{fe0f}') {
+        return 2;
+    }
+
+    // Combining accents and the joiners inside emoji sequences have no width of their own,
+    // so the widest character decides the width of the whole cluster.
+    grapheme
+        .chars()
+        .map(|c| c.width().unwrap_or(0))
+        .max()
+        .unwrap_or(0)
+}
+
+/// The start of `text` that fits in `width` columns, never cutting a grapheme in half.
+pub fn truncate(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..index];
+        }
+    }
+
+    text
+}
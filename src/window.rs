@@ -0,0 +1,318 @@
+use super::editor::Position;
+
+/// A view into a buffer. The focused window lives in the editor itself, this holds the others.
+pub struct Window {
+    pub id: usize,
+    pub buffer_id: usize,
+    pub cursor: Position,
+    pub offset: Position,
+}
+
+/// A tab page that isn't shown, with its own windows. The window that had the cursor is in
+/// `windows` too.
+pub struct Tab {
+    pub layout: Layout,
+    pub windows: Vec<Window>,
+    pub window_id: usize,
+    pub previous_window: Option<usize>,
+}
+
+impl Tab {
+    /// A tab page with one window.
+    pub fn new(window_id: usize, buffer_id: usize) -> Self {
+        Self {
+            layout: Layout::Window(window_id),
+            windows: vec![Window {
+                id: window_id,
+                buffer_id,
+                cursor: Position::default(),
+                offset: Position::default(),
+            }],
+            window_id,
+            previous_window: None,
+        }
+    }
+
+    /// Closes the windows that show a buffer, returns whether any window is left.
+    pub fn close_windows_showing(&mut self, buffer_id: usize) -> bool {
+        for window in self.windows.iter().filter(|w| w.buffer_id == buffer_id) {
+            self.layout.remove(window.id);
+        }
+        self.windows.retain(|w| w.buffer_id != buffer_id);
+
+        let windows = &self.windows;
+        let exists = |id: usize| windows.iter().any(|w| w.id == id);
+        if !self.previous_window.is_some_and(exists) {
+            self.previous_window = None;
+        }
+        if !exists(self.window_id) {
+            self.window_id = self.windows.first().map_or(0, |w| w.id);
+        }
+
+        !self.windows.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    // Windows stacked on top of each other, like `:split`.
+    Horizontal,
+    // Windows side by side with a separator between them, like `:vsplit`.
+    Vertical,
+}
+
+/// Where a window is on the screen. The last row is its status line.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// How the windows divide the screen. Every child of a split has a size, and the sizes are
+/// scaled to whatever space the split gets, so the layout follows the terminal when it's resized.
+pub enum Layout {
+    Window(usize),
+    Split(Split, Vec<(Layout, u16)>),
+}
+
+/// Divides `total` between the weights, every cell goes to someone.
+fn sizes(total: u16, weights: &[u16]) -> Vec<u16> {
+    let sum: u32 = weights.iter().map(|&weight| u32::from(weight.max(1))).sum();
+    let mut sizes = Vec::with_capacity(weights.len());
+    let mut done = 0;
+    let mut used = 0;
+
+    for weight in weights {
+        done += u32::from((*weight).max(1));
+        let end = (u32::from(total) * done / sum) as u16;
+        sizes.push(end - used);
+        used = end;
+    }
+
+    sizes
+}
+
+impl Layout {
+    /// The windows in the order `Ctrl-W w` goes through them, along with where they are.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut rects = Vec::new();
+        self.collect_rects(area, &mut rects);
+        rects
+    }
+
+    fn collect_rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::Window(id) => rects.push((*id, area)),
+            Layout::Split(split, children) => {
+                for (child, area) in children.iter().zip(Self::divide(*split, area, children)) {
+                    child.0.collect_rects(area, rects);
+                }
+            }
+        }
+    }
+
+    fn divide(split: Split, area: Rect, children: &[(Layout, u16)]) -> Vec<Rect> {
+        let weights: Vec<u16> = children.iter().map(|(_, weight)| *weight).collect();
+        let mut rects = Vec::with_capacity(children.len());
+
+        match split {
+            Split::Horizontal => {
+                let mut y = area.y;
+                for height in sizes(area.height, &weights) {
+                    rects.push(Rect { y, height, ..area });
+                    y += height;
+                }
+            }
+            Split::Vertical => {
+                // Every window but the last has a separator column on its right.
+                let separators = weights.len().saturating_sub(1) as u16;
+                let mut x = area.x;
+                for width in sizes(area.width.saturating_sub(separators), &weights) {
+                    rects.push(Rect { x, width, ..area });
+                    x += width + 1;
+                }
+            }
+        }
+
+        rects
+    }
+
+    /// Sets the size of every child to what it's given right now, so sizes can be changed
+    /// by whole lines and columns.
+    pub fn normalize(&mut self, area: Rect) {
+        if let Layout::Split(split, children) = self {
+            let rects = Self::divide(*split, area, children);
+            for ((child, size), rect) in children.iter_mut().zip(rects) {
+                *size = match split {
+                    Split::Horizontal => rect.height,
+                    Split::Vertical => rect.width,
+                };
+                child.normalize(rect);
+            }
+        }
+    }
+
+    /// Gives every window in the layout the same size.
+    pub fn equalize(&mut self) {
+        if let Layout::Split(split, children) = self {
+            for (child, size) in children.iter_mut() {
+                *size = child.count(*split);
+                child.equalize();
+            }
+        }
+    }
+
+    /// The number of windows side by side (or on top of each other) in this part of the layout,
+    /// which is how much space it needs for its windows to be as big as the others.
+    fn count(&self, split: Split) -> u16 {
+        match self {
+            Layout::Window(_) => 1,
+            Layout::Split(inner, children) if *inner == split => {
+                children.iter().map(|(child, _)| child.count(split)).sum()
+            }
+            Layout::Split(_, children) => children
+                .iter()
+                .map(|(child, _)| child.count(split))
+                .max()
+                .unwrap_or(1),
+        }
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        match self {
+            Layout::Window(window) => *window == id,
+            Layout::Split(_, children) => children.iter().any(|(child, _)| child.contains(id)),
+        }
+    }
+
+    /// Splits a window in two, the new window goes above or to the left of it.
+    /// Needs the sizes to be normalized.
+    pub fn split(&mut self, id: usize, new: usize, split: Split) {
+        match self {
+            Layout::Window(window) if *window == id => {
+                let old = Layout::Window(id);
+                *self = Layout::Split(split, vec![(Layout::Window(new), 1), (old, 1)]);
+            }
+            Layout::Window(_) => (),
+            Layout::Split(inner, children) => {
+                let index = children.iter().position(
+                    |(child, _)| matches!(child, Layout::Window(window) if *window == id),
+                );
+
+                match index {
+                    Some(index) if *inner == split => {
+                        let size = children[index].1;
+                        children[index].1 = size - size / 2;
+                        children.insert(index, (Layout::Window(new), size / 2));
+                    }
+                    _ => {
+                        for (child, _) in children.iter_mut() {
+                            child.split(id, new, split);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Takes a window out of the layout, the window before it (or after it) gets its space.
+    pub fn remove(&mut self, id: usize) {
+        if let Layout::Split(_, children) = self {
+            if let Some(index) = children
+                .iter()
+                .position(|(child, _)| matches!(child, Layout::Window(window) if *window == id))
+            {
+                let (_, size) = children.remove(index);
+                let neighbour = index
+                    .saturating_sub(1)
+                    .min(children.len().saturating_sub(1));
+                if let Some((_, neighbour)) = children.get_mut(neighbour) {
+                    *neighbour += size;
+                }
+            } else {
+                for (child, _) in children.iter_mut() {
+                    child.remove(id);
+                }
+            }
+
+            // A split with one window left is just that window.
+            if children.len() == 1 {
+                let (child, _) = children.remove(0);
+                *self = child;
+            }
+        }
+    }
+
+    /// Moves the windows next to a window around, the last one becomes the first one
+    /// or the other way around.
+    pub fn rotate(&mut self, id: usize, downwards: bool) {
+        if let Layout::Split(_, children) = self {
+            let is_here = children
+                .iter()
+                .any(|(child, _)| matches!(child, Layout::Window(window) if *window == id));
+
+            if is_here {
+                if downwards {
+                    children.rotate_right(1);
+                } else {
+                    children.rotate_left(1);
+                }
+            } else {
+                for (child, _) in children.iter_mut() {
+                    child.rotate(id, downwards);
+                }
+            }
+        }
+    }
+
+    /// Makes a window bigger (or smaller) in the direction of a split, taking the space from
+    /// the window after it, or before it if it's the last one. Needs the sizes to be normalized.
+    /// Returns whether a split in that direction was found.
+    pub fn resize(&mut self, id: usize, split: Split, delta: i32) -> bool {
+        let (inner, children) = match self {
+            Layout::Window(_) => return false,
+            Layout::Split(inner, children) => (*inner, children),
+        };
+
+        let index = match children.iter().position(|(child, _)| child.contains(id)) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        if children[index].0.resize(id, split, delta) {
+            return true;
+        } else if inner != split {
+            return false;
+        }
+
+        let neighbour = if index + 1 < children.len() {
+            index + 1
+        } else {
+            index.saturating_sub(1)
+        };
+        if neighbour == index {
+            return true;
+        }
+
+        // Every window keeps at least a line of text and its status line, or a column.
+        let minimum = match split {
+            Split::Horizontal => 2,
+            Split::Vertical => 1,
+        };
+        let size = i32::from(children[index].1);
+        let other = i32::from(children[neighbour].1);
+        let delta = delta.clamp(minimum - size, other - minimum);
+
+        children[index].1 = (size + delta).max(minimum) as u16;
+        children[neighbour].1 = (other - delta).max(minimum) as u16;
+        true
+    }
+}